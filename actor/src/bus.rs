@@ -0,0 +1,455 @@
+use actix::Recipient;
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use tokio::sync::Notify;
+
+/// What happens to an event published to a subscriber whose buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BufferPolicy {
+    /// The new event is dropped.
+    DropNewest,
+    /// The oldest buffered event is dropped to make room for the new one.
+    DropOldest,
+    /// The publisher waits until the subscriber has room (backpressure).
+    #[default]
+    Block,
+}
+
+/// The buffer of a subscriber, built with [`SubscribeOptions::new`] which keeps room
+/// for at least one event, an empty `Block` buffer would stall the publisher forever.
+#[derive(Debug, Clone, Copy)]
+pub struct SubscribeOptions {
+    capacity: usize,
+    policy: BufferPolicy,
+}
+
+impl Default for SubscribeOptions {
+    fn default() -> Self {
+        SubscribeOptions {
+            capacity: 1024,
+            policy: BufferPolicy::default(),
+        }
+    }
+}
+
+impl SubscribeOptions {
+    pub fn new(capacity: usize, policy: BufferPolicy) -> Self {
+        SubscribeOptions {
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriberStats {
+    pub delivered: u64,
+    pub dropped: u64,
+    pub buffered: usize,
+}
+
+/// A topic pattern, the segments are separated by `/`.
+/// `*` matches exactly one segment and a trailing `#` matches any number of segments,
+/// e.g. `plant/*/temperature` or `plant/line-1/#`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicPattern(Vec<String>);
+
+impl TopicPattern {
+    pub fn new(pattern: &str) -> Self {
+        TopicPattern(pattern.split('/').map(str::to_string).collect())
+    }
+
+    pub fn matches(&self, topic: &str) -> bool {
+        let mut topic = topic.split('/');
+        for segment in &self.0 {
+            match (segment.as_str(), topic.next()) {
+                ("#", _) => return true,
+                ("*", Some(_)) => {}
+                (s, Some(t)) if s == t => {}
+                _ => return false,
+            }
+        }
+        topic.next().is_none()
+    }
+}
+
+struct Buffer<M> {
+    options: SubscribeOptions,
+    events: Mutex<VecDeque<M>>,
+    has_events: Notify,
+    has_room: Notify,
+    closed: AtomicBool,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl<M> Buffer<M> {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<M>> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.has_events.notify_one();
+        self.has_room.notify_waiters();
+    }
+
+    /// Returns false when the event was dropped.
+    async fn push(&self, event: M, wait: bool) -> bool {
+        let mut event = Some(event);
+        loop {
+            if self.is_closed() {
+                return false;
+            }
+            let room = self.has_room.notified();
+            {
+                let mut events = self.lock();
+                if events.len() < self.options.capacity {
+                    events.extend(event.take());
+                } else {
+                    match self.options.policy {
+                        BufferPolicy::DropNewest => {
+                            self.dropped.fetch_add(1, Ordering::SeqCst);
+                            return false;
+                        }
+                        BufferPolicy::DropOldest => {
+                            events.pop_front();
+                            events.extend(event.take());
+                            self.dropped.fetch_add(1, Ordering::SeqCst);
+                        }
+                        BufferPolicy::Block if !wait => {
+                            self.dropped.fetch_add(1, Ordering::SeqCst);
+                            return false;
+                        }
+                        BufferPolicy::Block => {}
+                    }
+                }
+            }
+            if event.is_none() {
+                self.has_events.notify_one();
+                return true;
+            }
+            room.await;
+        }
+    }
+
+    async fn pop(&self) -> Option<M> {
+        loop {
+            let notified = self.has_events.notified();
+            if let Some(event) = self.lock().pop_front() {
+                self.has_room.notify_one();
+                return Some(event);
+            }
+            if self.is_closed() {
+                return None;
+            }
+            notified.await;
+        }
+    }
+}
+
+trait AnySubscriber: Send + Sync {
+    fn id(&self) -> SubscriptionId;
+    fn is_closed(&self) -> bool;
+    fn close(&self);
+    fn stats(&self) -> SubscriberStats;
+    fn as_any(&self) -> &dyn Any;
+}
+
+struct Subscriber<M> {
+    id: SubscriptionId,
+    pattern: TopicPattern,
+    buffer: Arc<Buffer<M>>,
+}
+
+impl<M: Send + 'static> AnySubscriber for Subscriber<M> {
+    fn id(&self) -> SubscriptionId {
+        self.id
+    }
+
+    fn is_closed(&self) -> bool {
+        self.buffer.is_closed()
+    }
+
+    fn close(&self) {
+        self.buffer.close()
+    }
+
+    fn stats(&self) -> SubscriberStats {
+        SubscriberStats {
+            delivered: self.buffer.delivered.load(Ordering::SeqCst),
+            dropped: self.buffer.dropped.load(Ordering::SeqCst),
+            buffered: self.buffer.lock().len(),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[derive(Default)]
+struct BusInner {
+    next_id: u64,
+    subscribers: HashMap<TypeId, Vec<Arc<dyn AnySubscriber>>>,
+}
+
+/// In-process publish/subscribe between actors.
+///
+/// Events are typed: a subscriber of `M` only gets the events of type `M`
+/// published to the topics matching its pattern.
+/// Every subscriber has its own buffer drained into the recipient,
+/// so a slow subscriber does not hold back the others unless its policy is `Block`.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Mutex<BusInner>>,
+}
+
+impl std::fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let subscribers: usize = self.lock().subscribers.values().map(Vec::len).sum();
+        f.debug_struct("EventBus")
+            .field("subscribers", &subscribers)
+            .finish()
+    }
+}
+
+impl EventBus {
+    /// The bus used by the actors unless another one is given explicitly.
+    pub fn global() -> EventBus {
+        static GLOBAL: OnceLock<EventBus> = OnceLock::new();
+        GLOBAL.get_or_init(EventBus::default).clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BusInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Subscribes the recipient to the topics matching the pattern.
+    /// Has to be called within a running actix system, the buffer is drained by a spawned task.
+    pub fn subscribe<M>(
+        &self,
+        pattern: &str,
+        recipient: Recipient<M>,
+        options: SubscribeOptions,
+    ) -> SubscriptionId
+    where
+        M: actix::Message + Send + 'static,
+        M::Result: Send,
+    {
+        let buffer = Arc::new(Buffer {
+            options,
+            events: Mutex::new(VecDeque::new()),
+            has_events: Notify::new(),
+            has_room: Notify::new(),
+            closed: AtomicBool::new(false),
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+
+        let id = {
+            let mut inner = self.lock();
+            inner.next_id += 1;
+            let id = SubscriptionId(inner.next_id);
+            inner
+                .subscribers
+                .entry(TypeId::of::<M>())
+                .or_default()
+                .push(Arc::new(Subscriber {
+                    id,
+                    pattern: TopicPattern::new(pattern),
+                    buffer: buffer.clone(),
+                }));
+            id
+        };
+
+        actix::spawn(async move {
+            while let Some(event) = buffer.pop().await {
+                if recipient.send(event).await.is_err() {
+                    buffer.close();
+                    break;
+                }
+                buffer.delivered.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        id
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) {
+        for subscribers in self.lock().subscribers.values_mut() {
+            subscribers.retain(|s| {
+                if s.id() == id {
+                    s.close();
+                }
+                s.id() != id
+            });
+        }
+    }
+
+    pub fn stats(&self, id: SubscriptionId) -> Option<SubscriberStats> {
+        self.lock()
+            .subscribers
+            .values()
+            .flatten()
+            .find(|s| s.id() == id)
+            .map(|s| s.stats())
+    }
+
+    fn buffers<M: Send + 'static>(&self, topic: &str) -> Vec<Arc<Buffer<M>>> {
+        let mut inner = self.lock();
+        let Some(subscribers) = inner.subscribers.get_mut(&TypeId::of::<M>()) else {
+            return vec![];
+        };
+        subscribers.retain(|s| !s.is_closed());
+        subscribers
+            .iter()
+            .filter_map(|s| s.as_any().downcast_ref::<Subscriber<M>>())
+            .filter(|s| s.pattern.matches(topic))
+            .map(|s| s.buffer.clone())
+            .collect()
+    }
+
+    /// Publishes the event to every matching subscriber and returns how many accepted it.
+    /// Waits for room in the buffers of the `Block` subscribers.
+    pub async fn publish<M>(&self, topic: &str, event: M) -> usize
+    where
+        M: Clone + Send + 'static,
+    {
+        let mut accepted = 0;
+        for buffer in self.buffers::<M>(topic) {
+            if buffer.push(event.clone(), true).await {
+                accepted += 1;
+            }
+        }
+        accepted
+    }
+
+    /// Publishes without waiting, a full `Block` buffer drops the event like `DropNewest`.
+    pub fn try_publish<M>(&self, topic: &str, event: M) -> usize
+    where
+        M: Clone + Send + 'static,
+    {
+        let mut accepted = 0;
+        for buffer in self.buffers::<M>(topic) {
+            let pushed = futures_util::FutureExt::now_or_never(buffer.push(event.clone(), false));
+            if pushed == Some(true) {
+                accepted += 1;
+            }
+        }
+        accepted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::{Actor, Context, Handler, Message};
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, Message)]
+    #[rtype(result = "()")]
+    struct Reading(u32);
+
+    struct Collector(Arc<Mutex<Vec<u32>>>, Duration);
+
+    impl Actor for Collector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Reading> for Collector {
+        type Result = actix::ResponseFuture<()>;
+
+        fn handle(&mut self, msg: Reading, _ctx: &mut Self::Context) -> Self::Result {
+            self.0.lock().unwrap().push(msg.0);
+            let delay = self.1;
+            Box::pin(actix::clock::sleep(delay))
+        }
+    }
+
+    fn collector(delay: Duration) -> (Recipient<Reading>, Arc<Mutex<Vec<u32>>>) {
+        let events = Arc::new(Mutex::new(vec![]));
+        (Collector(events.clone(), delay).start().recipient(), events)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(TopicPattern::new("plant/*/temp").matches("plant/line-1/temp"));
+        assert!(!TopicPattern::new("plant/*/temp").matches("plant/line-1/press"));
+        assert!(!TopicPattern::new("plant/*").matches("plant/line-1/temp"));
+        assert!(TopicPattern::new("plant/#").matches("plant/line-1/temp"));
+        assert!(TopicPattern::new("#").matches("anything"));
+        assert!(TopicPattern::new("plant").matches("plant"));
+    }
+
+    #[actix::test]
+    async fn publish_with_policies() {
+        let bus = EventBus::default();
+        let (fast, fast_events) = collector(Duration::ZERO);
+        let (slow, slow_events) = collector(Duration::from_millis(50));
+        let fast_id = bus.subscribe("line/#", fast, SubscribeOptions::default());
+        let slow_id = bus.subscribe(
+            "line/*/temp",
+            slow,
+            SubscribeOptions::new(1, BufferPolicy::DropNewest),
+        );
+
+        assert_eq!(bus.publish("other", Reading(0)).await, 0);
+        assert_eq!(bus.publish("other", "not a reading".to_string()).await, 0);
+        for i in 1..=5 {
+            bus.publish("line/1/temp", Reading(i)).await;
+        }
+        actix::clock::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(*fast_events.lock().unwrap(), vec![1, 2, 3, 4, 5]);
+        let slow_stats = bus.stats(slow_id).unwrap();
+        assert!(slow_stats.dropped > 0);
+        assert_eq!(
+            slow_stats.delivered + slow_stats.dropped,
+            5,
+            "{:?}",
+            slow_events
+        );
+        assert_eq!(bus.stats(fast_id).unwrap().delivered, 5);
+
+        bus.unsubscribe(fast_id);
+        assert_eq!(bus.publish("line/1/temp", Reading(6)).await, 1);
+        assert!(bus.stats(fast_id).is_none());
+    }
+
+    #[actix::test]
+    async fn block_and_drop_oldest() {
+        // an empty buffer would never have room
+        assert_eq!(SubscribeOptions::new(0, BufferPolicy::Block).capacity, 1);
+        let bus = EventBus::default();
+        let (slow, slow_events) = collector(Duration::from_millis(20));
+        bus.subscribe("t", slow, SubscribeOptions::new(1, BufferPolicy::Block));
+        for i in 1..=4 {
+            assert_eq!(bus.publish("t", Reading(i)).await, 1);
+        }
+        actix::clock::sleep(Duration::from_millis(150)).await;
+        assert_eq!(*slow_events.lock().unwrap(), vec![1, 2, 3, 4]);
+
+        let (slow, slow_events) = collector(Duration::from_millis(50));
+        let id = bus.subscribe(
+            "u",
+            slow,
+            SubscribeOptions::new(1, BufferPolicy::DropOldest),
+        );
+        for i in 1..=4 {
+            bus.try_publish("u", Reading(i));
+        }
+        actix::clock::sleep(Duration::from_millis(200)).await;
+        let events = slow_events.lock().unwrap().clone();
+        assert_eq!(events.last(), Some(&4));
+        assert_eq!(bus.stats(id).unwrap().dropped as usize, 4 - events.len());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{ActorError, ActorResultVoid};
+use actix::fut::{ActorStreamExt, wrap_stream};
+use actix::{Actor, AsyncContext, Context, SpawnHandle};
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+#[derive(Debug)]
+struct ClockInner {
+    /// The simulated time at the moment `anchor` was taken.
+    sim_anchor: DateTime<Utc>,
+    anchor: Instant,
+    speed: f64,
+    paused: bool,
+}
+
+impl ClockInner {
+    fn now(&self) -> DateTime<Utc> {
+        if self.paused {
+            self.sim_anchor
+        } else {
+            let elapsed = self.anchor.elapsed().mul_f64(self.speed);
+            self.sim_anchor + chrono::Duration::from_std(elapsed).unwrap_or(chrono::Duration::MAX)
+        }
+    }
+
+    fn rebase(&mut self) {
+        self.sim_anchor = self.now();
+        self.anchor = Instant::now();
+    }
+}
+
+/// Simulation time shared between actors.
+///
+/// The clock runs at `speed` times the wall clock, can be paused
+/// and advanced step by step. Sleepers are woken up whenever the clock is changed,
+/// so a paused clock only moves forward through `advance`.
+#[derive(Debug, Clone)]
+pub struct SimClock {
+    inner: Arc<Mutex<ClockInner>>,
+    changes: Arc<watch::Sender<()>>,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        SimClock::starting_at(Utc::now())
+    }
+}
+
+impl SimClock {
+    /// A clock running with the wall clock speed starting at the given time.
+    pub fn starting_at(start: DateTime<Utc>) -> Self {
+        SimClock {
+            inner: Arc::new(Mutex::new(ClockInner {
+                sim_anchor: start,
+                anchor: Instant::now(),
+                speed: 1.0,
+                paused: false,
+            })),
+            changes: Arc::new(watch::Sender::new(())),
+        }
+    }
+
+    /// The clock used by the actors unless another one is given explicitly.
+    pub fn global() -> SimClock {
+        static GLOBAL: OnceLock<SimClock> = OnceLock::new();
+        GLOBAL.get_or_init(SimClock::default).clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ClockInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ClockInner)) {
+        {
+            let mut inner = self.lock();
+            inner.rebase();
+            f(&mut inner);
+        }
+        self.changes.send_replace(());
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.lock().now()
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.lock().speed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    /// Sets how many simulated seconds pass per wall clock second.
+    pub fn set_speed(&self, speed: f64) -> ActorResultVoid {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(ActorError::RuntimeError(format!(
+                "The clock speed has to be positive, got {}",
+                speed
+            )));
+        }
+        self.update(|inner| inner.speed = speed);
+        Ok(())
+    }
+
+    pub fn pause(&self) {
+        self.update(|inner| inner.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.update(|inner| inner.paused = false);
+    }
+
+    /// Moves the simulated time forward, it works for both a running and a paused clock.
+    pub fn advance(&self, step: Duration) {
+        let step = chrono::Duration::from_std(step).unwrap_or(chrono::Duration::MAX);
+        self.update(|inner| inner.sim_anchor += step);
+    }
+
+    /// Waits until the given amount of simulated time has passed.
+    pub async fn sleep(&self, duration: Duration) {
+        let deadline =
+            self.now() + chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX);
+        self.sleep_until(deadline).await
+    }
+
+    /// Waits until the simulated time reaches the deadline.
+    pub async fn sleep_until(&self, deadline: DateTime<Utc>) {
+        let mut changes = self.changes.subscribe();
+        loop {
+            let (now, speed, paused) = {
+                let inner = self.lock();
+                (inner.now(), inner.speed, inner.paused)
+            };
+            let Ok(remaining) = (deadline - now).to_std() else {
+                return;
+            };
+            if remaining.is_zero() {
+                return;
+            }
+            if paused {
+                let _ = changes.changed().await;
+            } else {
+                tokio::select! {
+                    _ = tokio::time::sleep(remaining.div_f64(speed)) => {}
+                    _ = changes.changed() => {}
+                }
+            }
+        }
+    }
+}
+
+/// Runs `f` every `period` of simulated time, the counterpart of `AsyncContext::run_interval`.
+/// The ticks do not drift but do not catch up either: when the clock jumps forward
+/// (e.g. `advance`), the missed ticks are skipped and `f` runs once.
+pub fn run_interval<A, F>(
+    clock: &SimClock,
+    ctx: &mut Context<A>,
+    period: Duration,
+    mut f: F,
+) -> SpawnHandle
+where
+    A: Actor<Context = Context<A>>,
+    F: FnMut(&mut A, &mut Context<A>) + 'static,
+{
+    let clock = clock.clone();
+    let period = chrono::Duration::from_std(period).unwrap_or(chrono::Duration::MAX);
+    let first = clock.now() + period;
+    let ticks = futures_util::stream::unfold(first, move |next| {
+        let clock = clock.clone();
+        async move {
+            clock.sleep_until(next).await;
+            Some(((), next_tick(next, period, clock.now())))
+        }
+    });
+    ctx.spawn(
+        wrap_stream(ticks)
+            .map(move |_, act, ctx| f(act, ctx))
+            .finish(),
+    )
+}
+
+/// The first deadline after `now` on the grid of `period` starting at the `last` one.
+fn next_tick(last: DateTime<Utc>, period: chrono::Duration, now: DateTime<Utc>) -> DateTime<Utc> {
+    match ((now - last).num_nanoseconds(), period.num_nanoseconds()) {
+        (Some(behind), Some(nanos)) if nanos > 0 => {
+            last + chrono::Duration::nanoseconds(nanos.saturating_mul(behind / nanos + 1))
+        }
+        _ => last + period,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn speed_pause_advance() {
+        let start = Utc::now();
+        let clock = SimClock::starting_at(start);
+        clock.pause();
+        let paused_at = clock.now();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(clock.now(), paused_at);
+
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(clock.now() - paused_at, chrono::Duration::hours(1));
+
+        clock.set_speed(1000.0).unwrap();
+        clock.resume();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(
+            clock.now() - paused_at >= chrono::Duration::hours(1) + chrono::Duration::seconds(20)
+        );
+        assert!(clock.set_speed(0.0).is_err());
+    }
+
+    struct Ticker(Arc<AtomicUsize>, SimClock);
+
+    impl Actor for Ticker {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Self::Context) {
+            let clock = self.1.clone();
+            run_interval(&clock, ctx, Duration::from_secs(60), |act, _| {
+                act.0.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    }
+
+    #[actix::test]
+    async fn interval_follows_the_clock() {
+        let clock = SimClock::default();
+        clock.pause();
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let _ticker = Ticker(ticks.clone(), clock.clone()).start();
+
+        actix::clock::sleep(Duration::from_millis(20)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 0);
+
+        // the missed ticks are coalesced into one, the next one keeps the phase
+        clock.advance(Duration::from_secs(150));
+        actix::clock::sleep(Duration::from_millis(20)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 1);
+        clock.advance(Duration::from_secs(20));
+        actix::clock::sleep(Duration::from_millis(20)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 1);
+        clock.advance(Duration::from_secs(10));
+        actix::clock::sleep(Duration::from_millis(20)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), 2);
+
+        clock.set_speed(60_000.0).unwrap();
+        clock.resume();
+        let sleeper = clock.clone();
+        sleeper.sleep(Duration::from_secs(60)).await;
+        actix::clock::sleep(Duration::from_millis(20)).await;
+        assert!(ticks.load(Ordering::SeqCst) >= 3);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::trace::{Span, TraceRecorder};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The participant used for the hops coming from outside of the simulation, e.g. a test client.
+pub const EXTERNAL: &str = "external";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagramFormat {
+    PlantUml,
+    Mermaid,
+}
+
+impl DiagramFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DiagramFormat::PlantUml => "puml",
+            DiagramFormat::Mermaid => "mmd",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arrow {
+    pub from: String,
+    pub to: String,
+    pub label: String,
+}
+
+/// The "data travel map": participants are actor keys and every recorded hop is an arrow
+/// from the actor of the parent span to the actor of the span.
+#[derive(Debug, Clone)]
+pub struct SequenceDiagram {
+    pub title: String,
+    pub participants: Vec<String>,
+    pub arrows: Vec<Arrow>,
+}
+
+impl SequenceDiagram {
+    pub fn from_spans(title: impl Into<String>, spans: &[Span]) -> Self {
+        let mut spans = spans.to_vec();
+        spans.sort_by_key(|s| s.timestamp);
+        let actors: HashMap<&str, &str> = spans
+            .iter()
+            .map(|s| (s.span_id.as_str(), s.actor.as_str()))
+            .collect();
+
+        let mut participants: Vec<String> = vec![];
+        let mut arrows = vec![];
+        for span in &spans {
+            let from = span
+                .parent_span_id
+                .as_deref()
+                .and_then(|p| actors.get(p).copied())
+                .unwrap_or(EXTERNAL);
+            for p in [from, span.actor.as_str()] {
+                if !participants.iter().any(|known| known == p) {
+                    participants.push(p.to_string());
+                }
+            }
+            let label = if span.detail.is_empty() {
+                span.protocol.to_string()
+            } else {
+                format!("{} {}", span.protocol, span.detail)
+            };
+            arrows.push(Arrow {
+                from: from.to_string(),
+                to: span.actor.clone(),
+                label: label.replace(['\n', '\r'], " "),
+            });
+        }
+
+        SequenceDiagram {
+            title: title.into(),
+            participants,
+            arrows,
+        }
+    }
+
+    /// The diagram of one transaction.
+    pub fn for_trace(recorder: &TraceRecorder, trace_id: &str) -> Self {
+        SequenceDiagram::from_spans(trace_id, &recorder.trace(trace_id))
+    }
+
+    /// The diagram of everything recorded so far, e.g. of a whole test run.
+    pub fn for_run(recorder: &TraceRecorder, title: impl Into<String>) -> Self {
+        SequenceDiagram::from_spans(title, &recorder.spans())
+    }
+
+    fn alias(&self, participant: &str) -> String {
+        let idx = self
+            .participants
+            .iter()
+            .position(|p| p == participant)
+            .unwrap_or_default();
+        format!("P{}", idx)
+    }
+
+    pub fn render(&self, format: DiagramFormat) -> String {
+        let mut out = vec![];
+        match format {
+            DiagramFormat::PlantUml => {
+                out.push("@startuml".to_string());
+                out.push(format!("title {}", self.title));
+                out.push("autonumber".to_string());
+                for p in &self.participants {
+                    out.push(format!("participant \"{}\" as {}", p, self.alias(p)));
+                }
+                for a in &self.arrows {
+                    out.push(format!(
+                        "{} -> {} : {}",
+                        self.alias(&a.from),
+                        self.alias(&a.to),
+                        a.label
+                    ));
+                }
+                out.push("@enduml".to_string());
+            }
+            DiagramFormat::Mermaid => {
+                out.push("sequenceDiagram".to_string());
+                out.push(format!("    title {}", self.title));
+                out.push("    autonumber".to_string());
+                for p in &self.participants {
+                    out.push(format!("    participant {} as {}", self.alias(p), p));
+                }
+                for a in &self.arrows {
+                    out.push(format!(
+                        "    {}->>{}: {}",
+                        self.alias(&a.from),
+                        self.alias(&a.to),
+                        a.label.replace(';', "#59;")
+                    ));
+                }
+            }
+        }
+        out.push(String::new());
+        out.join("\n")
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>, format: DiagramFormat) -> std::io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.render(format))
+    }
+
+    /// Writes `<dir>/<name>.puml` and `<dir>/<name>.mmd` and returns their paths.
+    pub fn write_all(&self, dir: impl AsRef<Path>, name: &str) -> std::io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for format in [DiagramFormat::PlantUml, DiagramFormat::Mermaid] {
+            let path = dir
+                .as_ref()
+                .join(format!("{}.{}", name, format.extension()));
+            self.write(&path, format)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::{Direction, Protocol, TraceContext};
+
+    #[test]
+    fn render_trace() {
+        let recorder = TraceRecorder::new(100);
+        let order = TraceContext::new_root();
+        recorder.record(&order, "mes", Protocol::Sql, Direction::Outbound, "orders");
+        let sent = order.child();
+        recorder.record(
+            &sent,
+            "bridge",
+            Protocol::Amqp,
+            Direction::Outbound,
+            "orders",
+        );
+        let received = sent.child();
+        recorder.record(
+            &received,
+            "fes",
+            Protocol::Amqp,
+            Direction::Inbound,
+            "orders",
+        );
+        let cmd = received.child();
+        recorder.record(
+            &cmd,
+            "machine",
+            Protocol::Ssh,
+            Direction::Inbound,
+            "start; go",
+        );
+
+        let diagram = SequenceDiagram::for_trace(&recorder, &order.trace_id);
+        assert_eq!(
+            diagram.participants,
+            vec![EXTERNAL, "mes", "bridge", "fes", "machine"]
+        );
+
+        let plantuml = diagram.render(DiagramFormat::PlantUml);
+        assert!(plantuml.starts_with("@startuml"));
+        assert!(plantuml.contains("participant \"machine\" as P4"));
+        assert!(plantuml.contains("P3 -> P4 : Ssh start; go"));
+
+        let mermaid = diagram.render(DiagramFormat::Mermaid);
+        assert!(mermaid.contains("P0->>P1: Sql orders"));
+        assert!(mermaid.contains("P3->>P4: Ssh start#59; go"));
+
+        let dir = std::env::temp_dir().join(format!("parallax-diagram-{}", order.trace_id));
+        let paths = diagram.write_all(&dir, "order").unwrap();
+        assert_eq!(std::fs::read_to_string(&paths[1]).unwrap(), mermaid);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
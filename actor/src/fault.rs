@@ -0,0 +1,231 @@
+use crate::clock::SimClock;
+use crate::{ActorError, ActorResultVoid};
+use actix::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// How often a hanging request checks whether the hang is over.
+const HANG_POLL: Duration = Duration::from_millis(100);
+
+/// A misbehaviour of the simulated equipment, the actors ignore the faults of other protocols.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fault {
+    /// Delays the responses (HTTP, SSH) or the messages (AMQP) by the simulated milliseconds.
+    DelayMs(u64),
+    /// HTTP answers with the status instead of the route, e.g. 500 or 503.
+    HttpError(u16),
+    /// SSH refuses the authentication.
+    RefuseAuth,
+    /// HTTP and SSH do not answer until the fault is over.
+    Hang,
+    /// OPC UA writes the values with a bad status code.
+    BadStatus,
+    /// OPC UA stops accepting connections.
+    Offline,
+    /// AMQP drops the messages.
+    Drop,
+    /// AMQP delivers the messages twice.
+    Duplicate,
+}
+
+/// A fault and when it hits: each request or message is hit with the probability
+/// while the simulation time is in the window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FaultInjection {
+    pub fault: Fault,
+    #[serde(default = "always")]
+    pub probability: f64,
+    /// The fault is active from the start until the end of the window, always when missing.
+    #[serde(default)]
+    pub window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+fn always() -> f64 {
+    1.0
+}
+
+impl FaultInjection {
+    pub fn new(fault: Fault) -> Self {
+        FaultInjection {
+            fault,
+            probability: always(),
+            window: None,
+        }
+    }
+
+    pub fn with_probability(mut self, probability: f64) -> Self {
+        self.probability = probability;
+        self
+    }
+
+    pub fn between(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        self.window = Some((start, end));
+        self
+    }
+
+    /// The window starting at `start` and lasting the given simulated time.
+    pub fn during(self, start: DateTime<Utc>, duration: Duration) -> Self {
+        let end = start + chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX);
+        self.between(start, end)
+    }
+
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.window
+            .is_none_or(|(start, end)| start <= now && now < end)
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.window.is_some_and(|(_, end)| end <= now)
+    }
+}
+
+/// Injects or clears the faults of a protocol actor.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "ActorResultVoid")]
+pub enum FaultMessage {
+    Inject(FaultInjection),
+    Clear,
+}
+
+/// The faults of an actor, shared with the tasks serving its connections.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    key: String,
+    injections: Arc<Mutex<Vec<FaultInjection>>>,
+    clock: SimClock,
+}
+
+impl Faults {
+    pub fn new(key: impl Into<String>) -> Self {
+        Faults {
+            key: key.into(),
+            injections: Arc::new(Mutex::new(vec![])),
+            clock: SimClock::global(),
+        }
+    }
+
+    /// Follows the windows and the delays on the given clock instead of the global one.
+    pub fn with_clock(mut self, clock: SimClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<FaultInjection>> {
+        self.injections.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn handle(&self, msg: FaultMessage) -> ActorResultVoid {
+        match msg {
+            FaultMessage::Inject(injection) => {
+                if !(0.0..=1.0).contains(&injection.probability) {
+                    return Err(ActorError::RuntimeError(format!(
+                        "The probability {} is not in [0, 1]",
+                        injection.probability
+                    )));
+                }
+                log::info!("[{}] Inject {:?}", self.key, injection);
+                self.lock().push(injection);
+            }
+            FaultMessage::Clear => {
+                log::info!("[{}] Clear the faults", self.key);
+                self.lock().clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// The faults in their windows, regardless of the probability.
+    pub fn active(&self) -> Vec<Fault> {
+        let now = self.clock.now();
+        let mut injections = self.lock();
+        injections.retain(|i| !i.is_expired(now));
+        injections
+            .iter()
+            .filter(|i| i.is_active(now))
+            .map(|i| i.fault.clone())
+            .collect()
+    }
+
+    /// The faults hitting a request or a message, each one is drawn with its probability.
+    pub fn draw(&self) -> Vec<Fault> {
+        let now = self.clock.now();
+        let mut injections = self.lock();
+        injections.retain(|i| !i.is_expired(now));
+        injections
+            .iter()
+            .filter(|i| i.is_active(now) && rand::random::<f64>() < i.probability)
+            .map(|i| i.fault.clone())
+            .collect()
+    }
+
+    /// Waits the sum of the drawn delays in the simulated time.
+    pub async fn delay(&self, drawn: &[Fault]) {
+        let ms: u64 = drawn
+            .iter()
+            .map(|f| match f {
+                Fault::DelayMs(ms) => *ms,
+                _ => 0,
+            })
+            .sum();
+        if ms > 0 {
+            self.clock.sleep(Duration::from_millis(ms)).await;
+        }
+    }
+
+    /// Waits while a hang is active, a hang without a window lasts until the faults are cleared.
+    pub async fn hang(&self) {
+        while self.active().contains(&Fault::Hang) {
+            self.clock.sleep(HANG_POLL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probability_and_window() {
+        let clock = SimClock::default();
+        clock.pause();
+        let faults = Faults::new("plc").with_clock(clock.clone());
+        let now = clock.now();
+        faults
+            .handle(FaultMessage::Inject(FaultInjection::new(Fault::Drop)))
+            .unwrap();
+        faults
+            .handle(FaultMessage::Inject(
+                FaultInjection::new(Fault::HttpError(503)).with_probability(0.0),
+            ))
+            .unwrap();
+        faults
+            .handle(FaultMessage::Inject(
+                FaultInjection::new(Fault::Offline)
+                    .during(now + chrono::Duration::seconds(10), Duration::from_secs(5)),
+            ))
+            .unwrap();
+        assert!(
+            faults
+                .handle(FaultMessage::Inject(
+                    FaultInjection::new(Fault::Hang).with_probability(2.0)
+                ))
+                .is_err()
+        );
+
+        assert_eq!(faults.draw(), vec![Fault::Drop]);
+        assert_eq!(faults.active(), vec![Fault::Drop, Fault::HttpError(503)]);
+
+        clock.advance(Duration::from_secs(12));
+        assert_eq!(faults.draw(), vec![Fault::Drop, Fault::Offline]);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(faults.draw(), vec![Fault::Drop]);
+        assert_eq!(faults.lock().len(), 2);
+
+        faults.handle(FaultMessage::Clear).unwrap();
+        assert!(faults.active().is_empty());
+    }
+}
@@ -1,5 +1,14 @@
+pub mod bus;
+pub mod clock;
+pub mod diagram;
+pub mod fault;
+pub mod recording;
+pub mod registry;
+pub mod status;
+pub mod supervisor;
+pub mod trace;
+
 use actix::{Actor, Context, Handler, Message};
-use log::log;
 use std::fmt::Display;
 
 #[derive(Debug)]
@@ -18,6 +27,16 @@ pub enum ActorServiceMessage {
     Stop,
 }
 
+impl Display for ActorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActorError::StartupError(e) => write!(f, "startup error: {}", e),
+            ActorError::RuntimeError(e) => write!(f, "runtime error: {}", e),
+            ActorError::ShutdownError(e) => write!(f, "shutdown error: {}", e),
+        }
+    }
+}
+
 impl From<String> for ActorError {
     fn from(s: String) -> Self {
         ActorError::RuntimeError(s)
@@ -33,7 +52,7 @@ impl Actor for EchoActor {
 impl<T: Display + actix::Message<Result = ()>> Handler<T> for EchoActor {
     type Result = ();
 
-    fn handle(&mut self, msg: T, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: T, _ctx: &mut Self::Context) -> Self::Result {
         log::info!("[Echo] {}", msg);
     }
 }
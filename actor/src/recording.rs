@@ -0,0 +1,201 @@
+use crate::clock::SimClock;
+use crate::trace::{Direction, Protocol};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// What was exchanged, the responses are kept to be compared on replay.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Exchange {
+    Http {
+        method: String,
+        /// The path and the query.
+        uri: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(default)]
+        body: String,
+        status: Option<u16>,
+        response: Option<String>,
+    },
+    Ssh {
+        command: String,
+        output: Option<String>,
+    },
+    Amqp {
+        topic: String,
+        body: serde_json::Value,
+    },
+    OpcUa {
+        node: String,
+        /// The OPC UA type of the value, e.g. `Double`.
+        data_type: String,
+        value: serde_json::Value,
+    },
+}
+
+impl Exchange {
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            Exchange::Http { .. } => Protocol::Http,
+            Exchange::Ssh { .. } => Protocol::Ssh,
+            Exchange::Amqp { .. } => Protocol::Amqp,
+            Exchange::OpcUa { .. } => Protocol::OpcUa,
+        }
+    }
+}
+
+/// One line of the recording.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    /// The simulation time of the interaction.
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub direction: Direction,
+    #[serde(flatten)]
+    pub exchange: Exchange,
+}
+
+#[derive(Debug, Default)]
+struct TrafficInner {
+    recording: bool,
+    interactions: Vec<Interaction>,
+}
+
+/// Records the protocol traffic of the actors while it is switched on, it is off by default.
+/// The recording is saved as json lines and can be replayed against a system under test.
+#[derive(Debug, Clone)]
+pub struct TrafficRecorder {
+    inner: Arc<Mutex<TrafficInner>>,
+    clock: SimClock,
+}
+
+impl Default for TrafficRecorder {
+    fn default() -> Self {
+        TrafficRecorder {
+            inner: Arc::new(Mutex::new(TrafficInner::default())),
+            clock: SimClock::global(),
+        }
+    }
+}
+
+impl TrafficRecorder {
+    /// The recorder used by the actors.
+    pub fn global() -> TrafficRecorder {
+        static GLOBAL: OnceLock<TrafficRecorder> = OnceLock::new();
+        GLOBAL.get_or_init(TrafficRecorder::default).clone()
+    }
+
+    pub fn with_clock(mut self, clock: SimClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TrafficInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn start(&self) {
+        self.lock().recording = true;
+    }
+
+    pub fn stop(&self) {
+        self.lock().recording = false;
+    }
+
+    /// The actors skip preparing the exchanges (e.g. buffering the bodies) when it is off.
+    pub fn is_recording(&self) -> bool {
+        self.lock().recording
+    }
+
+    pub fn record(&self, actor: &str, direction: Direction, exchange: Exchange) {
+        let mut inner = self.lock();
+        if inner.recording {
+            inner.interactions.push(Interaction {
+                timestamp: self.clock.now(),
+                actor: actor.to_string(),
+                direction,
+                exchange,
+            });
+        }
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.lock().interactions.clone()
+    }
+
+    pub fn clear(&self) {
+        self.lock().interactions.clear();
+    }
+
+    /// Writes the interactions as json lines.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut out = BufWriter::new(std::fs::File::create(path)?);
+        for interaction in self.lock().interactions.iter() {
+            serde_json::to_writer(&mut out, interaction)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Vec<Interaction>> {
+        let file = std::fs::File::open(path)?;
+        let mut interactions = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                interactions.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(interactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_save_load() {
+        let recorder = TrafficRecorder::default();
+        let ssh = Exchange::Ssh {
+            command: "cat recipe.txt".to_string(),
+            output: Some("speed=10".to_string()),
+        };
+        recorder.record("shell", Direction::Inbound, ssh.clone());
+        assert!(recorder.interactions().is_empty());
+
+        recorder.start();
+        recorder.record("shell", Direction::Inbound, ssh);
+        recorder.record(
+            "line",
+            Direction::Outbound,
+            Exchange::Amqp {
+                topic: "orders".to_string(),
+                body: serde_json::json!({"id": 1}),
+            },
+        );
+        recorder.stop();
+        recorder.record(
+            "late",
+            Direction::Inbound,
+            Exchange::Ssh {
+                command: "ls".to_string(),
+                output: None,
+            },
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("parallax-traffic-{}.jsonl", std::process::id()));
+        recorder.save(&path).unwrap();
+        let loaded = TrafficRecorder::load(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded, recorder.interactions());
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].exchange.protocol(), Protocol::Amqp);
+    }
+}
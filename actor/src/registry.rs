@@ -0,0 +1,299 @@
+use crate::ActorServiceMessage;
+use actix::{
+    Actor, Context, Handler, Message, MessageResult, Recipient, Supervised, SystemService,
+    WeakRecipient,
+};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// The set of typed recipients an actor exposes under its key.
+/// The service recipient is mandatory and identifies the actor instance.
+pub struct Registration {
+    key: String,
+    service: WeakRecipient<ActorServiceMessage>,
+    recipients: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl Registration {
+    pub fn new(key: impl Into<String>, service: Recipient<ActorServiceMessage>) -> Self {
+        Registration {
+            key: key.into(),
+            service: service.downgrade(),
+            recipients: HashMap::new(),
+        }
+    }
+
+    pub fn with<M>(mut self, recipient: Recipient<M>) -> Self
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+    {
+        self.recipients
+            .insert(TypeId::of::<M>(), Box::new(recipient.downgrade()));
+        self
+    }
+
+    fn lookup<M>(&self) -> Option<Recipient<M>>
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+    {
+        if TypeId::of::<M>() == TypeId::of::<ActorServiceMessage>() {
+            let service: &dyn Any = &self.service;
+            return service
+                .downcast_ref::<WeakRecipient<M>>()
+                .and_then(WeakRecipient::upgrade);
+        }
+        self.recipients
+            .get(&TypeId::of::<M>())
+            .and_then(|r| r.downcast_ref::<WeakRecipient<M>>())
+            .and_then(WeakRecipient::upgrade)
+    }
+
+    fn is_alive(&self) -> bool {
+        self.service.upgrade().is_some_and(|s| s.connected())
+    }
+}
+
+/// Keeps track of the running actors by their keys.
+/// The registry holds weak references only, so it never keeps an actor alive.
+///
+/// The actors register themselves when they are started and deregister when they are stopped,
+/// so any of them can be found by key, e.g. `ActorRegistry::lookup::<UpdateValueMessage>("opcua_line_1")`.
+#[derive(Default)]
+pub struct ActorRegistry {
+    entries: HashMap<String, Registration>,
+}
+
+impl ActorRegistry {
+    pub fn register(registration: Registration) {
+        ActorRegistry::from_registry().do_send(Register(registration));
+    }
+
+    pub fn deregister(key: impl Into<String>, service: Recipient<ActorServiceMessage>) {
+        ActorRegistry::from_registry().do_send(Deregister {
+            key: key.into(),
+            service,
+        });
+    }
+
+    pub async fn lookup<M>(key: impl Into<String>) -> Option<Recipient<M>>
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+    {
+        ActorRegistry::from_registry()
+            .send(Lookup::<M>::new(key))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn keys() -> Vec<String> {
+        ActorRegistry::from_registry()
+            .send(RegisteredKeys)
+            .await
+            .unwrap_or_default()
+    }
+}
+
+impl Actor for ActorRegistry {
+    type Context = Context<Self>;
+}
+
+impl Supervised for ActorRegistry {}
+
+impl SystemService for ActorRegistry {}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Register(pub Registration);
+
+impl Handler<Register> for ActorRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: Register, _ctx: &mut Self::Context) -> Self::Result {
+        let registration = msg.0;
+        if let Some(prev) = self.entries.get(&registration.key)
+            && prev.is_alive()
+        {
+            log::warn!(
+                "[registry] The key {} is taken by a running actor, replacing it",
+                registration.key
+            );
+        }
+        log::info!("[registry] Registered {}", registration.key);
+        self.entries.insert(registration.key.clone(), registration);
+    }
+}
+
+/// Removes the key only when it still belongs to the given actor,
+/// so a stopping actor does not remove its restarted replacement.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Deregister {
+    pub key: String,
+    pub service: Recipient<ActorServiceMessage>,
+}
+
+impl Handler<Deregister> for ActorRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: Deregister, _ctx: &mut Self::Context) -> Self::Result {
+        let owned = self
+            .entries
+            .get(&msg.key)
+            .is_some_and(|e| e.service.upgrade().is_none_or(|s| s == msg.service));
+        if owned {
+            self.entries.remove(&msg.key);
+            log::info!("[registry] Deregistered {}", msg.key);
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<Recipient<M>>")]
+pub struct Lookup<M>
+where
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    key: String,
+    _phantom: std::marker::PhantomData<M>,
+}
+
+impl<M> Lookup<M>
+where
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    pub fn new(key: impl Into<String>) -> Self {
+        Lookup {
+            key: key.into(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<M> Handler<Lookup<M>> for ActorRegistry
+where
+    M: Message + Send + 'static,
+    M::Result: Send,
+{
+    type Result = MessageResult<Lookup<M>>;
+
+    fn handle(&mut self, msg: Lookup<M>, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(
+            self.entries
+                .get(&msg.key)
+                .and_then(|e| e.lookup::<M>())
+                .filter(|r| r.connected()),
+        )
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<String>")]
+pub struct RegisteredKeys;
+
+impl Handler<RegisteredKeys> for ActorRegistry {
+    type Result = MessageResult<RegisteredKeys>;
+
+    fn handle(&mut self, _msg: RegisteredKeys, _ctx: &mut Self::Context) -> Self::Result {
+        let mut keys: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.is_alive())
+            .map(|(k, _)| k.clone())
+            .collect();
+        keys.sort();
+        MessageResult(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ActorResultVoid;
+    use actix::clock::sleep;
+    use actix::{ActorContext, AsyncContext};
+    use std::time::Duration;
+
+    #[derive(Message)]
+    #[rtype(result = "String")]
+    struct Ping;
+
+    struct Machine(String);
+
+    impl Actor for Machine {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Self::Context) {
+            ActorRegistry::register(
+                Registration::new(self.0.clone(), ctx.address().recipient())
+                    .with(ctx.address().recipient::<Ping>()),
+            );
+        }
+
+        fn stopped(&mut self, ctx: &mut Self::Context) {
+            ActorRegistry::deregister(self.0.clone(), ctx.address().recipient());
+        }
+    }
+
+    impl Handler<ActorServiceMessage> for Machine {
+        type Result = ActorResultVoid;
+
+        fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Context<Self>) -> Self::Result {
+            if let ActorServiceMessage::Stop = msg {
+                ctx.stop();
+            }
+            Ok(())
+        }
+    }
+
+    impl Handler<Ping> for Machine {
+        type Result = String;
+
+        fn handle(&mut self, _msg: Ping, _ctx: &mut Context<Self>) -> Self::Result {
+            format!("pong from {}", self.0)
+        }
+    }
+
+    #[actix::test]
+    async fn register_lookup_deregister() {
+        let _line_1 = Machine("opcua_line_1".to_string()).start();
+        let _line_2 = Machine("opcua_line_2".to_string()).start();
+        sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(
+            ActorRegistry::keys().await,
+            vec!["opcua_line_1".to_string(), "opcua_line_2".to_string()]
+        );
+
+        let ping = ActorRegistry::lookup::<Ping>("opcua_line_1").await.unwrap();
+        assert_eq!(ping.send(Ping).await.unwrap(), "pong from opcua_line_1");
+
+        assert!(ActorRegistry::lookup::<Ping>("unknown").await.is_none());
+
+        let service = ActorRegistry::lookup::<ActorServiceMessage>("opcua_line_2")
+            .await
+            .unwrap();
+        service
+            .send(ActorServiceMessage::Stop)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(service);
+        sleep(Duration::from_millis(50)).await;
+
+        assert!(
+            ActorRegistry::lookup::<Ping>("opcua_line_2")
+                .await
+                .is_none()
+        );
+        assert_eq!(
+            ActorRegistry::keys().await,
+            vec!["opcua_line_1".to_string()]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{ActorError, ActorResult};
+use actix::{Message, Recipient};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActorState {
+    Created,
+    Starting,
+    Running,
+    /// The actor is alive but can not fully serve, e.g. it is reconnecting.
+    Degraded,
+    Stopping,
+    Stopped,
+    Failed,
+}
+
+impl Display for ActorState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ActorStatus {
+    pub key: String,
+    pub state: ActorState,
+    pub last_error: Option<String>,
+    /// The time since the actor became running for the first time.
+    pub uptime: Option<Duration>,
+}
+
+/// Asks an actor about its lifecycle state.
+#[derive(Debug, Message)]
+#[rtype(result = "ActorStatus")]
+pub struct ActorStatusMessage;
+
+#[derive(Debug)]
+struct LifecycleInner {
+    state: ActorState,
+    last_error: Option<String>,
+    running_since: Option<Instant>,
+}
+
+/// Lifecycle state shared between an actor and the futures it spawns.
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    key: String,
+    inner: Arc<Mutex<LifecycleInner>>,
+}
+
+impl Lifecycle {
+    pub fn new(key: impl Into<String>) -> Self {
+        Lifecycle {
+            key: key.into(),
+            inner: Arc::new(Mutex::new(LifecycleInner {
+                state: ActorState::Created,
+                last_error: None,
+                running_since: None,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LifecycleInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set(&self, state: ActorState) {
+        let mut inner = self.lock();
+        if inner.state != state {
+            log::debug!("[{}] {} -> {}", self.key, inner.state, state);
+        }
+        match state {
+            ActorState::Running | ActorState::Degraded => {
+                inner.running_since.get_or_insert_with(Instant::now);
+            }
+            ActorState::Stopped | ActorState::Failed | ActorState::Created => {
+                inner.running_since = None;
+            }
+            ActorState::Starting | ActorState::Stopping => {}
+        }
+        inner.state = state;
+    }
+
+    pub fn fail(&self, error: impl Display) {
+        self.set_with_error(ActorState::Failed, error);
+    }
+
+    pub fn degrade(&self, error: impl Display) {
+        self.set_with_error(ActorState::Degraded, error);
+    }
+
+    fn set_with_error(&self, state: ActorState, error: impl Display) {
+        let error = error.to_string();
+        log::warn!("[{}] {}: {}", self.key, state, error);
+        self.set(state);
+        self.lock().last_error = Some(error);
+    }
+
+    pub fn state(&self) -> ActorState {
+        self.lock().state
+    }
+
+    pub fn status(&self) -> ActorStatus {
+        let inner = self.lock();
+        ActorStatus {
+            key: self.key.clone(),
+            state: inner.state,
+            last_error: inner.last_error.clone(),
+            uptime: inner.running_since.map(|s| s.elapsed()),
+        }
+    }
+}
+
+/// Polls the actor until it reaches the expected state.
+/// Fails fast when the actor reports `Failed` or can not be reached anymore.
+pub async fn wait_for_state(
+    actor: &Recipient<ActorStatusMessage>,
+    expected: ActorState,
+    timeout: Duration,
+) -> ActorResult<ActorStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let status = actor
+            .send(ActorStatusMessage)
+            .await
+            .map_err(|e| ActorError::RuntimeError(e.to_string()))?;
+
+        if status.state == expected {
+            return Ok(status);
+        }
+        if status.state == ActorState::Failed {
+            return Err(ActorError::RuntimeError(format!(
+                "{} failed: {}",
+                status.key,
+                status.last_error.unwrap_or_default()
+            )));
+        }
+        if Instant::now() >= deadline {
+            return Err(ActorError::RuntimeError(format!(
+                "{} is {} after {:?}, expected {}",
+                status.key, status.state, timeout, expected
+            )));
+        }
+        actix::clock::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifecycle() {
+        let lifecycle = Lifecycle::new("machine");
+        assert_eq!(lifecycle.status().state, ActorState::Created);
+        assert!(lifecycle.status().uptime.is_none());
+
+        lifecycle.set(ActorState::Running);
+        lifecycle.degrade("connection lost");
+        let status = lifecycle.status();
+        assert_eq!(status.state, ActorState::Degraded);
+        assert_eq!(status.last_error.as_deref(), Some("connection lost"));
+        assert!(status.uptime.is_some());
+
+        lifecycle.clone().set(ActorState::Stopped);
+        assert_eq!(lifecycle.state(), ActorState::Stopped);
+        assert!(lifecycle.status().uptime.is_none());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::registry::{ActorRegistry, Registration};
+use crate::status::{ActorState, ActorStatusMessage, Lifecycle};
+use crate::{ActorError, ActorResultVoid, ActorServiceMessage};
+use actix::{
+    Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, Message, MessageResult,
+    Recipient, SpawnHandle, WrapFuture,
+};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub type ChildFactory = Arc<dyn Fn() -> Recipient<ActorServiceMessage> + Send + Sync>;
+
+/// Decides which children are restarted when one of them fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartStrategy {
+    /// Only the failed child is restarted.
+    #[default]
+    OneForOne,
+    /// Every child is restarted.
+    OneForAll,
+    /// The failed child and all children declared after it are restarted.
+    RestForOne,
+}
+
+/// Upper bound of restarts within a sliding window.
+/// When it is exceeded the supervisor stops all children and itself.
+#[derive(Debug, Clone, Copy)]
+pub struct RestartIntensity {
+    pub max_restarts: usize,
+    pub window: Duration,
+}
+
+impl Default for RestartIntensity {
+    fn default() -> Self {
+        RestartIntensity {
+            max_restarts: 3,
+            window: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Describes how to create a child.
+/// The factory is called on the first start and on every restart,
+/// it should start the actor and return its service recipient.
+#[derive(Clone)]
+pub struct ChildSpec {
+    key: String,
+    factory: ChildFactory,
+}
+
+impl ChildSpec {
+    pub fn new<F>(key: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn() -> Recipient<ActorServiceMessage> + Send + Sync + 'static,
+    {
+        ChildSpec {
+            key: key.into(),
+            factory: Arc::new(factory),
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+pub struct ActorSupervisor {
+    key: String,
+    strategy: RestartStrategy,
+    intensity: RestartIntensity,
+    check_interval: Duration,
+    specs: Vec<ChildSpec>,
+    children: Vec<Option<Recipient<ActorServiceMessage>>>,
+    restarts: VecDeque<Instant>,
+    watchdog: Option<SpawnHandle>,
+    lifecycle: Lifecycle,
+}
+
+impl ActorSupervisor {
+    pub fn new(key: impl Into<String>, strategy: RestartStrategy) -> Self {
+        let key = key.into();
+        ActorSupervisor {
+            lifecycle: Lifecycle::new(&key),
+            key,
+            strategy,
+            intensity: RestartIntensity::default(),
+            check_interval: Duration::from_millis(100),
+            specs: vec![],
+            children: vec![],
+            restarts: VecDeque::new(),
+            watchdog: None,
+        }
+    }
+
+    pub fn with_intensity(mut self, max_restarts: usize, window: Duration) -> Self {
+        self.intensity = RestartIntensity {
+            max_restarts,
+            window,
+        };
+        self
+    }
+
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// Children are started in the order they are added and stopped in the reverse one.
+    pub fn child(mut self, spec: ChildSpec) -> Self {
+        self.specs.push(spec);
+        self.children.push(None);
+        self
+    }
+
+    fn start_child(&mut self, idx: usize, ctx: &mut Context<Self>) {
+        let spec = &self.specs[idx];
+        log::info!("[{}] Starting child {}", self.key, spec.key);
+        let child = (spec.factory)();
+        self.children[idx] = Some(child.clone());
+
+        ctx.spawn(child.send(ActorServiceMessage::Start).into_actor(self).map(
+            move |res, act, ctx| {
+                let error = match res {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => e,
+                    Err(e) => ActorError::StartupError(e.to_string()),
+                };
+                // the child may have been replaced in the meantime
+                if act.children[idx].as_ref() == Some(&child) {
+                    act.on_failure(idx, error, ctx);
+                }
+            },
+        ));
+    }
+
+    fn stop_child(&mut self, idx: usize) {
+        if let Some(child) = self.children[idx].take() {
+            log::info!("[{}] Stopping child {}", self.key, self.specs[idx].key);
+            child.do_send(ActorServiceMessage::Stop);
+        }
+    }
+
+    fn affected(&self, idx: usize) -> std::ops::Range<usize> {
+        match self.strategy {
+            RestartStrategy::OneForOne => idx..idx + 1,
+            RestartStrategy::OneForAll => 0..self.specs.len(),
+            RestartStrategy::RestForOne => idx..self.specs.len(),
+        }
+    }
+
+    fn intensity_exceeded(&mut self) -> bool {
+        let now = Instant::now();
+        while let Some(first) = self.restarts.front() {
+            if now.duration_since(*first) > self.intensity.window {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.restarts.push_back(now);
+        self.restarts.len() > self.intensity.max_restarts
+    }
+
+    fn on_failure(&mut self, idx: usize, error: ActorError, ctx: &mut Context<Self>) {
+        log::error!(
+            "[{}] Child {} failed: {:?}",
+            self.key,
+            self.specs[idx].key,
+            error
+        );
+
+        if self.intensity_exceeded() {
+            log::error!(
+                "[{}] More than {} restarts in {:?}, giving up",
+                self.key,
+                self.intensity.max_restarts,
+                self.intensity.window
+            );
+            self.lifecycle.fail(format!(
+                "{} failed too often: {}",
+                self.specs[idx].key, error
+            ));
+            self.stop_all(ctx);
+            return;
+        }
+
+        let range = self.affected(idx);
+        for i in range.clone().rev() {
+            self.stop_child(i);
+        }
+        for i in range {
+            self.start_child(i, ctx);
+        }
+    }
+
+    fn stop_all(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.watchdog.take() {
+            ctx.cancel_future(handle);
+        }
+        for i in (0..self.children.len()).rev() {
+            self.stop_child(i);
+        }
+        ctx.stop();
+    }
+
+    fn check_children(&mut self, ctx: &mut Context<Self>) {
+        let dead = self
+            .children
+            .iter()
+            .position(|c| c.as_ref().is_some_and(|c| !c.connected()));
+
+        if let Some(idx) = dead {
+            self.children[idx] = None;
+            self.on_failure(
+                idx,
+                ActorError::RuntimeError("Actor is not alive".to_string()),
+                ctx,
+            );
+        }
+    }
+}
+
+impl Actor for ActorSupervisor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>())
+                .with(ctx.address().recipient::<ChildFailed>()),
+        );
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if self.lifecycle.state() != ActorState::Failed {
+            self.lifecycle.set(ActorState::Stopped);
+        }
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
+}
+
+impl Handler<ActorServiceMessage> for ActorSupervisor {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            ActorServiceMessage::Start => {
+                if self.watchdog.is_some() {
+                    return Err(ActorError::StartupError(format!(
+                        "Supervisor {} is already running",
+                        self.key
+                    )));
+                }
+                log::info!("[{}] Starting {} children", self.key, self.specs.len());
+                self.lifecycle.set(ActorState::Running);
+                for idx in 0..self.specs.len() {
+                    self.start_child(idx, ctx);
+                }
+                self.watchdog =
+                    Some(ctx.run_interval(self.check_interval, |act, ctx| act.check_children(ctx)));
+            }
+            ActorServiceMessage::Stop => {
+                log::info!("[{}] Stopping supervisor", self.key);
+                self.lifecycle.set(ActorState::Stopping);
+                self.stop_all(ctx);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Handler<ActorStatusMessage> for ActorSupervisor {
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
+/// Reports a failure of a child explicitly,
+/// e.g. when the actor is still alive but can not serve anymore.
+#[derive(Debug, Message)]
+#[rtype(result = "ActorResultVoid")]
+pub struct ChildFailed {
+    pub key: String,
+    pub error: ActorError,
+}
+
+impl Handler<ChildFailed> for ActorSupervisor {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: ChildFailed, ctx: &mut Context<Self>) -> Self::Result {
+        let idx = self
+            .specs
+            .iter()
+            .position(|s| s.key == msg.key)
+            .ok_or_else(|| ActorError::RuntimeError(format!("Unknown child {}", msg.key)))?;
+        self.on_failure(idx, msg.error, ctx);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::clock::sleep;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Crash;
+
+    struct Machine {
+        starts: Arc<AtomicUsize>,
+    }
+
+    impl Actor for Machine {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<ActorServiceMessage> for Machine {
+        type Result = ActorResultVoid;
+
+        fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Context<Self>) -> Self::Result {
+            match msg {
+                ActorServiceMessage::Start => {
+                    self.starts.fetch_add(1, Ordering::SeqCst);
+                }
+                ActorServiceMessage::Stop => ctx.stop(),
+            }
+            Ok(())
+        }
+    }
+
+    impl Handler<Crash> for Machine {
+        type Result = ();
+
+        fn handle(&mut self, _msg: Crash, ctx: &mut Context<Self>) {
+            ctx.stop();
+        }
+    }
+
+    fn machine(
+        key: &str,
+        starts: Arc<AtomicUsize>,
+        last: Arc<std::sync::Mutex<Option<actix::Addr<Machine>>>>,
+    ) -> ChildSpec {
+        ChildSpec::new(key, move || {
+            let addr = Machine {
+                starts: starts.clone(),
+            }
+            .start();
+            *last.lock().unwrap() = Some(addr.clone());
+            addr.recipient()
+        })
+    }
+
+    #[actix::test]
+    async fn strategies() {
+        for (strategy, expected) in [
+            (RestartStrategy::OneForOne, [1, 2, 1]),
+            (RestartStrategy::OneForAll, [2, 2, 2]),
+            (RestartStrategy::RestForOne, [1, 2, 2]),
+        ] {
+            let counters: Vec<_> = (0..3).map(|_| Arc::new(AtomicUsize::new(0))).collect();
+            let addrs: Vec<_> = (0..3)
+                .map(|_| Arc::new(std::sync::Mutex::new(None)))
+                .collect();
+
+            let mut supervisor = ActorSupervisor::new("line", strategy);
+            for i in 0..3 {
+                supervisor = supervisor.child(machine(
+                    &format!("machine_{i}"),
+                    counters[i].clone(),
+                    addrs[i].clone(),
+                ));
+            }
+            let supervisor = supervisor.start();
+            supervisor
+                .send(ActorServiceMessage::Start)
+                .await
+                .unwrap()
+                .unwrap();
+            sleep(Duration::from_millis(50)).await;
+
+            let crashed = addrs[1].lock().unwrap().clone().unwrap();
+            crashed.do_send(Crash);
+            sleep(Duration::from_millis(300)).await;
+
+            let starts: Vec<_> = counters.iter().map(|c| c.load(Ordering::SeqCst)).collect();
+            assert_eq!(starts, expected, "{:?}", strategy);
+
+            supervisor.do_send(ActorServiceMessage::Stop);
+        }
+    }
+
+    #[actix::test]
+    async fn gives_up_after_max_restarts() {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let last = Arc::new(std::sync::Mutex::new(None));
+        let supervisor = ActorSupervisor::new("line", RestartStrategy::OneForOne)
+            .with_intensity(2, Duration::from_secs(10))
+            .child(machine("machine", starts.clone(), last.clone()))
+            .start();
+
+        supervisor
+            .send(ActorServiceMessage::Start)
+            .await
+            .unwrap()
+            .unwrap();
+
+        for _ in 0..3 {
+            sleep(Duration::from_millis(50)).await;
+            supervisor
+                .send(ChildFailed {
+                    key: "machine".to_string(),
+                    error: ActorError::RuntimeError("boom".to_string()),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+        assert!(!supervisor.connected());
+    }
+}
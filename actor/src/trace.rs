@@ -0,0 +1,357 @@
+use crate::clock::SimClock;
+use actix::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Display;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// The name of the HTTP header and the AMQP application property carrying the context.
+pub const TRACEPARENT: &str = "traceparent";
+/// The prefix of an SSH exec command carrying the context, e.g. `TRACEPARENT=00-... ls`.
+const SSH_PREFIX: &str = "TRACEPARENT=";
+
+/// Identifies a production order (trace) and the current hop (span) while it travels between actors.
+/// The text form follows the W3C `traceparent` header: `00-<trace id>-<span id>-01`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+}
+
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    RandomState::new().hash_one(COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+impl TraceContext {
+    /// Starts a new trace.
+    pub fn new_root() -> Self {
+        TraceContext {
+            trace_id: format!("{:016x}{:016x}", random_u64(), random_u64()),
+            span_id: format!("{:016x}", random_u64()),
+            parent_span_id: None,
+        }
+    }
+
+    /// The context of the next hop of the same trace.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            trace_id: self.trace_id.clone(),
+            span_id: format!("{:016x}", random_u64()),
+            parent_span_id: Some(self.span_id.clone()),
+        }
+    }
+
+    pub fn to_traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+
+    /// Parses a `traceparent` value, the parsed context is the remote parent of the next hop.
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let (_version, trace_id, span_id, _flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        let is_hex =
+            |s: &str, len: usize| s.len() == len && s.chars().all(|c| c.is_ascii_hexdigit());
+        if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || parts.next().is_some() {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id: trace_id.to_lowercase(),
+            span_id: span_id.to_lowercase(),
+            parent_span_id: None,
+        })
+    }
+
+    /// Continues the incoming trace or starts a new one when there is none.
+    pub fn continue_from(incoming: Option<&str>) -> Self {
+        incoming
+            .and_then(TraceContext::from_traceparent)
+            .map(|parent| parent.child())
+            .unwrap_or_else(TraceContext::new_root)
+    }
+
+    /// Prepends the context to an SSH exec command.
+    pub fn wrap_command(&self, cmd: &str) -> String {
+        format!("{}{} {}", SSH_PREFIX, self.to_traceparent(), cmd)
+    }
+
+    /// Splits an SSH exec command into the carried `traceparent` and the command itself.
+    pub fn split_command(cmd: &str) -> (Option<&str>, &str) {
+        match cmd.strip_prefix(SSH_PREFIX) {
+            Some(rest) => match rest.split_once(' ') {
+                Some((traceparent, cmd)) => (Some(traceparent), cmd),
+                None => (Some(rest), ""),
+            },
+            None => (None, cmd),
+        }
+    }
+
+    /// Records a hop of this context in the global recorder.
+    pub fn record(
+        &self,
+        actor: &str,
+        protocol: Protocol,
+        direction: Direction,
+        detail: impl Into<String>,
+    ) {
+        TraceRecorder::global().record(self, actor, protocol, direction, detail);
+    }
+}
+
+impl Display for TraceContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_traceparent())
+    }
+}
+
+/// A message travelling together with its trace context.
+#[derive(Debug, Clone)]
+pub struct Traced<M> {
+    pub trace: TraceContext,
+    pub message: M,
+}
+
+impl<M> Traced<M> {
+    pub fn new(trace: TraceContext, message: M) -> Self {
+        Traced { trace, message }
+    }
+}
+
+impl<M: Message> Message for Traced<M> {
+    type Result = M::Result;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Protocol {
+    Amqp,
+    Http,
+    Ssh,
+    OpcUa,
+    Sql,
+    Internal,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A recorded hop of a trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub actor: String,
+    pub protocol: Protocol,
+    pub direction: Direction,
+    /// What happened, e.g. the HTTP method and path or the SSH command.
+    pub detail: String,
+    /// The simulation time of the hop.
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct RecorderInner {
+    capacity: usize,
+    spans: VecDeque<Span>,
+    /// The number of spans ever recorded, the dropped and cleared ones included.
+    recorded: u64,
+}
+
+/// Collects the spans of all actors, the oldest spans are dropped when the capacity is reached.
+#[derive(Debug, Clone)]
+pub struct TraceRecorder {
+    inner: Arc<Mutex<RecorderInner>>,
+    clock: SimClock,
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        TraceRecorder::new(100_000)
+    }
+}
+
+impl TraceRecorder {
+    pub fn new(capacity: usize) -> Self {
+        TraceRecorder {
+            inner: Arc::new(Mutex::new(RecorderInner {
+                capacity: capacity.max(1),
+                spans: VecDeque::new(),
+                recorded: 0,
+            })),
+            clock: SimClock::global(),
+        }
+    }
+
+    /// The recorder used by the actors.
+    pub fn global() -> TraceRecorder {
+        static GLOBAL: OnceLock<TraceRecorder> = OnceLock::new();
+        GLOBAL.get_or_init(TraceRecorder::default).clone()
+    }
+
+    /// Stamps the spans with the given clock instead of the global one.
+    pub fn with_clock(mut self, clock: SimClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RecorderInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record(
+        &self,
+        ctx: &TraceContext,
+        actor: &str,
+        protocol: Protocol,
+        direction: Direction,
+        detail: impl Into<String>,
+    ) {
+        let span = Span {
+            trace_id: ctx.trace_id.clone(),
+            span_id: ctx.span_id.clone(),
+            parent_span_id: ctx.parent_span_id.clone(),
+            actor: actor.to_string(),
+            protocol,
+            direction,
+            detail: detail.into(),
+            timestamp: self.clock.now(),
+        };
+        log::debug!(
+            "[{}] {} {} {} {}",
+            span.actor,
+            span.protocol,
+            span.direction,
+            ctx,
+            span.detail
+        );
+        let mut inner = self.lock();
+        if inner.spans.len() == inner.capacity {
+            inner.spans.pop_front();
+        }
+        inner.spans.push_back(span);
+        inner.recorded += 1;
+    }
+
+    /// The sequence number the next span gets, the spans are numbered from 0 in the recording order.
+    pub fn sequence(&self) -> u64 {
+        self.lock().recorded
+    }
+
+    /// The spans still held with a sequence number at or after the given one, with their numbers.
+    pub fn spans_since(&self, sequence: u64) -> Vec<(u64, Span)> {
+        let inner = self.lock();
+        let first = inner.recorded - inner.spans.len() as u64;
+        inner
+            .spans
+            .iter()
+            .zip(first..)
+            .filter(|(_, seq)| *seq >= sequence)
+            .map(|(span, seq)| (seq, span.clone()))
+            .collect()
+    }
+
+    pub fn spans(&self) -> Vec<Span> {
+        self.lock().spans.iter().cloned().collect()
+    }
+
+    /// The spans of one trace in the recording order.
+    pub fn trace(&self, trace_id: &str) -> Vec<Span> {
+        self.lock()
+            .spans
+            .iter()
+            .filter(|s| s.trace_id == trace_id)
+            .cloned()
+            .collect()
+    }
+
+    /// The number of the recorded messages per protocol.
+    pub fn message_counts(&self) -> BTreeMap<String, u64> {
+        let mut counts = BTreeMap::new();
+        for span in self.lock().spans.iter() {
+            *counts.entry(span.protocol.to_string()).or_default() += 1;
+        }
+        counts
+    }
+
+    pub fn clear(&self) {
+        self.lock().spans.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn propagation() {
+        let root = TraceContext::new_root();
+        let header = root.to_traceparent();
+        assert_eq!(
+            TraceContext::from_traceparent(&header).unwrap().span_id,
+            root.span_id
+        );
+        assert!(TraceContext::from_traceparent("00-xyz-1-01").is_none());
+
+        let next = TraceContext::continue_from(Some(&header));
+        assert_eq!(next.trace_id, root.trace_id);
+        assert_eq!(next.parent_span_id.as_ref(), Some(&root.span_id));
+        assert_ne!(TraceContext::continue_from(None).trace_id, root.trace_id);
+
+        let cmd = next.wrap_command("cat recipe.txt");
+        let (traceparent, plain) = TraceContext::split_command(&cmd);
+        assert_eq!(traceparent, Some(next.to_traceparent().as_str()));
+        assert_eq!(plain, "cat recipe.txt");
+        assert_eq!(TraceContext::split_command("ls"), (None, "ls"));
+
+        let recorder = TraceRecorder::new(2);
+        recorder.record(
+            &root,
+            "mes",
+            Protocol::Http,
+            Direction::Inbound,
+            "GET /orders",
+        );
+        recorder.record(&next, "fes", Protocol::Ssh, Direction::Inbound, plain);
+        recorder.record(
+            &TraceContext::new_root(),
+            "other",
+            Protocol::Sql,
+            Direction::Outbound,
+            "",
+        );
+        assert_eq!(recorder.spans().len(), 2);
+        assert_eq!(recorder.sequence(), 3);
+        let since: Vec<_> = recorder
+            .spans_since(2)
+            .into_iter()
+            .map(|(seq, _)| seq)
+            .collect();
+        assert_eq!(since, vec![2]);
+        assert_eq!(recorder.spans_since(0).len(), 2);
+        let trace = recorder.trace(&root.trace_id);
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0].actor, "fes");
+        assert_eq!(recorder.message_counts()["Ssh"], 1);
+    }
+}
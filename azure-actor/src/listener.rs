@@ -1,11 +1,19 @@
-use actix::{Actor, AsyncContext, Context, Handler, Recipient, WrapFuture};
+use actix::{Actor, AsyncContext, Context, Handler, MessageResult, WrapFuture};
+use actor::bus::EventBus;
+use actor::clock::SimClock;
+use actor::fault::{Fault, FaultMessage, Faults};
+use actor::recording::{Exchange, TrafficRecorder};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
+use actor::trace::{Direction, Protocol, TRACEPARENT, TraceContext, Traced};
 use actor::{ActorResultVoid, ActorServiceMessage};
 use fe2o3_amqp::types::messaging::FromBody;
+use fe2o3_amqp::types::primitives::SimpleValue;
 use fe2o3_amqp::{Connection, Receiver, Session};
 use std::time::Duration;
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::Sender as TSender;
-use tokio::time::sleep;
+use utils::gauges::metrics::Metrics;
 
 #[derive(Debug)]
 pub struct AzureTopicListener<AzureMes, ActorMes>
@@ -17,9 +25,14 @@ where
     url: String,
     topic: String,
     subscription: String,
-    processors: Vec<Recipient<ActorMes>>,
+    /// The bus topic the received messages are published to.
+    publish_to: String,
+    bus: EventBus,
     shutdown: Option<TSender<()>>,
-    _phantom: std::marker::PhantomData<AzureMes>,
+    lifecycle: Lifecycle,
+    clock: SimClock,
+    faults: Faults,
+    _phantom: std::marker::PhantomData<(AzureMes, ActorMes)>,
 }
 
 impl<AzureM, ActorM> Default for AzureTopicListener<AzureM, ActorM>
@@ -33,7 +46,6 @@ where
             "amqp://localhost:5672",
             "topic",
             "subscription",
-            vec![],
         )
     }
 }
@@ -43,35 +55,58 @@ where
     ActorM: actix::Message + Send + Clone + Unpin + From<AzureM> + 'static,
     <ActorM as actix::Message>::Result: Send,
 {
-    pub fn new<T: Into<String>>(
-        key: T,
-        url: T,
-        topic: T,
-        subscription: T,
-        processors: Vec<Recipient<ActorM>>,
-    ) -> Self {
+    /// The received messages are published to the topic named after the key on the global bus,
+    /// both as `ActorM` and as `Traced<ActorM>` carrying the incoming `traceparent`.
+    pub fn new<T: Into<String>>(key: T, url: T, topic: T, subscription: T) -> Self {
+        let key = key.into();
         AzureTopicListener {
-            key: key.into(),
+            lifecycle: Lifecycle::new(&key),
+            faults: Faults::new(&key),
+            publish_to: key.clone(),
+            bus: EventBus::global(),
+            key,
             url: url.into(),
             topic: topic.into(),
             subscription: subscription.into(),
-            processors,
             shutdown: None,
+            clock: SimClock::global(),
             _phantom: std::marker::PhantomData,
         }
     }
+
+    pub fn publish_to(mut self, topic: impl Into<String>) -> Self {
+        self.publish_to = topic.into();
+        self
+    }
+
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// Waits for reconnects following the given clock instead of the global one.
+    pub fn with_clock(mut self, clock: SimClock) -> Self {
+        self.faults = self.faults.with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
 }
 
 impl<AzureM, ActorM> Handler<ActorServiceMessage> for AzureTopicListener<AzureM, ActorM>
 where
-    AzureM:
-        for<'de> serde::Deserialize<'de> + Clone + Send + for<'de> FromBody<'de> + Unpin + 'static,
+    AzureM: for<'de> serde::Deserialize<'de>
+        + serde::Serialize
+        + Clone
+        + Send
+        + for<'de> FromBody<'de>
+        + Unpin
+        + 'static,
     ActorM: actix::Message + Send + Clone + Unpin + From<AzureM> + 'static,
     <ActorM as actix::Message>::Result: Send,
 {
     type Result = ActorResultVoid;
 
-    fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: ActorServiceMessage, _ctx: &mut Self::Context) -> Self::Result {
         match msg {
             ActorServiceMessage::Start => {
                 log::info!("Already started, ignoring ...");
@@ -80,7 +115,8 @@ where
                 log::info!("trying stopping actor");
                 if let Some(shutdown) = self.shutdown.take() {
                     log::info!("Stopping actor");
-                    shutdown.send(()).unwrap();
+                    self.lifecycle.set(ActorState::Stopping);
+                    let _ = shutdown.send(());
                 }
             }
         }
@@ -88,21 +124,76 @@ where
     }
 }
 
+impl<AzureM, ActorM> Handler<FaultMessage> for AzureTopicListener<AzureM, ActorM>
+where
+    AzureM: for<'de> serde::Deserialize<'de>
+        + serde::Serialize
+        + Clone
+        + Send
+        + for<'de> FromBody<'de>
+        + Unpin
+        + 'static,
+    ActorM: actix::Message + Send + Clone + Unpin + From<AzureM> + 'static,
+    <ActorM as actix::Message>::Result: Send,
+{
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: FaultMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.faults.handle(msg)
+    }
+}
+
+impl<AzureM, ActorM> Handler<ActorStatusMessage> for AzureTopicListener<AzureM, ActorM>
+where
+    AzureM: for<'de> serde::Deserialize<'de>
+        + serde::Serialize
+        + Clone
+        + Send
+        + for<'de> FromBody<'de>
+        + Unpin
+        + 'static,
+    ActorM: actix::Message + Send + Clone + Unpin + From<AzureM> + 'static,
+    <ActorM as actix::Message>::Result: Send,
+{
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
 impl<AzureM, ActorM> Actor for AzureTopicListener<AzureM, ActorM>
 where
-    AzureM:
-        for<'de> serde::Deserialize<'de> + Clone + Send + for<'de> FromBody<'de> + Unpin + 'static,
+    AzureM: for<'de> serde::Deserialize<'de>
+        + serde::Serialize
+        + Clone
+        + Send
+        + for<'de> FromBody<'de>
+        + Unpin
+        + 'static,
     ActorM: actix::Message + Send + Clone + Unpin + From<AzureM> + 'static,
     <ActorM as actix::Message>::Result: Send,
 {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>())
+                .with(ctx.address().recipient::<FaultMessage>()),
+        );
+        self.lifecycle.set(ActorState::Starting);
         let key = self.key.clone();
         let url = self.url.clone();
         let topic = self.topic.clone();
         let subscription = self.subscription.clone();
-        let processors = self.processors.clone();
+        let outlet = Outlet {
+            bus: self.bus.clone(),
+            publish_to: self.publish_to.clone(),
+            faults: self.faults.clone(),
+        };
+        let lifecycle = self.lifecycle.clone();
+        let clock = self.clock.clone();
         let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
         self.shutdown = Some(shutdown_tx);
         ctx.spawn(
@@ -113,18 +204,24 @@ where
                         url.clone(),
                         topic.clone(),
                         subscription.clone(),
-                        processors.clone(),
+                        outlet.clone(),
+                        lifecycle.clone(),
                     )
                     .await
                     {
-                        Ok(_) => log::info!("Listener disconnected, retrying ..."),
+                        Ok(_) => {
+                            log::info!("Listener disconnected, retrying ...");
+                            lifecycle.degrade("Listener disconnected");
+                        }
                         Err(e) => {
                             log::error!("Error: {}, retrying in 5s...", e);
-                            sleep(Duration::from_secs(5)).await;
+                            lifecycle.degrade(&e);
+                            clock.sleep(Duration::from_secs(5)).await;
                         }
                     }
                     if shutdown_rx.try_recv().is_ok() {
                         log::info!("Shutting down...");
+                        lifecycle.set(ActorState::Stopped);
                         break;
                     }
                 }
@@ -132,6 +229,48 @@ where
             .into_actor(self),
         );
     }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.lifecycle.set(ActorState::Stopped);
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
+}
+
+/// Where the received messages go, with the faults applied on the way.
+#[derive(Debug, Clone)]
+struct Outlet {
+    bus: EventBus,
+    publish_to: String,
+    faults: Faults,
+}
+
+impl Outlet {
+    async fn publish<M>(&self, key: &str, message: M, trace: TraceContext)
+    where
+        M: actix::Message + Send + Clone + 'static,
+        M::Result: Send,
+    {
+        let drawn = self.faults.draw();
+        if drawn.contains(&Fault::Drop) {
+            log::info!("[{}] Drop the message", key);
+            return;
+        }
+        self.faults.delay(&drawn).await;
+        let copies = if drawn.contains(&Fault::Duplicate) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            self.bus.publish(&self.publish_to, message.clone()).await;
+            self.bus
+                .publish(
+                    &self.publish_to,
+                    Traced::new(trace.clone(), message.clone()),
+                )
+                .await;
+        }
+    }
 }
 
 async fn start_listener<AzureM, ActorM>(
@@ -139,15 +278,17 @@ async fn start_listener<AzureM, ActorM>(
     url: String,
     topic: String,
     sub: String,
-    processors: Vec<Recipient<ActorM>>,
+    outlet: Outlet,
+    lifecycle: Lifecycle,
 ) -> Result<(), Box<dyn std::error::Error>>
 where
-    AzureM: for<'de> serde::Deserialize<'de> + Clone + Send + for<'de> FromBody<'de>,
+    AzureM:
+        for<'de> serde::Deserialize<'de> + serde::Serialize + Clone + Send + for<'de> FromBody<'de>,
     ActorM: actix::Message + Send + Clone + Unpin + From<AzureM> + 'static,
     <ActorM as actix::Message>::Result: Send,
 {
     let mut connection = Connection::builder()
-        .container_id(key)
+        .container_id(key.clone())
         .open(url.as_str())
         .await?;
 
@@ -161,18 +302,41 @@ where
     .await?;
 
     log::info!("Listening for messages...");
+    lifecycle.set(ActorState::Running);
 
     loop {
         match receiver.recv::<AzureM>().await {
             Ok(delivery) => {
                 receiver.accept(&delivery).await?;
-                let actor_mes: ActorM = delivery.into_body().into();
-                for processor in &processors {
-                    processor.do_send(actor_mes.clone());
+                let incoming = delivery
+                    .message()
+                    .application_properties
+                    .as_ref()
+                    .and_then(|p| p.get(TRACEPARENT))
+                    .and_then(|v| match v {
+                        SimpleValue::String(s) => Some(s.as_str()),
+                        _ => None,
+                    });
+                let trace = TraceContext::continue_from(incoming);
+                trace.record(&key, Protocol::Amqp, Direction::Inbound, &topic);
+                Metrics::global().counter(&key, "amqp_received").inc();
+                let body = delivery.into_body();
+                let recorder = TrafficRecorder::global();
+                if recorder.is_recording() {
+                    recorder.record(
+                        &key,
+                        Direction::Inbound,
+                        Exchange::Amqp {
+                            topic: topic.clone(),
+                            body: serde_json::to_value(&body).unwrap_or_default(),
+                        },
+                    );
                 }
+                outlet.publish::<ActorM>(&key, body.into(), trace).await;
             }
             Err(e) => {
                 eprintln!("Receive error: {}", e);
+                Metrics::global().counter(&key, "amqp_receive_errors").inc();
                 break;
             }
         }
@@ -180,3 +344,70 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Outlet;
+    use actix::{Actor, Context, Handler, Message};
+    use actor::bus::{EventBus, SubscribeOptions};
+    use actor::fault::{Fault, FaultInjection, FaultMessage, Faults};
+    use actor::trace::TraceContext;
+    use std::time::Duration;
+    use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+    use tokio::time::timeout;
+
+    #[derive(Debug, Message, Clone, PartialEq)]
+    #[rtype(result = "()")]
+    struct Order(u32);
+
+    struct Collector(UnboundedSender<Order>);
+
+    impl Actor for Collector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Order> for Collector {
+        type Result = ();
+
+        fn handle(&mut self, msg: Order, _ctx: &mut Self::Context) -> Self::Result {
+            let _ = self.0.send(msg);
+        }
+    }
+
+    #[actix::test]
+    async fn drop_and_duplicate() {
+        let bus = EventBus::default();
+        let (tx, mut orders) = unbounded_channel();
+        bus.subscribe(
+            "azure/orders",
+            Collector(tx).start().recipient(),
+            SubscribeOptions::default(),
+        );
+        let outlet = Outlet {
+            bus,
+            publish_to: "azure/orders".to_string(),
+            faults: Faults::new("listener"),
+        };
+        let inject = |fault| FaultMessage::Inject(FaultInjection::new(fault));
+
+        outlet.faults.handle(inject(Fault::Drop)).unwrap();
+        outlet
+            .publish("listener", Order(1), TraceContext::new_root())
+            .await;
+        outlet.faults.handle(FaultMessage::Clear).unwrap();
+        outlet.faults.handle(inject(Fault::Duplicate)).unwrap();
+        outlet
+            .publish("listener", Order(2), TraceContext::new_root())
+            .await;
+        outlet.faults.handle(FaultMessage::Clear).unwrap();
+        outlet
+            .publish("listener", Order(3), TraceContext::new_root())
+            .await;
+
+        // the dropped order never arrives, the duplicated one twice
+        for expected in [2, 2, 3] {
+            let order = timeout(Duration::from_secs(1), orders.recv()).await;
+            assert_eq!(order.unwrap(), Some(Order(expected)));
+        }
+    }
+}
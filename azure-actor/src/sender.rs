@@ -1,15 +1,23 @@
 use actix::{
-    Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, Message as ActixMessage,
-    Message, ResponseActFuture, WrapFuture,
+    Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, Message, MessageResult,
+    WrapFuture,
 };
+use actor::fault::{Fault, FaultMessage, Faults};
+use actor::recording::{Exchange, TrafficRecorder};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
+use actor::trace::{Direction, Protocol, TRACEPARENT, TraceContext, Traced};
 use actor::{ActorError, ActorResultVoid, ActorServiceMessage};
 use fe2o3_amqp::connection::ConnectionHandle;
 use fe2o3_amqp::session::SessionHandle;
-use fe2o3_amqp::types::messaging::{Body, IntoBody, Outcome};
+use fe2o3_amqp::types::messaging::{
+    ApplicationProperties, IntoBody, Message as AmqpMessage, Outcome,
+};
+use fe2o3_amqp::types::primitives::SimpleValue;
 use fe2o3_amqp::{Connection, Sender, Session};
-use log::log;
 use std::collections::VecDeque;
 use std::fmt::Debug;
+use utils::gauges::metrics::Metrics;
 
 #[derive(Debug)]
 pub struct AzureTopicSender<AzureM>
@@ -22,18 +30,23 @@ where
     connection: Option<ConnectionHandle<()>>,
     session: Option<SessionHandle<()>>,
     sender: Option<Sender>,
-    message_queue: VecDeque<AzureM>,
+    message_queue: VecDeque<(AzureM, Option<TraceContext>)>,
     is_processing: bool,
+    lifecycle: Lifecycle,
+    faults: Faults,
     _phantom: std::marker::PhantomData<AzureM>,
 }
 
 impl<AzureM> AzureTopicSender<AzureM>
 where
-    AzureM: IntoBody + Send + 'static + Clone,
+    AzureM: IntoBody + serde::Serialize + Send + Unpin + Clone + 'static,
 {
     pub fn new<T: Into<String>>(key: T, url: T, topic: T) -> Self {
+        let key = key.into();
         AzureTopicSender {
-            key: key.into(),
+            lifecycle: Lifecycle::new(&key),
+            faults: Faults::new(&key),
+            key,
             url: url.into(),
             topic: topic.into(),
             connection: None,
@@ -45,74 +58,100 @@ where
         }
     }
 
-    async fn establish_connection(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        log::info!(
-            "[{}] Establishing AMQP connection to {}",
-            self.key,
-            self.url
-        );
-
-        let mut connection = Connection::builder()
-            .container_id(&format!("{}", self.key))
-            .open(self.url.as_str())
-            .await?;
-
-        let mut session = Session::begin(&mut connection).await?;
-
-        let sender = Sender::attach(
-            &mut session,
-            &format!("{}-sender-link", self.key),
-            &self.topic,
-        )
-        .await?;
-
-        self.connection = Some(connection);
-        self.session = Some(session);
-        self.sender = Some(sender);
-
-        log::info!("[{}] Connected to topic: {}", self.key, self.topic);
-        Ok(())
-    }
-
-    async fn reconnect(&mut self) -> Result<(), String> {
-        log::info!("[{}] Attempting to reconnect...", self.key);
-
-        self.connection = None;
-        self.session = None;
-        self.sender = None;
-
-        self.establish_connection()
-            .await
-            .map_err(|e| format!("Reconnection failed: {}", e))
-    }
-
     fn is_connected(&self) -> bool {
-        self.connection.is_some() && self.session.is_some() && self.sender.is_some()
+        self.connection.is_some()
+            && self.session.is_some()
+            && (self.sender.is_some() || self.is_processing)
     }
 
-    async fn try_send(key: String, message: AzureM, mut sender: Sender) -> Result<(), String> {
-        let outcome: Outcome = sender
-            .send(message)
-            .await
-            .map_err(|e| format!("Send failed: {}", e))?;
-
-        outcome
-            .accepted_or_else(|state| state)
-            .map_err(|e| format!("Message not accepted: {:?}", e))?;
+    async fn try_send(
+        key: String,
+        message: AzureM,
+        trace: Option<TraceContext>,
+        mut sender: Sender,
+    ) -> (Result<(), String>, Sender) {
+        let mut message: AmqpMessage<AzureM::Body> = message.into();
+        if let Some(trace) = trace {
+            let mut properties = ApplicationProperties::default();
+            properties.insert(
+                TRACEPARENT.to_string(),
+                SimpleValue::String(trace.to_traceparent()),
+            );
+            message.application_properties = Some(properties);
+        }
+        let result = async {
+            let outcome: Outcome = sender
+                .send(message)
+                .await
+                .map_err(|e| format!("Send failed: {}", e))?;
+
+            outcome
+                .accepted_or_else(|state| state)
+                .map_err(|e| format!("Message not accepted: {:?}", e))?;
+
+            log::info!("[{}] Message sent successfully", key);
+            Ok(())
+        }
+        .await;
+        (result, sender)
+    }
 
-        log::info!("[{}] Message sent successfully", key);
-        Ok(())
+    /// Sends the queued messages one by one, since the link can serve only one delivery at a time.
+    fn process_queue(&mut self, ctx: &mut Context<Self>) {
+        if self.is_processing {
+            return;
+        }
+        let Some(sender) = self.sender.take() else {
+            return;
+        };
+        let Some((message, trace)) = self.message_queue.pop_front() else {
+            self.sender = Some(sender);
+            return;
+        };
+        self.is_processing = true;
+        let key = self.key.clone();
+        ctx.spawn(
+            Self::try_send(key, message, trace, sender)
+                .into_actor(self)
+                .map(|(result, sender), actor, ctx| {
+                    actor.is_processing = false;
+                    actor.sender = Some(sender);
+                    let metrics = Metrics::global();
+                    match result {
+                        Ok(()) => {
+                            metrics.counter(&actor.key, "amqp_sent").inc();
+                            if actor.lifecycle.state() == ActorState::Degraded {
+                                actor.lifecycle.set(ActorState::Running);
+                            }
+                        }
+                        Err(e) => {
+                            log::error!("[{}] {}", actor.key, e);
+                            metrics.counter(&actor.key, "amqp_send_errors").inc();
+                            actor.lifecycle.degrade(e);
+                        }
+                    }
+                    actor.process_queue(ctx);
+                }),
+        );
     }
 }
 
 impl<AzureM> Actor for AzureTopicSender<AzureM>
 where
-    AzureM: IntoBody + Send + Unpin + Clone + 'static,
+    AzureM: IntoBody + serde::Serialize + Send + Unpin + Clone + 'static,
 {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
         log::info!("[{}] Azure sender actor started", self.key);
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>())
+                .with(ctx.address().recipient::<SendMessage<AzureM>>())
+                .with(ctx.address().recipient::<Traced<SendMessage<AzureM>>>())
+                .with(ctx.address().recipient::<FaultMessage>()),
+        );
+        self.lifecycle.set(ActorState::Starting);
 
         // Establish connection on startup
         let key = self.key.clone();
@@ -124,7 +163,7 @@ where
                 log::info!("[{}] Establishing AMQP connection to {}", key, url);
 
                 let mut connection = Connection::builder()
-                    .container_id(&format!("{}", key))
+                    .container_id(&key)
                     .open(url.as_str())
                     .await
                     .map_err(|e| format!("Connection failed: {}", e))?;
@@ -149,6 +188,7 @@ where
                             actor.connection = Some(connection);
                             actor.session = Some(session);
                             actor.sender = Some(sender);
+                            actor.lifecycle.set(ActorState::Running);
                             log::info!(
                                 "[{}] Initial connection established successfully",
                                 actor.key
@@ -160,6 +200,7 @@ where
                                 actor.key,
                                 e
                             );
+                            actor.lifecycle.fail(e);
                             // Could implement retry logic here
                         }
                     }
@@ -181,11 +222,18 @@ where
         log::info!("[{}] Azure sender actor stopped", self.key);
         actix::Running::Stop
     }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if self.lifecycle.state() != ActorState::Failed {
+            self.lifecycle.set(ActorState::Stopped);
+        }
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
 }
 
 impl<AzureM> Handler<ActorServiceMessage> for AzureTopicSender<AzureM>
 where
-    AzureM: IntoBody + Send + Unpin + Clone + 'static,
+    AzureM: IntoBody + serde::Serialize + Send + Unpin + Clone + 'static,
 {
     type Result = ActorResultVoid;
 
@@ -196,6 +244,7 @@ where
             }
             ActorServiceMessage::Stop => {
                 log::info!("[{}] Stop message received", self.key);
+                self.lifecycle.set(ActorState::Stopping);
                 ctx.stop();
             }
         }
@@ -209,27 +258,200 @@ pub struct SendMessage<AzureM>(pub AzureM);
 
 impl<AzureM> Handler<SendMessage<AzureM>> for AzureTopicSender<AzureM>
 where
-    AzureM: IntoBody + Send + Unpin + Clone + 'static,
+    AzureM: IntoBody + serde::Serialize + Send + Unpin + Clone + 'static,
 {
     type Result = ActorResultVoid;
 
     fn handle(&mut self, msg: SendMessage<AzureM>, ctx: &mut Self::Context) -> Self::Result {
+        self.enqueue(msg.0, None, ctx)
+    }
+}
+
+/// Sends the message with the trace context in the `traceparent` application property.
+impl<AzureM> Handler<Traced<SendMessage<AzureM>>> for AzureTopicSender<AzureM>
+where
+    AzureM: IntoBody + serde::Serialize + Send + Unpin + Clone + 'static,
+{
+    type Result = ActorResultVoid;
+
+    fn handle(
+        &mut self,
+        msg: Traced<SendMessage<AzureM>>,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let trace = msg.trace.child();
+        trace.record(&self.key, Protocol::Amqp, Direction::Outbound, &self.topic);
+        self.enqueue(msg.message.0, Some(trace), ctx)
+    }
+}
+
+impl<AzureM> AzureTopicSender<AzureM>
+where
+    AzureM: IntoBody + serde::Serialize + Send + Unpin + Clone + 'static,
+{
+    fn enqueue(
+        &mut self,
+        message: AzureM,
+        trace: Option<TraceContext>,
+        ctx: &mut Context<Self>,
+    ) -> ActorResultVoid {
         if !self.is_connected() {
             return Err(ActorError::RuntimeError(
                 "Connection not established".to_string(),
             ));
         }
-        let sender = self.sender.take().unwrap();
-        let key = self.key.clone();
-        let _ = Box::pin(
+        let recorder = TrafficRecorder::global();
+        if recorder.is_recording() {
+            recorder.record(
+                &self.key,
+                Direction::Outbound,
+                Exchange::Amqp {
+                    topic: self.topic.clone(),
+                    body: serde_json::to_value(&message).unwrap_or_default(),
+                },
+            );
+        }
+        let drawn = self.faults.draw();
+        let messages = self.deliveries(message, trace, &drawn);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        if drawn.iter().any(|f| matches!(f, Fault::DelayMs(_))) {
+            let faults = self.faults.clone();
             ctx.spawn(
-                async move {
-                    let _ = Self::try_send(key.clone(), msg.0.clone(), sender).await;
-                }
-                .into_actor(self),
-            ),
-        );
+                async move { faults.delay(&drawn).await }
+                    .into_actor(self)
+                    .map(|_, actor, ctx| {
+                        actor.message_queue.extend(messages);
+                        actor.process_queue(ctx);
+                    }),
+            );
+        } else {
+            self.message_queue.extend(messages);
+            self.process_queue(ctx);
+        }
 
         Ok(())
     }
+
+    /// The copies of the message to queue with the drawn faults applied, none when it is dropped.
+    fn deliveries(
+        &self,
+        message: AzureM,
+        trace: Option<TraceContext>,
+        drawn: &[Fault],
+    ) -> Vec<(AzureM, Option<TraceContext>)> {
+        if drawn.contains(&Fault::Drop) {
+            log::info!("[{}] Drop the message", self.key);
+            return vec![];
+        }
+        let mut messages = vec![(message, trace)];
+        if drawn.contains(&Fault::Duplicate) {
+            log::info!("[{}] Duplicate the message", self.key);
+            messages.push(messages[0].clone());
+        }
+        messages
+    }
+}
+
+impl<AzureM> Handler<FaultMessage> for AzureTopicSender<AzureM>
+where
+    AzureM: IntoBody + serde::Serialize + Send + Unpin + Clone + 'static,
+{
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: FaultMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.faults.handle(msg)
+    }
+}
+
+impl<AzureM> Handler<ActorStatusMessage> for AzureTopicSender<AzureM>
+where
+    AzureM: IntoBody + serde::Serialize + Send + Unpin + Clone + 'static,
+{
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AzureTopicSender, SendMessage};
+    use actix::Actor;
+    use actor::fault::{Fault, FaultInjection, FaultMessage};
+    use actor::status::{ActorState, wait_for_state};
+    use fe2o3_amqp::acceptor::{ConnectionAcceptor, LinkAcceptor, LinkEndpoint, SessionAcceptor};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+    use tokio::time::timeout;
+
+    /// A broker accepting a single sender link, the bodies it receives and its url.
+    async fn broker() -> (String, UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("amqp://{}", listener.local_addr().unwrap());
+        let (tx, bodies) = unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = ConnectionAcceptor::new("broker")
+                .accept(stream)
+                .await
+                .unwrap();
+            let mut session = SessionAcceptor::new()
+                .accept(&mut connection)
+                .await
+                .unwrap();
+            let Ok(LinkEndpoint::Receiver(mut receiver)) =
+                LinkAcceptor::new().accept(&mut session).await
+            else {
+                return;
+            };
+            while let Ok(delivery) = receiver.recv::<String>().await {
+                let _ = receiver.accept(&delivery).await;
+                let _ = tx.send(delivery.into_body());
+            }
+        });
+        (url, bodies)
+    }
+
+    #[actix::test]
+    async fn drop_and_duplicate() {
+        let (url, mut bodies) = broker().await;
+        let sender =
+            AzureTopicSender::<String>::new("faulty-sender", url.as_str(), "orders").start();
+        wait_for_state(
+            &sender.clone().recipient(),
+            ActorState::Running,
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+        let inject = |fault| FaultMessage::Inject(FaultInjection::new(fault));
+        let send = |order: &str| sender.send(SendMessage(order.to_string()));
+
+        sender.send(inject(Fault::Drop)).await.unwrap().unwrap();
+        send("1").await.unwrap().unwrap();
+        sender.send(FaultMessage::Clear).await.unwrap().unwrap();
+        sender
+            .send(inject(Fault::Duplicate))
+            .await
+            .unwrap()
+            .unwrap();
+        send("2").await.unwrap().unwrap();
+        sender.send(FaultMessage::Clear).await.unwrap().unwrap();
+        send("3").await.unwrap().unwrap();
+
+        // the dropped order never reaches the broker, the duplicated one twice
+        for expected in ["2", "2", "3"] {
+            let body = timeout(Duration::from_secs(1), bodies.recv()).await;
+            assert_eq!(body.unwrap().as_deref(), Some(expected));
+        }
+        assert!(
+            timeout(Duration::from_millis(200), bodies.recv())
+                .await
+                .is_err()
+        );
+    }
 }
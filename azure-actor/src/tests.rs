@@ -1,8 +1,8 @@
 use crate::listener::AzureTopicListener;
 use crate::sender::{AzureTopicSender, SendMessage};
 use actix::{Actor, Message};
-use actor::{ActorError, ActorResultVoid, ActorServiceMessage, EchoActor};
-use fe2o3_amqp::{Connection, Sender, Session};
+use actor::bus::{EventBus, SubscribeOptions};
+use actor::{ActorResultVoid, ActorServiceMessage, EchoActor};
 use std::fmt::Display;
 use std::time::Duration;
 use tokio::time::sleep;
@@ -36,12 +36,13 @@ async fn azure_smoke() -> ActorResultVoid {
         .send(SendMessage("Hello World".to_string()))
         .await;
 
-    let l: AzureTopicListener<String, M> = AzureTopicListener::new(
-        "Test",
-        "amqp://localhost:5672",
-        "test-topic",
-        "test-sub",
-        vec![EchoActor.start().recipient()],
+    let l: AzureTopicListener<String, M> =
+        AzureTopicListener::new("Test", "amqp://localhost:5672", "test-topic", "test-sub")
+            .publish_to("azure/test-topic");
+    EventBus::global().subscribe::<M>(
+        "azure/#",
+        EchoActor.start().recipient(),
+        SubscribeOptions::default(),
     );
 
     let actor = l.start();
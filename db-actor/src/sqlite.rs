@@ -1,14 +1,63 @@
-use actix::{Actor, ActorContext, AsyncContext, Context, Handler, Recipient, WrapFuture};
-use actor::{ActorError, ActorResultVoid, ActorServiceMessage};
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, MessageResult, WrapFuture};
+use actor::bus::EventBus;
+use actor::clock::{self, SimClock};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
+use actor::trace::{Direction, Protocol, TraceContext, Traced};
+use actor::{ActorResultVoid, ActorServiceMessage};
 use sqlx::query::Query;
 use sqlx::sqlite::SqliteRow;
-use sqlx::{Database, Error, Executor, Sqlite};
-use std::ops::Index;
-use std::time::Duration;
+use sqlx::{Database, Sqlite};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use utils::gauges::metrics::Metrics;
+
+type Rows = Pin<Box<dyn Future<Output = Result<Vec<SqliteRow>, sqlx::Error>>>>;
+
+/// The query run on every poll, built by a closure or given as its sql text.
+pub trait PollQuery: Unpin + 'static {
+    fn fetch_all(&self, pool: sqlx::Pool<Sqlite>) -> Rows;
+}
+
+impl<F> PollQuery for F
+where
+    F: Fn() -> Query<'static, Sqlite, <Sqlite as Database>::Arguments<'static>> + Unpin + 'static,
+{
+    fn fetch_all(&self, pool: sqlx::Pool<Sqlite>) -> Rows {
+        let query = self();
+        Box::pin(async move { query.fetch_all(&pool).await })
+    }
+}
+
+/// The sql text of a query known only at runtime, e.g. read from a topology file.
+#[derive(Debug, Clone)]
+pub struct Sql(Arc<str>);
+
+impl From<&str> for Sql {
+    fn from(sql: &str) -> Self {
+        Sql(sql.into())
+    }
+}
+
+impl From<String> for Sql {
+    fn from(sql: String) -> Self {
+        Sql(sql.into())
+    }
+}
+
+impl PollQuery for Sql {
+    fn fetch_all(&self, pool: sqlx::Pool<Sqlite>) -> Rows {
+        let sql = self.0.clone();
+        Box::pin(async move { sqlx::query(&sql).fetch_all(&pool).await })
+    }
+}
+
 #[derive(Clone)]
 pub struct SqLiteQueryActor<T, Q>
 where
-    Q: Fn() -> Query<'static, Sqlite, <Sqlite as Database>::Arguments<'static>>,
+    Q: PollQuery,
     T: actix::Message + Send + Clone + Unpin + 'static,
     <T as actix::Message>::Result: Send,
 {
@@ -16,32 +65,54 @@ where
     pool: sqlx::Pool<Sqlite>,
     duration: Duration,
     query: Q,
-    subscribers: Vec<Recipient<T>>,
+    topic: String,
+    bus: EventBus,
+    lifecycle: Lifecycle,
+    clock: SimClock,
+    _phantom: std::marker::PhantomData<T>,
 }
 
 impl<Q, T> SqLiteQueryActor<T, Q>
 where
-    Q: Fn() -> Query<'static, Sqlite, <Sqlite as Database>::Arguments<'static>>,
+    Q: PollQuery,
     T: actix::Message + Send + Clone + Unpin + 'static,
     <T as actix::Message>::Result: Send,
 {
+    /// The query results are published to the topic named after the key on the global bus,
+    /// both as `T` and as `Traced<T>` starting a new trace.
     pub fn new(key: String, query: Q, duration: Duration, pool: sqlx::Pool<Sqlite>) -> Self {
         Self {
+            lifecycle: Lifecycle::new(&key),
+            topic: key.clone(),
             key,
             pool,
             duration,
             query,
-            subscribers: vec![],
+            bus: EventBus::global(),
+            clock: SimClock::global(),
+            _phantom: std::marker::PhantomData,
         }
     }
 
-    pub fn subscribe(&mut self, recipient: Recipient<T>) {
-        self.subscribers.push(recipient);
+    pub fn publish_to(mut self, topic: impl Into<String>) -> Self {
+        self.topic = topic.into();
+        self
+    }
+
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// Polls following the given clock instead of the global one.
+    pub fn with_clock(mut self, clock: SimClock) -> Self {
+        self.clock = clock;
+        self
     }
 }
 impl<Q, T> Handler<ActorServiceMessage> for SqLiteQueryActor<T, Q>
 where
-    Q: Fn() -> Query<'static, Sqlite, <Sqlite as Database>::Arguments<'static>> + Unpin + 'static,
+    Q: PollQuery,
     T: From<Vec<SqliteRow>> + actix::Message + Send + Clone + Unpin + 'static,
     <T as actix::Message>::Result: Send,
 {
@@ -53,6 +124,7 @@ where
                 log::info!("[{}] The actor starts during startup.", self.key);
             }
             ActorServiceMessage::Stop => {
+                self.lifecycle.set(ActorState::Stopping);
                 ctx.stop();
             }
         }
@@ -61,30 +133,64 @@ where
     }
 }
 
+impl<Q, T> Handler<ActorStatusMessage> for SqLiteQueryActor<T, Q>
+where
+    Q: PollQuery,
+    T: From<Vec<SqliteRow>> + actix::Message + Send + Clone + Unpin + 'static,
+    <T as actix::Message>::Result: Send,
+{
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
 impl<Q, T> Actor for SqLiteQueryActor<T, Q>
 where
-    Q: Fn() -> Query<'static, Sqlite, <Sqlite as Database>::Arguments<'static>> + Unpin + 'static,
+    Q: PollQuery,
     T: From<Vec<SqliteRow>> + actix::Message + Send + Clone + Unpin + 'static,
     <T as actix::Message>::Result: Send,
 {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(self.duration, |act, ctx| {
-            let pool = act.pool.clone();
-            let query = (act.query)();
-            let subscribers = act.subscribers.clone();
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>()),
+        );
+        self.lifecycle.set(ActorState::Running);
+        clock::run_interval(&self.clock, ctx, self.duration, |act, ctx| {
+            let rows = act.query.fetch_all(act.pool.clone());
+            let bus = act.bus.clone();
+            let topic = act.topic.clone();
+            let key = act.key.clone();
+            let lifecycle = act.lifecycle.clone();
+            let started = Instant::now();
             ctx.spawn(
                 async move {
-                    match query.fetch_all(&pool).await {
+                    let result = rows.await;
+                    let metrics = Metrics::global();
+                    metrics.counter(&key, "sql_polls").inc();
+                    metrics
+                        .histogram(&key, "sql_query_latency_us")
+                        .record_duration(started.elapsed());
+                    match result {
                         Ok(r) => {
-                            let message: T = r.into();
-                            for sub in &subscribers {
-                                sub.do_send(message.clone());
+                            if lifecycle.state() == ActorState::Degraded {
+                                lifecycle.set(ActorState::Running);
                             }
+                            // every poll starts a new trace, e.g. a production order read from the MES
+                            let trace = TraceContext::new_root();
+                            trace.record(&key, Protocol::Sql, Direction::Outbound, &topic);
+                            let message: T = r.into();
+                            bus.publish(&topic, message.clone()).await;
+                            bus.publish(&topic, Traced::new(trace, message)).await;
                         }
                         Err(e) => {
                             log::error!("SqLiteQueryWorker error: {:?}", e);
+                            metrics.counter(&key, "sql_errors").inc();
+                            lifecycle.degrade(e);
                         }
                     }
                 }
@@ -92,4 +198,9 @@ where
             );
         });
     }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.lifecycle.set(ActorState::Stopped);
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
 }
@@ -1,15 +1,18 @@
 use crate::error::SqlResult;
 use crate::sqlite::SqLiteQueryActor;
-use actix::{Actor, AsyncContext, Context, Handler, Message, WrapFuture};
+use actix::{Actor, Context, Handler, Message};
+use actor::bus::{EventBus, SubscribeOptions};
+use actor::clock::SimClock;
+use actor::status::{ActorState, wait_for_state};
 use sqlx::sqlite::SqliteRow;
 use sqlx::types::chrono;
-use sqlx::{Row, Sqlite, SqlitePool};
+use sqlx::{Row, SqlitePool};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
+use utils::gauges::metrics::Metrics;
 use utils::logger_on;
 
-#[derive(Clone)]
-struct DataQuery;
-
 #[derive(Debug, Message, Clone)]
 #[rtype(result = "()")]
 struct DataQueryResult(Vec<String>);
@@ -32,7 +35,7 @@ impl From<Vec<SqliteRow>> for DataQueryResult {
     }
 }
 
-struct DataQueryReceiver(usize);
+struct DataQueryReceiver(Arc<AtomicUsize>);
 
 impl Actor for DataQueryReceiver {
     type Context = Context<Self>;
@@ -41,9 +44,9 @@ impl Actor for DataQueryReceiver {
 impl Handler<DataQueryResult> for DataQueryReceiver {
     type Result = ();
 
-    fn handle(&mut self, msg: DataQueryResult, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: DataQueryResult, _ctx: &mut Self::Context) -> Self::Result {
         log::info!("{:?}", msg.0);
-        self.0 += 1;
+        self.0.fetch_add(1, Ordering::SeqCst);
     }
 }
 
@@ -52,6 +55,8 @@ async fn sqlite_smoke() -> SqlResult<()> {
     logger_on();
     let database_url = "sqlite::memory:";
     let pool = SqlitePool::connect(database_url).await?;
+    let clock = SimClock::default();
+    clock.pause();
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS tasks (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -69,23 +74,52 @@ async fn sqlite_smoke() -> SqlResult<()> {
         .execute(&pool)
         .await?;
 
-    let mut actor: SqLiteQueryActor<DataQueryResult, _> = SqLiteQueryActor::new(
+    let bus = EventBus::default();
+    let received = Arc::new(AtomicUsize::new(0));
+    bus.subscribe(
+        "tasks/#",
+        DataQueryReceiver(received.clone()).start().recipient(),
+        SubscribeOptions::default(),
+    );
+
+    let actor: SqLiteQueryActor<DataQueryResult, _> = SqLiteQueryActor::new(
         "TaskQuerySqliteWorker".to_string(),
         || sqlx::query("SELECT * FROM tasks"),
         Duration::from_secs(1),
         pool.clone(),
-    );
+    )
+    .with_clock(clock.clone())
+    .with_bus(bus)
+    .publish_to("tasks/all");
+
+    let actor = actor.start();
+    let status = wait_for_state(
+        &actor.recipient(),
+        ActorState::Running,
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+    assert!(status.last_error.is_none());
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(received.load(Ordering::SeqCst), 0);
 
-    actor.subscribe(DataQueryReceiver(0).start().recipient());
-    actor.start();
+    // the two polls due by then are run once
+    clock.advance(Duration::from_secs(2));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(received.load(Ordering::SeqCst), 1);
 
-    tokio::time::sleep(Duration::from_secs(2)).await;
     sqlx::query("INSERT INTO tasks (description, completed) VALUES (?, ?)")
         .bind("Sample Task2")
         .bind(false)
         .execute(&pool)
         .await?;
-    tokio::time::sleep(Duration::from_secs(2)).await;
+    clock.advance(Duration::from_secs(1));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(received.load(Ordering::SeqCst), 2);
+    let polls = Metrics::global().counter("TaskQuerySqliteWorker", "sql_polls");
+    assert_eq!(polls.get(), 2);
 
     Ok(())
 }
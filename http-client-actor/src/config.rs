@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The credentials sent with every request of a client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Auth {
+    /// `Authorization: Bearer <token>`.
+    Bearer {
+        token: String,
+    },
+    Basic {
+        user: String,
+        password: String,
+    },
+    /// Any other header, e.g. an API key.
+    Header {
+        name: String,
+        value: String,
+    },
+}
+
+impl Auth {
+    pub fn bearer(token: impl Into<String>) -> Self {
+        Auth::Bearer {
+            token: token.into(),
+        }
+    }
+
+    pub fn basic(user: impl Into<String>, password: impl Into<String>) -> Self {
+        Auth::Basic {
+            user: user.into(),
+            password: password.into(),
+        }
+    }
+
+    pub fn header(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Auth::Header {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    pub(crate) fn apply(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            Auth::Bearer { token } => request.bearer_auth(token),
+            Auth::Basic { user, password } => request.basic_auth(user, Some(password)),
+            Auth::Header { name, value } => request.header(name, value),
+        }
+    }
+}
+
+/// How the failed attempts are retried, waiting longer after every attempt.
+/// The connection errors and `429 Too Many Requests` are always retried, the server may not
+/// have processed the request. The timeouts and the server errors are only retried for
+/// the idempotent requests, a POST the server processed but answered slowly is not sent twice.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// The attempts after the first one, 0 never retries.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// The factor applied to the wait after every attempt.
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    100
+}
+
+fn default_max_backoff_ms() -> u64 {
+    5000
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: default_max_retries(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            multiplier: default_multiplier(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Every request is attempted once.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff_ms = initial.as_millis() as u64;
+        self.max_backoff_ms = max.as_millis() as u64;
+        self
+    }
+
+    /// The wait before the retry following the attempt, counted from 0.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let millis = self.initial_backoff_ms as f64 * self.multiplier.max(1.0).powi(attempt as i32);
+        Duration::from_millis(millis.min(self.max_backoff_ms as f64) as u64)
+    }
+
+    pub(crate) fn retries_status(status: u16, idempotent: bool) -> bool {
+        status == 429 || (idempotent && (500..600).contains(&status))
+    }
+
+    pub(crate) fn retries_error(error: &reqwest::Error, idempotent: bool) -> bool {
+        error.is_connect() || (idempotent && error.is_timeout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_to_the_max() {
+        let policy = RetryPolicy::default()
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(500));
+        let waits: Vec<u64> = (0..5)
+            .map(|attempt| policy.backoff(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(waits, vec![100, 200, 400, 500, 500]);
+        assert!(RetryPolicy::retries_status(503, true));
+        assert!(!RetryPolicy::retries_status(503, false));
+        assert!(RetryPolicy::retries_status(429, false));
+        assert!(!RetryPolicy::retries_status(404, true));
+    }
+}
@@ -0,0 +1,361 @@
+pub mod config;
+pub mod request;
+#[cfg(test)]
+mod tests;
+
+use crate::config::{Auth, RetryPolicy};
+use crate::request::{ClientEvent, ClientRequest, ClientResponse};
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, MessageResult};
+use actor::bus::EventBus;
+use actor::clock::SimClock;
+use actor::recording::{Exchange, TrafficRecorder};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
+use actor::trace::{Direction, Protocol, TRACEPARENT, TraceContext, Traced};
+use actor::{ActorError, ActorResult, ActorResultVoid, ActorServiceMessage};
+use std::time::{Duration, Instant};
+use utils::gauges::metrics::Metrics;
+
+/// The timeout of an attempt when none is given.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An HTTP client calling the system under test, e.g. a machine posting its completion
+/// callback to the FES. It sends the [`ClientRequest`]s to its base url, retrying them
+/// according to its [`RetryPolicy`], and publishes the outcomes as [`ClientEvent`]s on the bus.
+pub struct HttpClient {
+    key: String,
+    base_url: String,
+    auth: Option<Auth>,
+    timeout: Duration,
+    retry: RetryPolicy,
+    client: Option<reqwest::Client>,
+    /// The bus topic the outcomes are published to.
+    publish_to: String,
+    bus: EventBus,
+    clock: SimClock,
+    lifecycle: Lifecycle,
+}
+
+impl HttpClient {
+    /// The outcomes are published to the topic named after the key on the global bus.
+    pub fn new(key: impl Into<String>, base_url: impl Into<String>) -> Self {
+        let key = key.into();
+        HttpClient {
+            lifecycle: Lifecycle::new(&key),
+            publish_to: key.clone(),
+            bus: EventBus::global(),
+            clock: SimClock::global(),
+            key,
+            base_url: base_url.into(),
+            auth: None,
+            timeout: DEFAULT_TIMEOUT,
+            retry: RetryPolicy::default(),
+            client: None,
+        }
+    }
+
+    pub fn publish_to(mut self, topic: impl Into<String>) -> Self {
+        self.publish_to = topic.into();
+        self
+    }
+
+    pub fn with_bus(mut self, bus: EventBus) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// Waits between the retries following the given clock instead of the global one.
+    pub fn with_clock(mut self, clock: SimClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// The timeout of every attempt, from the connection to the end of the response body.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn send(
+        &mut self,
+        request: ClientRequest,
+        trace: Option<TraceContext>,
+    ) -> actix::ResponseFuture<ActorResult<ClientResponse>> {
+        let key = self.key.clone();
+        let bus = self.bus.clone();
+        let publish_to = self.publish_to.clone();
+        let call = self.client.clone().map(|client| Call {
+            key: self.key.clone(),
+            client,
+            base_url: self.base_url.clone(),
+            auth: self.auth.clone(),
+            retry: self.retry.clone(),
+            clock: self.clock.clone(),
+        });
+        Box::pin(async move {
+            let result = match call {
+                Some(call) => call.send(&request, trace).await,
+                None => Err(ActorError::RuntimeError(
+                    "The client is not running".to_string(),
+                )),
+            };
+            let event = ClientEvent {
+                key,
+                request,
+                result: result
+                    .as_ref()
+                    .map(Clone::clone)
+                    .map_err(ToString::to_string),
+            };
+            bus.publish(&publish_to, event).await;
+            result
+        })
+    }
+}
+
+/// What a request needs from its client, moved to the future sending it.
+struct Call {
+    key: String,
+    client: reqwest::Client,
+    base_url: String,
+    auth: Option<Auth>,
+    retry: RetryPolicy,
+    clock: SimClock,
+}
+
+impl Call {
+    async fn send(
+        &self,
+        request: &ClientRequest,
+        trace: Option<TraceContext>,
+    ) -> ActorResult<ClientResponse> {
+        let method = reqwest::Method::from_bytes(request.method.to_uppercase().as_bytes())
+            .map_err(|_| ActorError::RuntimeError(format!("Invalid method {}", request.method)))?;
+        let url = format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            request.path.trim_start_matches('/')
+        );
+        let idempotent = request.idempotent || method.is_idempotent();
+        let started = Instant::now();
+        let metrics = Metrics::global();
+        let mut uri = None;
+        let mut attempt = 0;
+        loop {
+            let outcome = match self.build(method.clone(), &url, request, &trace) {
+                Ok(built) => {
+                    // the path and the query only, a replay sends them to its own target
+                    uri = Some(match built.url().query() {
+                        Some(query) => format!("{}?{}", built.url().path(), query),
+                        None => built.url().path().to_string(),
+                    });
+                    self.attempt(built).await
+                }
+                Err(e) => Err(e),
+            };
+            let retries = match &outcome {
+                Ok(response) => RetryPolicy::retries_status(response.status, idempotent),
+                Err(e) => RetryPolicy::retries_error(e, idempotent),
+            };
+            if !retries || attempt >= self.retry.max_retries {
+                metrics.counter(&self.key, "http_client_requests").inc();
+                metrics
+                    .histogram(&self.key, "http_client_latency_us")
+                    .record_duration(started.elapsed());
+                if outcome.as_ref().map_or(true, |r| r.status >= 500) {
+                    metrics.counter(&self.key, "http_client_errors").inc();
+                }
+                // the final outcome only, a replay sends the request once
+                if let Some(uri) = uri {
+                    self.record(request, uri, outcome.as_ref().ok());
+                }
+                return outcome
+                    .map_err(|e| ActorError::RuntimeError(format!("{} {}: {}", method, url, e)));
+            }
+            let wait = self.retry.backoff(attempt);
+            match &outcome {
+                Ok(response) => log::warn!(
+                    "[{}] {} {} answered {}, retry in {:?}",
+                    self.key,
+                    method,
+                    url,
+                    response.status,
+                    wait
+                ),
+                Err(e) => log::warn!(
+                    "[{}] {} {} failed: {}, retry in {:?}",
+                    self.key,
+                    method,
+                    url,
+                    e,
+                    wait
+                ),
+            }
+            metrics.counter(&self.key, "http_client_retries").inc();
+            self.clock.sleep(wait).await;
+            attempt += 1;
+        }
+    }
+
+    fn build(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        request: &ClientRequest,
+        trace: &Option<TraceContext>,
+    ) -> Result<reqwest::Request, reqwest::Error> {
+        let mut builder = self.client.request(method, url).query(&request.query);
+        for (name, value) in request.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        if let Some(auth) = &self.auth {
+            builder = auth.apply(builder);
+        }
+        if let Some(trace) = trace {
+            builder = builder.header(TRACEPARENT, trace.to_traceparent());
+        }
+        if let Some(body) = &request.body {
+            builder = builder.json(body);
+        }
+        builder.build()
+    }
+
+    async fn attempt(&self, built: reqwest::Request) -> Result<ClientResponse, reqwest::Error> {
+        let response = self.client.execute(built).await?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| v.to_str().ok().map(|v| (k.to_string(), v.to_string())))
+            .collect();
+        let body = response.text().await?;
+        Ok(ClientResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    fn record(&self, request: &ClientRequest, uri: String, response: Option<&ClientResponse>) {
+        let recorder = TrafficRecorder::global();
+        if recorder.is_recording() {
+            recorder.record(
+                &self.key,
+                Direction::Outbound,
+                Exchange::Http {
+                    method: request.method.to_uppercase(),
+                    uri,
+                    headers: request.headers.clone(),
+                    body: request
+                        .body
+                        .as_ref()
+                        .map(|b| b.to_string())
+                        .unwrap_or_default(),
+                    status: response.map(|r| r.status),
+                    response: response.map(|r| r.body.clone()),
+                },
+            );
+        }
+    }
+}
+
+impl Actor for HttpClient {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>())
+                .with(ctx.address().recipient::<ClientRequest>())
+                .with(ctx.address().recipient::<Traced<ClientRequest>>()),
+        );
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if self.lifecycle.state() != ActorState::Failed {
+            self.lifecycle.set(ActorState::Stopped);
+        }
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
+}
+
+impl Handler<ActorServiceMessage> for HttpClient {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            ActorServiceMessage::Start => {
+                if self.client.is_some() {
+                    return Err(ActorError::StartupError(
+                        "The client is already running".to_string(),
+                    ));
+                }
+                log::info!("[{}] Starting the client of {}", self.key, self.base_url);
+                self.lifecycle.set(ActorState::Starting);
+                if let Err(e) = reqwest::Url::parse(&self.base_url) {
+                    let error = format!("Invalid base url {}: {}", self.base_url, e);
+                    self.lifecycle.fail(error.clone());
+                    return Err(ActorError::StartupError(error));
+                }
+                let client = reqwest::Client::builder()
+                    .timeout(self.timeout)
+                    .build()
+                    .map_err(|e| {
+                        self.lifecycle.fail(e.to_string());
+                        ActorError::StartupError(e.to_string())
+                    })?;
+                self.client = Some(client);
+                self.lifecycle.set(ActorState::Running);
+            }
+            ActorServiceMessage::Stop => {
+                log::info!("[{}] Stopping the client", self.key);
+                self.lifecycle.set(ActorState::Stopping);
+                self.client = None;
+                ctx.stop();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Handler<ActorStatusMessage> for HttpClient {
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
+impl Handler<ClientRequest> for HttpClient {
+    type Result = actix::ResponseFuture<ActorResult<ClientResponse>>;
+
+    fn handle(&mut self, msg: ClientRequest, _ctx: &mut Self::Context) -> Self::Result {
+        self.send(msg, None)
+    }
+}
+
+/// Sends the request with the trace context in the `traceparent` header.
+impl Handler<Traced<ClientRequest>> for HttpClient {
+    type Result = actix::ResponseFuture<ActorResult<ClientResponse>>;
+
+    fn handle(&mut self, msg: Traced<ClientRequest>, _ctx: &mut Self::Context) -> Self::Result {
+        let trace = msg.trace.child();
+        trace.record(
+            &self.key,
+            Protocol::Http,
+            Direction::Outbound,
+            format!("{} {}", msg.message.method, msg.message.path),
+        );
+        self.send(msg.message, Some(trace))
+    }
+}
@@ -0,0 +1,104 @@
+use actix::Message;
+use actor::ActorResult;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// A request sent by a client to its base url, e.g. the completion callback of a machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Message)]
+#[rtype(result = "ActorResult<ClientResponse>")]
+pub struct ClientRequest {
+    pub method: String,
+    /// Relative to the base url, e.g. `/orders/42/complete`.
+    pub path: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    /// Sent as JSON.
+    #[serde(default)]
+    pub body: Option<Value>,
+    /// The request can be sent twice without harm although its method is not idempotent,
+    /// e.g. a POST with an idempotency key, so its timeouts and server errors are retried.
+    #[serde(default)]
+    pub idempotent: bool,
+}
+
+impl ClientRequest {
+    pub fn new(method: impl Into<String>, path: impl Into<String>) -> Self {
+        ClientRequest {
+            method: method.into(),
+            path: path.into(),
+            headers: BTreeMap::new(),
+            query: BTreeMap::new(),
+            body: None,
+            idempotent: false,
+        }
+    }
+
+    pub fn get(path: impl Into<String>) -> Self {
+        ClientRequest::new("GET", path)
+    }
+
+    pub fn post(path: impl Into<String>) -> Self {
+        ClientRequest::new("POST", path)
+    }
+
+    pub fn put(path: impl Into<String>) -> Self {
+        ClientRequest::new("PUT", path)
+    }
+
+    pub fn delete(path: impl Into<String>) -> Self {
+        ClientRequest::new("DELETE", path)
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_query(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_json(mut self, body: Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    pub fn idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+}
+
+/// The response to a [`ClientRequest`], whatever its status.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientResponse {
+    pub status: u16,
+    /// By the lowercase name.
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl ClientResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The body parsed as JSON.
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.body).ok()
+    }
+}
+
+/// Published on the bus by a client for every request it sent,
+/// the error is the one returned to the sender once the retries are exhausted.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct ClientEvent {
+    pub key: String,
+    pub request: ClientRequest,
+    pub result: Result<ClientResponse, String>,
+}
@@ -0,0 +1,298 @@
+use crate::HttpClient;
+use crate::config::{Auth, RetryPolicy};
+use crate::request::{ClientEvent, ClientRequest};
+use actix::{Actor, Addr, Context, Handler};
+use actor::ActorServiceMessage;
+use actor::bus::{EventBus, SubscribeOptions};
+use actor::clock::SimClock;
+use actor::recording::{Exchange, TrafficRecorder};
+use http_serv_actor::journal::FindRequests;
+use http_serv_actor::stub::{STARTED, Stub, StubMessage};
+use http_serv_actor::{BaseHttpServer, BoundAddresses};
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+struct Collector(UnboundedSender<ClientEvent>);
+
+impl Actor for Collector {
+    type Context = Context<Self>;
+}
+
+impl Handler<ClientEvent> for Collector {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientEvent, _ctx: &mut Self::Context) -> Self::Result {
+        let _ = self.0.send(msg);
+    }
+}
+
+/// A bus of its own with a collector subscribed to the topic.
+fn collect(topic: &str) -> (EventBus, UnboundedReceiver<ClientEvent>) {
+    let bus = EventBus::default();
+    let (tx, events) = unbounded_channel();
+    bus.subscribe(
+        topic,
+        Collector(tx).start().recipient(),
+        SubscribeOptions::default(),
+    );
+    (bus, events)
+}
+
+async fn fes() -> (Addr<BaseHttpServer>, String) {
+    let server = BaseHttpServer::new("fes", "127.0.0.1", 0, None).start();
+    server
+        .send(ActorServiceMessage::Start)
+        .await
+        .unwrap()
+        .unwrap();
+    let completion = |id: &str| {
+        Stub::new(id, "POST", "/orders/{id}/complete")
+            .with_header("authorization", "Bearer secret")
+            .in_scenario("fes")
+    };
+    for stub in [
+        completion("busy")
+            .when_state(STARTED)
+            .will_set_state("up")
+            .respond(503, "busy"),
+        completion("ack")
+            .when_state("up")
+            .respond(200, json!({"order": "{{request.path.id}}"})),
+        Stub::new("slow", "GET", "/slow")
+            .respond(200, "late")
+            .with_delay(Duration::from_millis(500)),
+        Stub::new("slow-post", "POST", "/slow")
+            .respond(200, "late")
+            .with_delay(Duration::from_millis(500)),
+        Stub::new("broken", "POST", "/broken").respond(500, "broken"),
+    ] {
+        server.send(StubMessage::add(stub)).await.unwrap().unwrap();
+    }
+    let port = server.send(BoundAddresses).await.unwrap()[0].port();
+    (server, format!("http://127.0.0.1:{}", port))
+}
+
+#[actix::test]
+async fn retries_and_publishes() {
+    let (server, base_url) = fes().await;
+    let (bus, mut events) = collect("presses");
+    let clock = SimClock::default();
+    let client = HttpClient::new("press-1", &base_url)
+        .publish_to("presses")
+        .with_bus(bus)
+        .with_clock(clock.clone())
+        .with_auth(Auth::bearer("secret"))
+        .with_retry(
+            RetryPolicy::default()
+                .with_backoff(Duration::from_millis(10), Duration::from_millis(50)),
+        )
+        .start();
+    // the completion carries an idempotency key, its server errors are retried
+    let completion = ClientRequest::post("/orders/42/complete")
+        .with_json(json!({"parts": 12}))
+        .idempotent();
+    assert!(client.send(completion.clone()).await.unwrap().is_err());
+    assert!(events.recv().await.unwrap().result.is_err());
+
+    client
+        .send(ActorServiceMessage::Start)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // the first attempt gets a 503, the retry the acknowledgment once the clock moved on
+    let recorder = TrafficRecorder::global();
+    recorder.start();
+    clock.pause();
+    let sent = client.send(completion.clone());
+    let sent = actix::spawn(sent);
+    while server
+        .send(FindRequests(None))
+        .await
+        .unwrap()
+        .unwrap()
+        .is_empty()
+    {
+        tokio::task::yield_now().await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        server
+            .send(FindRequests(None))
+            .await
+            .unwrap()
+            .unwrap()
+            .len(),
+        1
+    );
+    clock.advance(Duration::from_millis(10));
+    let response = sent.await.unwrap().unwrap().unwrap();
+    recorder.stop();
+    assert_eq!(response.status, 200);
+    // the final outcome only, a replay does not send the retries
+    let recorded = || -> Vec<_> {
+        recorder
+            .interactions()
+            .into_iter()
+            .filter(|i| i.actor == "press-1")
+            .collect()
+    };
+    assert_eq!(recorded().len(), 1);
+    assert!(matches!(
+        &recorded()[0].exchange,
+        Exchange::Http {
+            status: Some(200),
+            ..
+        }
+    ));
+    assert_eq!(response.json(), Some(json!({"order": "42"})));
+    let event = events.recv().await.unwrap();
+    assert_eq!(event.key, "press-1");
+    assert_eq!(event.request, completion);
+    assert_eq!(event.result, Ok(response));
+
+    let received = server.send(FindRequests(None)).await.unwrap().unwrap();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[1].json(), Some(json!({"parts": 12})));
+    assert_eq!(received[1].headers["authorization"], "Bearer secret");
+
+    // a client error is not retried
+    recorder.start();
+    let response = client
+        .send(ClientRequest::get("/unknown").with_query("line", "1"))
+        .await
+        .unwrap()
+        .unwrap();
+    recorder.stop();
+    assert_eq!(response.status, 404);
+    // recorded with the path only to be replayed against another target
+    assert!(matches!(
+        &recorded()[1].exchange,
+        Exchange::Http { uri, status: Some(404), .. } if uri == "/unknown?line=1"
+    ));
+    assert_eq!(events.recv().await.unwrap().result.unwrap().status, 404);
+    let received = server.send(FindRequests(None)).await.unwrap().unwrap();
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[2].query, "line=1");
+
+    client
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+    server
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[actix::test]
+async fn timeouts_are_errors() {
+    let (server, base_url) = fes().await;
+    let (bus, mut events) = collect("press-2");
+    let client = HttpClient::new("press-2", &base_url)
+        .with_bus(bus)
+        .with_timeout(Duration::from_millis(100))
+        .with_retry(
+            RetryPolicy::default()
+                .with_max_retries(1)
+                .with_backoff(Duration::from_millis(10), Duration::from_millis(10)),
+        )
+        .start();
+    client
+        .send(ActorServiceMessage::Start)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let err = client
+        .send(ClientRequest::get("/slow"))
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert!(err.to_string().contains("/slow"));
+    assert!(events.recv().await.unwrap().result.is_err());
+    // the timed out attempt is retried once
+    let received = server.send(FindRequests(None)).await.unwrap().unwrap();
+    assert_eq!(received.len(), 2);
+
+    assert!(
+        HttpClient::new("press-3", "not a url")
+            .start()
+            .send(ActorServiceMessage::Start)
+            .await
+            .unwrap()
+            .is_err()
+    );
+
+    client
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+    server
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[actix::test]
+async fn unsafe_requests_are_not_retried() {
+    let (server, base_url) = fes().await;
+    let (bus, _events) = collect("press-4");
+    let client = HttpClient::new("press-4", &base_url)
+        .with_bus(bus)
+        .with_timeout(Duration::from_millis(100))
+        .with_retry(
+            RetryPolicy::default()
+                .with_max_retries(1)
+                .with_backoff(Duration::from_millis(10), Duration::from_millis(10)),
+        )
+        .start();
+    client
+        .send(ActorServiceMessage::Start)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // the server may have processed the POST before failing or answering late
+    let response = client
+        .send(ClientRequest::post("/broken"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.status, 500);
+    assert!(
+        client
+            .send(ClientRequest::post("/slow"))
+            .await
+            .unwrap()
+            .is_err()
+    );
+    let received = server.send(FindRequests(None)).await.unwrap().unwrap();
+    assert_eq!(received.len(), 2);
+
+    // unless it says it can be sent twice
+    let response = client
+        .send(ClientRequest::post("/broken").idempotent())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.status, 500);
+    let received = server.send(FindRequests(None)).await.unwrap().unwrap();
+    assert_eq!(received.len(), 4);
+
+    client
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+    server
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+}
@@ -0,0 +1,66 @@
+use actix_web::body::BoxBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+use actor::fault::{Fault, Faults};
+
+/// Applies the drawn faults to the request: delays it, holds it during a hang
+/// or answers with an error status.
+pub(crate) async fn inject_faults(
+    faults: Faults,
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let drawn = faults.draw();
+    if drawn.is_empty() {
+        return next.call(req).await;
+    }
+    faults.delay(&drawn).await;
+    if drawn.contains(&Fault::Hang) {
+        faults.hang().await;
+    }
+    let status = drawn.iter().find_map(|f| match f {
+        Fault::HttpError(code) => StatusCode::from_u16(*code).ok(),
+        _ => None,
+    });
+    match status {
+        Some(status) => Ok(req.into_response(HttpResponse::new(status))),
+        None => next.call(req).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, test, web};
+    use actor::fault::{FaultInjection, FaultMessage};
+
+    #[actix::test]
+    async fn error_status() {
+        let faults = Faults::new("machine");
+        let injected = faults.clone();
+        let app = test::init_service(
+            App::new()
+                .route("/ping", web::get().to(HttpResponse::Ok))
+                .wrap(from_fn(move |req, next| {
+                    inject_faults(injected.clone(), req, next)
+                })),
+        )
+        .await;
+        let ping = || test::TestRequest::get().uri("/ping").to_request();
+
+        faults
+            .handle(FaultMessage::Inject(FaultInjection::new(Fault::HttpError(
+                503,
+            ))))
+            .unwrap();
+        let resp = test::call_service(&app, ping()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        faults.handle(FaultMessage::Clear).unwrap();
+        let resp = test::call_service(&app, ping()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::stub::{RequestContext, RequestMatcher, StubRequest, url_query};
+use actix::Message;
+use actix_web::body::BoxBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{Error, HttpResponse};
+use actor::clock::SimClock;
+use actor::{ActorError, ActorResult, ActorResultVoid};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The requests kept by default, the oldest ones are dropped first.
+pub const DEFAULT_JOURNAL_CAPACITY: usize = 1000;
+
+/// The path of the admin endpoints, the requests to them are not journaled.
+pub const ADMIN_PATH: &str = "/__admin";
+
+/// A request received by an http server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedRequest {
+    /// In the simulated time.
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    /// By the lowercase name.
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl LoggedRequest {
+    /// The body parsed as JSON.
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.body).ok()
+    }
+
+    fn context(&self) -> RequestContext {
+        RequestContext {
+            method: self.method.clone(),
+            path: self.path.clone(),
+            params: HashMap::new(),
+            query: url_query(&self.query),
+            headers: self.headers.clone().into_iter().collect(),
+            body: self.json(),
+            text: self.body.clone(),
+        }
+    }
+}
+
+/// How many times a request is expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Times {
+    Exactly(usize),
+    AtLeast(usize),
+    AtMost(usize),
+}
+
+impl Times {
+    pub fn check(&self, count: usize) -> bool {
+        match self {
+            Times::Exactly(n) => count == *n,
+            Times::AtLeast(n) => count >= *n,
+            Times::AtMost(n) => count <= *n,
+        }
+    }
+}
+
+impl Display for Times {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Times::Exactly(n) => write!(f, "exactly {}", n),
+            Times::AtLeast(n) => write!(f, "at least {}", n),
+            Times::AtMost(n) => write!(f, "at most {}", n),
+        }
+    }
+}
+
+/// The journaled requests matching the pattern, all of them when it is missing, the oldest first.
+#[derive(Debug, Message)]
+#[rtype(result = "ActorResult<Vec<LoggedRequest>>")]
+pub struct FindRequests(pub Option<StubRequest>);
+
+/// The number of journaled requests matching the pattern.
+#[derive(Debug, Message)]
+#[rtype(result = "ActorResult<usize>")]
+pub struct CountRequests(pub StubRequest);
+
+/// Fails unless the pattern matches the expected number of journaled requests,
+/// e.g. `POST /orders` exactly twice.
+#[derive(Debug, Message)]
+#[rtype(result = "ActorResultVoid")]
+pub struct VerifyRequests {
+    pub request: StubRequest,
+    pub times: Times,
+}
+
+/// Forgets the journaled requests.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct ResetJournal;
+
+/// The bounded journal of the requests received by a server, shared with its workers.
+#[derive(Debug, Clone)]
+pub(crate) struct Journal {
+    requests: Arc<Mutex<VecDeque<LoggedRequest>>>,
+    capacity: usize,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal::new(DEFAULT_JOURNAL_CAPACITY)
+    }
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Journal {
+            requests: Arc::new(Mutex::new(VecDeque::new())),
+            capacity,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<LoggedRequest>> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, request: LoggedRequest) {
+        let mut requests = self.lock();
+        while requests.len() >= self.capacity.max(1) {
+            requests.pop_front();
+        }
+        requests.push_back(request);
+    }
+
+    pub fn find(&self, pattern: Option<StubRequest>) -> ActorResult<Vec<LoggedRequest>> {
+        let requests = self.lock();
+        match pattern {
+            None => Ok(requests.iter().cloned().collect()),
+            Some(pattern) => {
+                let matcher = RequestMatcher::new(pattern)?;
+                Ok(requests
+                    .iter()
+                    .filter(|r| matcher.matches(&r.context()).is_some())
+                    .cloned()
+                    .collect())
+            }
+        }
+    }
+
+    pub fn count(&self, pattern: StubRequest) -> ActorResult<usize> {
+        self.find(Some(pattern)).map(|found| found.len())
+    }
+
+    pub fn verify(&self, pattern: StubRequest, times: Times) -> ActorResultVoid {
+        let described = format!(
+            "{} {}",
+            pattern.method.as_deref().unwrap_or("ANY"),
+            pattern.path
+        );
+        let count = self.count(pattern)?;
+        if times.check(count) {
+            Ok(())
+        } else {
+            Err(ActorError::RuntimeError(format!(
+                "Expected {} {} requests, received {}",
+                times, described, count
+            )))
+        }
+    }
+
+    pub fn reset(&self) {
+        self.lock().clear();
+    }
+}
+
+/// Journals the request before it is served, the requests to the admin endpoints are skipped.
+pub(crate) async fn journal_requests(
+    journal: Journal,
+    body_limit: usize,
+    mut req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if req.path().starts_with(ADMIN_PATH) {
+        return next.call(req).await;
+    }
+    let body = crate::buffer_body(&mut req, body_limit).await;
+    journal.record(LoggedRequest {
+        timestamp: SimClock::global().now(),
+        method: req.method().to_string(),
+        path: req.path().to_string(),
+        query: req.query_string().to_string(),
+        headers: req
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| v.to_str().ok().map(|v| (k.to_string(), v.to_string())))
+            .collect(),
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+    next.call(req).await
+}
+
+fn answer<T: Serialize>(result: ActorResult<T>, field: &str) -> HttpResponse {
+    match result {
+        Ok(value) => HttpResponse::Ok().json(json!({ field: value })),
+        Err(e) => HttpResponse::BadRequest().json(json!({ "error": e.to_string() })),
+    }
+}
+
+/// Mounts the journal endpoints:
+/// `GET /__admin/requests` lists the requests, `DELETE /__admin/requests` forgets them,
+/// `POST /__admin/requests/find` and `POST /__admin/requests/count` take a [`StubRequest`].
+pub(crate) fn mount(cfg: &mut ServiceConfig, journal: Journal) {
+    let (all, reset, find, count) = (journal.clone(), journal.clone(), journal.clone(), journal);
+    let path = format!("{}/requests", ADMIN_PATH);
+    cfg.route(
+        &path,
+        web::get().to(move || {
+            let requests = all.find(None);
+            async move { answer(requests, "requests") }
+        }),
+    )
+    .route(
+        &path,
+        web::delete().to(move || {
+            reset.reset();
+            async { HttpResponse::Ok().finish() }
+        }),
+    )
+    .route(
+        &format!("{}/find", path),
+        web::post().to(move |pattern: web::Json<StubRequest>| {
+            let requests = find.find(Some(pattern.into_inner()));
+            async move { answer(requests, "requests") }
+        }),
+    )
+    .route(
+        &format!("{}/count", path),
+        web::post().to(move |pattern: web::Json<StubRequest>| {
+            let counted = count.count(pattern.into_inner());
+            async move { answer(counted, "count") }
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, test};
+
+    fn orders(method: &str) -> StubRequest {
+        StubRequest {
+            method: Some(method.to_string()),
+            path: "/orders".to_string(),
+            headers: BTreeMap::new(),
+            query: BTreeMap::new(),
+            exact_query: false,
+            body: None,
+            body_text: None,
+        }
+    }
+
+    #[actix::test]
+    async fn journal_and_verify() {
+        let journal = Journal::new(3);
+        let (mounted, recording) = (journal.clone(), journal.clone());
+        let app = test::init_service(
+            App::new()
+                .configure(|cfg| mount(cfg, mounted))
+                .route("/orders", web::post().to(HttpResponse::Created))
+                .wrap(from_fn(move |req, next| {
+                    journal_requests(recording.clone(), crate::DEFAULT_BODY_LIMIT, req, next)
+                })),
+        )
+        .await;
+
+        for product in ["gear", "shaft"] {
+            let req = test::TestRequest::post()
+                .uri("/orders?line=1")
+                .set_json(json!({"product": product}))
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+        test::call_service(&app, test::TestRequest::get().uri("/status").to_request()).await;
+
+        journal.verify(orders("POST"), Times::Exactly(2)).unwrap();
+        let err = journal
+            .verify(orders("GET"), Times::AtLeast(1))
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Expected at least 1 GET /orders requests, received 0")
+        );
+        let mut gear = orders("POST");
+        gear.body = Some(json!({"product": "gear"}));
+        gear.query.insert("line".to_string(), "1".into());
+        assert_eq!(journal.count(gear.clone()).unwrap(), 1);
+
+        let req = test::TestRequest::post()
+            .uri("/__admin/requests/count")
+            .set_json(&gear)
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, json!({"count": 1}));
+
+        // the capacity drops the oldest request
+        test::call_service(&app, test::TestRequest::get().uri("/status").to_request()).await;
+        let req = test::TestRequest::get()
+            .uri("/__admin/requests")
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        let requests = body["requests"].as_array().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0]["body"], "{\"product\":\"shaft\"}");
+
+        let req = test::TestRequest::delete()
+            .uri("/__admin/requests")
+            .to_request();
+        test::call_service(&app, req).await;
+        assert!(journal.find(None).unwrap().is_empty());
+    }
+
+    /// Answers the length of the body read by the handler.
+    async fn length(mut payload: web::Payload) -> HttpResponse {
+        let mut length = 0;
+        while let Some(chunk) = futures_util::StreamExt::next(&mut payload).await {
+            length += chunk.unwrap().len();
+        }
+        HttpResponse::Ok().body(length.to_string())
+    }
+
+    #[actix::test]
+    async fn journal_large_bodies() {
+        // over the 256 KiB limit of the actix extractors
+        let body = "x".repeat(300 * 1024);
+        for (limit, journaled) in [(crate::DEFAULT_BODY_LIMIT, body.len()), (1000, 1000)] {
+            let journal = Journal::new(1);
+            let recording = journal.clone();
+            let app =
+                test::init_service(App::new().route("/programs", web::post().to(length)).wrap(
+                    from_fn(move |req, next| journal_requests(recording.clone(), limit, req, next)),
+                ))
+                .await;
+            let req = test::TestRequest::post()
+                .uri("/programs")
+                .set_payload(body.clone())
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), 200);
+            assert_eq!(test::read_body(res).await, body.len().to_string());
+            assert_eq!(journal.find(None).unwrap()[0].body.len(), journaled);
+        }
+    }
+}
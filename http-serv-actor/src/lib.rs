@@ -1,25 +1,79 @@
-use actix::{
-    Actor, ActorFutureExt, AsyncContext, Context, Handler, Message, Recipient, WrapFuture,
+mod fault;
+pub mod journal;
+pub mod live;
+pub mod metrics;
+mod recording;
+pub mod stub;
+pub mod tls;
+pub mod wiremock;
+
+use crate::journal::{
+    CountRequests, FindRequests, Journal, LoggedRequest, ResetJournal, VerifyRequests,
+};
+use crate::live::{LiveKind, LiveRoutes, Push, SubscribeFrames};
+use crate::stub::{ListScenarios, ListStubs, ResetScenarios, StubMessage, Stubs};
+use crate::tls::{CertificateAuthority, GetCertificateAuthority, TlsConfig};
+use crate::wiremock::Mappings;
+use actix::{Actor, AsyncContext, Context, Handler, Message, MessageResult, SpawnHandle};
+use actix_web::dev::{Payload, ServerHandle, Service, ServiceRequest};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::from_fn;
+use actix_web::web::{Bytes, BytesMut, ServiceConfig};
+use actix_web::{
+    App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Result as ActixResult, web,
 };
-use actix_web::dev::ServerHandle;
-use actix_web::web::ServiceConfig;
-use actix_web::{App, HttpResponse, HttpServer, Result as ActixResult, web};
+use actor::fault::{FaultMessage, Faults};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
+use actor::trace::{Direction, Protocol, TRACEPARENT, TraceContext};
 use actor::{ActorError, ActorResult, ActorResultVoid, ActorServiceMessage};
+use futures_util::StreamExt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::oneshot;
+use std::time::{Duration, Instant};
+use utils::gauges::metrics::Metrics;
+
+const DEFAULT_WORKERS: usize = 4;
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(75);
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How much of a request body is read by the journal, the recording and the stubs by default.
+pub const DEFAULT_BODY_LIMIT: usize = 1024 * 1024;
+
+/// How often the mapping directory is checked for changes.
+const MAPPINGS_POLL: Duration = Duration::from_secs(1);
+
+pub type RouterConfig = Arc<dyn Fn(&mut ServiceConfig) + Send + Sync>;
 
 pub struct BaseHttpServer {
     key: String,
     host: String,
     port: u16,
-    router_config: Option<Arc<dyn Fn(&mut ServiceConfig) + Send + Sync>>,
+    router_config: Option<RouterConfig>,
     server_handle: Option<ServerHandle>,
+    workers: usize,
+    keep_alive: Duration,
+    shutdown_timeout: Duration,
+    body_limit: usize,
+    addrs: Vec<SocketAddr>,
+    lifecycle: Lifecycle,
+    faults: Faults,
+    stubs: Stubs,
+    journal: Journal,
+    mappings: Option<Mappings>,
+    /// Reloads the changed mappings while the server runs.
+    mappings_watcher: Option<SpawnHandle>,
+    tls: Option<TlsConfig>,
+    ca: Option<CertificateAuthority>,
+    live: LiveRoutes,
 }
 
-fn default_config() -> Arc<dyn Fn(&mut ServiceConfig) + Send + Sync> {
+fn default_config() -> RouterConfig {
     Arc::new(|cfg| {
         cfg.route("/ping", web::get().to(ping_handler))
             .route("/health", web::get().to(health_handler));
+        metrics::mount(cfg, "/metrics", Metrics::global());
     })
 }
 impl Default for BaseHttpServer {
@@ -33,14 +87,114 @@ impl BaseHttpServer {
         key: impl Into<String>,
         host: impl Into<String>,
         port: u16,
-        mb_router_config: Option<Arc<dyn Fn(&mut ServiceConfig) + Send + Sync>>,
+        mb_router_config: Option<RouterConfig>,
     ) -> Self {
+        let key = key.into();
         Self {
-            key: key.into(),
+            lifecycle: Lifecycle::new(&key),
+            faults: Faults::new(&key),
+            stubs: Stubs::default(),
+            journal: Journal::default(),
+            mappings: None,
+            mappings_watcher: None,
+            tls: None,
+            ca: None,
+            live: LiveRoutes::default(),
+            key,
             host: host.into(),
             port,
             router_config: Some(mb_router_config.unwrap_or_else(default_config)),
             server_handle: None,
+            workers: DEFAULT_WORKERS,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            body_limit: DEFAULT_BODY_LIMIT,
+            addrs: vec![],
+        }
+    }
+
+    /// Keeps the given number of requests in the journal instead of [`journal::DEFAULT_JOURNAL_CAPACITY`].
+    pub fn with_journal_capacity(mut self, capacity: usize) -> Self {
+        self.journal = Journal::new(capacity);
+        self
+    }
+
+    /// Serves the WireMock mappings of the directory as stubs, see [`wiremock::load_mappings`].
+    /// They are loaded when the server starts and reloaded when a file changes.
+    pub fn with_mappings(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.mappings = Some(Mappings::new(dir.into()));
+        self
+    }
+
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// How long the open connections are given to finish when the server stops,
+    /// rounded up to whole seconds.
+    pub fn with_shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// How much of a request body the journal, the recording and the stubs read,
+    /// the longer bodies are truncated for them but still passed on whole to the routes.
+    pub fn with_body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    /// Adds a WebSocket route, the clients get the data of [`Push`] as text frames
+    /// and their frames go to the recipients of [`SubscribeFrames`].
+    pub fn with_websocket(self, path: impl Into<String>) -> Self {
+        self.live.add(path.into(), LiveKind::WebSocket);
+        self
+    }
+
+    /// Adds a route of server-sent events, the clients get the data of [`Push`] as events.
+    pub fn with_sse(self, path: impl Into<String>) -> Self {
+        self.live.add(path.into(), LiveKind::Sse);
+        self
+    }
+
+    /// Serves HTTPS, the generated CA is returned by [`GetCertificateAuthority`].
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    fn load_mappings(&mut self, ctx: &mut Context<Self>) -> ActorResultVoid {
+        let Some(mappings) = self.mappings.as_mut() else {
+            return Ok(());
+        };
+        log::info!(
+            "[{}] Loading the mappings from {}",
+            self.key,
+            mappings.root().display()
+        );
+        mappings.load(&self.stubs)?;
+        self.mappings_watcher =
+            Some(ctx.run_interval(MAPPINGS_POLL, |act, _ctx| act.reload_mappings()));
+        Ok(())
+    }
+
+    fn reload_mappings(&mut self) {
+        let Some(mappings) = self.mappings.as_mut().filter(|m| m.changed()) else {
+            return;
+        };
+        log::info!(
+            "[{}] Reloading the mappings from {}",
+            self.key,
+            mappings.root().display()
+        );
+        if let Err(e) = mappings.load(&self.stubs) {
+            log::error!("[{}] Keeping the previous mappings: {}", self.key, e);
         }
     }
 
@@ -51,45 +205,185 @@ impl BaseHttpServer {
             ))
         } else {
             let bind_addr = format!("{}:{}", self.host, self.port);
-            log::info!("[{}] Starting HTTP server on {}", self.key, bind_addr);
+            log::info!(
+                "[{}] Starting {} server on {}",
+                self.key,
+                if self.tls.is_some() { "HTTPS" } else { "HTTP" },
+                bind_addr
+            );
+            self.lifecycle.set(ActorState::Starting);
+            let tls = self
+                .tls
+                .as_ref()
+                .map(|tls| tls::server_config(&self.key, tls, &mut self.ca))
+                .transpose()?;
 
             let app_config = self
                 .router_config
                 .take()
                 .ok_or(ActorError::StartupError("unexpected!".to_string()))?;
 
+            let key = self.key.clone();
+            let faults = self.faults.clone();
+            let stubs = self.stubs.clone();
+            let journal = self.journal.clone();
+            let live = self.live.clone();
+            let body_limit = self.body_limit;
             let server = HttpServer::new(move || {
+                let key = key.clone();
+                let requests = Metrics::global().counter(&key, "http_requests");
+                let errors = Metrics::global().counter(&key, "http_errors");
+                let latency = Metrics::global().histogram(&key, "http_request_latency_us");
+                let record_key = key.clone();
+                let faults = faults.clone();
+                let stubs = stubs.clone();
+                let journal = journal.clone();
+                let admin = journal.clone();
+                let scenarios = stubs.clone();
+                let live = live.clone();
                 App::new()
+                    .configure(|cfg| live::mount(cfg, live))
+                    .configure(|cfg| journal::mount(cfg, admin))
+                    .configure(|cfg| stub::mount(cfg, scenarios))
                     .configure(|ctx| app_config(ctx))
+                    .wrap(from_fn(move |req, next| {
+                        stub::serve_stubs(stubs.clone(), body_limit, req, next)
+                    }))
+                    .wrap(from_fn(move |req, next| {
+                        fault::inject_faults(faults.clone(), req, next)
+                    }))
+                    .wrap(from_fn(move |req, next| {
+                        recording::record_traffic(record_key.clone(), body_limit, req, next)
+                    }))
+                    .wrap(from_fn(move |req, next| {
+                        journal::journal_requests(journal.clone(), body_limit, req, next)
+                    }))
+                    .wrap_fn(move |req, srv| {
+                        let incoming = req.headers().get(TRACEPARENT).and_then(|v| v.to_str().ok());
+                        let trace = TraceContext::continue_from(incoming);
+                        trace.record(
+                            &key,
+                            Protocol::Http,
+                            Direction::Inbound,
+                            format!("{} {}", req.method(), req.path()),
+                        );
+                        req.extensions_mut().insert(trace.clone());
+                        let started = Instant::now();
+                        let response = srv.call(req);
+                        let (requests, errors, latency) =
+                            (requests.clone(), errors.clone(), latency.clone());
+                        async move {
+                            let mut response = response.await?;
+                            requests.inc();
+                            latency.record_duration(started.elapsed());
+                            if response.status().is_server_error() {
+                                errors.inc();
+                            }
+                            if let Ok(value) = HeaderValue::from_str(&trace.to_traceparent()) {
+                                response
+                                    .headers_mut()
+                                    .insert(HeaderName::from_static(TRACEPARENT), value);
+                            }
+                            Ok(response)
+                        }
+                    })
                     .wrap(actix_web::middleware::Logger::default())
                     .wrap(actix_web::middleware::Compress::default())
-            })
-            .bind(&bind_addr)
+            });
+            let server = match tls {
+                Some(config) => server.bind_rustls_0_23(&bind_addr, config),
+                None => server.bind(&bind_addr),
+            }
             .map_err(|e| {
                 ActorError::StartupError(format!("Failed to bind to {}: {}", bind_addr, e))
             })?
-            .workers(4)
-            .keep_alive(std::time::Duration::from_secs(75))
-            .shutdown_timeout(30);
+            .workers(self.workers)
+            .keep_alive(self.keep_alive)
+            .shutdown_timeout(whole_seconds(self.shutdown_timeout))
+            // the actor is stopped with ActorServiceMessage::Stop, not by the process signals
+            .disable_signals();
+            self.addrs = server.addrs();
+            log::info!("[{}] Listening on {:?}", self.key, self.addrs);
 
             let server_runner = server.run();
-            let server_handle = server_runner.handle();
-            self.server_handle = Some(server_handle);
+            self.server_handle = Some(server_runner.handle());
+            self.lifecycle.set(ActorState::Running);
+
+            let lifecycle = self.lifecycle.clone();
+            actix::spawn(async move {
+                match server_runner.await {
+                    Ok(()) => lifecycle.set(ActorState::Stopped),
+                    Err(e) => lifecycle.fail(e),
+                }
+            });
 
             Ok(())
         }
     }
 
-    fn stop(&mut self) -> ActorResultVoid {
+    fn stop(&mut self, ctx: &mut Context<Self>) {
         log::info!("[{}] Stopping HTTP server", self.key);
+        self.addrs.clear();
+        if let Some(watcher) = self.mappings_watcher.take() {
+            ctx.cancel_future(watcher);
+        }
+        // the open streams would hold the graceful shutdown for the whole timeout
+        self.live.close();
         if let Some(handle) = self.server_handle.take() {
-            _ = handle.stop(true);
+            self.lifecycle.set(ActorState::Stopping);
+            actix::spawn(handle.stop(true));
             log::info!("[{}] HTTP server is stopping", self.key);
         }
-        Ok(())
     }
 }
 
+/// The duration in seconds rounded up, e.g. 1 for 500ms.
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// The body of a request, read ahead by the middlewares and put back for the handlers.
+/// Only the first `limit` bytes are returned, the handlers still get the whole body.
+/// It is empty for the upgrades (e.g. WebSocket) whose payload is the connection itself.
+/// An error of the payload is left to the handlers.
+pub(crate) async fn buffer_body(req: &mut ServiceRequest, limit: usize) -> Bytes {
+    if req.head().upgrade() {
+        return Bytes::new();
+    }
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    let mut rest = None;
+    while let Some(chunk) = payload.next().await {
+        match chunk {
+            Ok(chunk) if body.len() + chunk.len() <= limit => body.extend_from_slice(&chunk),
+            other => {
+                rest = Some(other);
+                break;
+            }
+        }
+    }
+    let read = body.clone().freeze();
+    match rest {
+        None => req.set_payload(Payload::from(read.clone())),
+        Some(next) => {
+            if let Ok(chunk) = &next {
+                body.extend_from_slice(&chunk[..limit - body.len()]);
+            }
+            let unread = futures_util::stream::iter([Ok(read), next]).chain(payload);
+            req.set_payload(Payload::Stream {
+                payload: Box::pin(unread),
+            });
+        }
+    }
+    body.freeze()
+}
+
+/// The trace context of the request, the server continues the trace of the `traceparent` header
+/// or starts a new one, and returns it in the `traceparent` header of the response.
+pub fn trace_context(req: &HttpRequest) -> Option<TraceContext> {
+    req.extensions().get::<TraceContext>().cloned()
+}
+
 async fn ping_handler() -> ActixResult<HttpResponse> {
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "status": "pong",
@@ -107,6 +401,30 @@ async fn health_handler() -> ActixResult<HttpResponse> {
 
 impl Actor for BaseHttpServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>())
+                .with(ctx.address().recipient::<FaultMessage>())
+                .with(ctx.address().recipient::<StubMessage>())
+                .with(ctx.address().recipient::<ListStubs>())
+                .with(ctx.address().recipient::<ResetScenarios>())
+                .with(ctx.address().recipient::<GetCertificateAuthority>())
+                .with(ctx.address().recipient::<BoundAddresses>())
+                .with(ctx.address().recipient::<Push>())
+                .with(ctx.address().recipient::<SubscribeFrames>())
+                .with(ctx.address().recipient::<ListScenarios>())
+                .with(ctx.address().recipient::<FindRequests>())
+                .with(ctx.address().recipient::<CountRequests>())
+                .with(ctx.address().recipient::<VerifyRequests>())
+                .with(ctx.address().recipient::<ResetJournal>()),
+        );
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
 }
 
 impl Handler<ActorServiceMessage> for BaseHttpServer {
@@ -115,11 +433,19 @@ impl Handler<ActorServiceMessage> for BaseHttpServer {
     fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Context<Self>) -> Self::Result {
         match msg {
             ActorServiceMessage::Start => {
-                self.prepare_start()?;
+                // checked before the mappings so a running server neither reloads them nor fails
+                if self.server_handle.is_some() {
+                    return Err(ActorError::StartupError(
+                        "Server is already running".to_string(),
+                    ));
+                }
+                self.load_mappings(ctx)
+                    .and_then(|_| self.prepare_start())
+                    .inspect_err(|e| self.lifecycle.fail(e))?;
                 log::info!("[{}] HTTP server started successfully", self.key);
             }
             ActorServiceMessage::Stop => {
-                self.stop();
+                self.stop(ctx);
             }
         }
 
@@ -127,10 +453,136 @@ impl Handler<ActorServiceMessage> for BaseHttpServer {
     }
 }
 
+impl Handler<ActorStatusMessage> for BaseHttpServer {
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
+impl Handler<FaultMessage> for BaseHttpServer {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: FaultMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        self.faults.handle(msg)
+    }
+}
+
+impl Handler<StubMessage> for BaseHttpServer {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: StubMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        match &msg {
+            StubMessage::Add(stub) => log::info!("[{}] Add the stub {}", self.key, stub.id),
+            StubMessage::Remove(id) => log::info!("[{}] Remove the stub {}", self.key, id),
+            StubMessage::Clear => log::info!("[{}] Clear the stubs", self.key),
+        }
+        self.stubs.handle(msg)
+    }
+}
+
+impl Handler<ListStubs> for BaseHttpServer {
+    type Result = MessageResult<ListStubs>;
+
+    fn handle(&mut self, _msg: ListStubs, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.stubs.list())
+    }
+}
+
+impl Handler<ResetScenarios> for BaseHttpServer {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ResetScenarios, _ctx: &mut Context<Self>) -> Self::Result {
+        log::info!("[{}] Reset the scenarios", self.key);
+        self.stubs.reset_scenarios()
+    }
+}
+
+impl Handler<ListScenarios> for BaseHttpServer {
+    type Result = MessageResult<ListScenarios>;
+
+    fn handle(&mut self, _msg: ListScenarios, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.stubs.scenarios())
+    }
+}
+
+impl Handler<Push> for BaseHttpServer {
+    type Result = ActorResult<usize>;
+
+    fn handle(&mut self, msg: Push, _ctx: &mut Context<Self>) -> Self::Result {
+        self.live.push(msg)
+    }
+}
+
+impl Handler<SubscribeFrames> for BaseHttpServer {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: SubscribeFrames, _ctx: &mut Context<Self>) -> Self::Result {
+        log::info!("[{}] Subscribe to the frames of {}", self.key, msg.path);
+        self.live.subscribe(msg)
+    }
+}
+
+/// The socket addresses a running server listens on, e.g. the port chosen for the port 0.
+#[derive(Debug, Message)]
+#[rtype(result = "Vec<SocketAddr>")]
+pub struct BoundAddresses;
+
+impl Handler<BoundAddresses> for BaseHttpServer {
+    type Result = MessageResult<BoundAddresses>;
+
+    fn handle(&mut self, _msg: BoundAddresses, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.addrs.clone())
+    }
+}
+
+impl Handler<GetCertificateAuthority> for BaseHttpServer {
+    type Result = Option<CertificateAuthority>;
+
+    fn handle(&mut self, _msg: GetCertificateAuthority, _ctx: &mut Context<Self>) -> Self::Result {
+        self.ca.clone()
+    }
+}
+
+impl Handler<FindRequests> for BaseHttpServer {
+    type Result = ActorResult<Vec<LoggedRequest>>;
+
+    fn handle(&mut self, msg: FindRequests, _ctx: &mut Context<Self>) -> Self::Result {
+        self.journal.find(msg.0)
+    }
+}
+
+impl Handler<CountRequests> for BaseHttpServer {
+    type Result = ActorResult<usize>;
+
+    fn handle(&mut self, msg: CountRequests, _ctx: &mut Context<Self>) -> Self::Result {
+        self.journal.count(msg.0)
+    }
+}
+
+impl Handler<VerifyRequests> for BaseHttpServer {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: VerifyRequests, _ctx: &mut Context<Self>) -> Self::Result {
+        self.journal.verify(msg.request, msg.times)
+    }
+}
+
+impl Handler<ResetJournal> for BaseHttpServer {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ResetJournal, _ctx: &mut Context<Self>) -> Self::Result {
+        log::info!("[{}] Reset the journal", self.key);
+        self.journal.reset()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use actix::prelude::*;
+    use actor::status::wait_for_state;
+    use std::time::Duration;
     use utils::logger_on;
 
     #[actix::test]
@@ -139,6 +591,8 @@ mod tests {
         assert_eq!(server.key, "http_server");
         assert_eq!(server.host, "127.0.0.1");
         assert_eq!(server.port, 8080);
+        assert_eq!(whole_seconds(Duration::from_millis(500)), 1);
+        assert_eq!(whole_seconds(Duration::from_secs(2)), 2);
     }
 
     #[actix::test]
@@ -158,9 +612,114 @@ mod tests {
         let start_result = addr.send(ActorServiceMessage::Start).await;
         assert!(start_result.is_ok());
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        let status = addr.clone().recipient();
+        wait_for_state(&status, ActorState::Running, Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        // a second start is refused and leaves the server running
+        assert!(
+            addr.send(ActorServiceMessage::Start)
+                .await
+                .unwrap()
+                .is_err()
+        );
+        wait_for_state(&status, ActorState::Running, Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        let registered = ActorRegistry::lookup::<ActorServiceMessage>("http_server").await;
+        assert!(registered.is_some());
 
         let stop_result = addr.send(ActorServiceMessage::Stop).await;
         assert!(stop_result.is_ok());
+
+        wait_for_state(&status, ActorState::Stopped, Duration::from_secs(1))
+            .await
+            .unwrap();
+    }
+
+    #[actix::test]
+    async fn test_mutual_tls() {
+        let server = BaseHttpServer::new("https_server", "127.0.0.1", 0, None)
+            .with_tls(TlsConfig::self_signed().with_client_cert());
+        let addr = server.start();
+        assert!(addr.send(GetCertificateAuthority).await.unwrap().is_none());
+        addr.send(ActorServiceMessage::Start)
+            .await
+            .unwrap()
+            .unwrap();
+        let status = addr.clone().recipient();
+        wait_for_state(&status, ActorState::Running, Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        let ca = addr.send(GetCertificateAuthority).await.unwrap().unwrap();
+        let trusted = reqwest::Certificate::from_pem(ca.pem().as_bytes()).unwrap();
+        let anonymous = reqwest::Client::builder()
+            .add_root_certificate(trusted.clone())
+            .build()
+            .unwrap();
+        let port = addr.send(BoundAddresses).await.unwrap()[0].port();
+        let url = &format!("https://localhost:{}/ping", port);
+        assert!(anonymous.get(url).send().await.is_err());
+
+        let other = CertificateAuthority::generate("other").unwrap();
+        let forged = other.issue(&["mes-client"], true).unwrap();
+        let client = |issued: tls::IssuedCertificate| {
+            let identity = reqwest::Identity::from_pem(
+                format!("{}{}", issued.cert_pem, issued.key_pem).as_bytes(),
+            )
+            .unwrap();
+            reqwest::Client::builder()
+                .add_root_certificate(trusted.clone())
+                .identity(identity)
+                .build()
+                .unwrap()
+        };
+        assert!(client(forged).get(url).send().await.is_err());
+        let issued = ca.issue(&["mes-client"], true).unwrap();
+        let resp = client(issued).get(url).send().await.unwrap();
+        assert!(resp.status().is_success());
+
+        addr.send(ActorServiceMessage::Stop).await.unwrap().unwrap();
+        wait_for_state(&status, ActorState::Stopped, Duration::from_secs(1))
+            .await
+            .unwrap();
+    }
+
+    #[actix::test]
+    async fn test_concurrent_servers_on_port_zero() {
+        let mut servers = vec![];
+        for key in ["mes_a", "mes_b"] {
+            let addr = BaseHttpServer::new(key, "127.0.0.1", 0, None)
+                .with_workers(1)
+                .with_keep_alive(Duration::from_secs(5))
+                .with_shutdown_timeout(Duration::from_secs(1))
+                .start();
+            assert!(addr.send(BoundAddresses).await.unwrap().is_empty());
+            addr.send(ActorServiceMessage::Start)
+                .await
+                .unwrap()
+                .unwrap();
+            servers.push(addr);
+        }
+
+        let mut ports = vec![];
+        for addr in servers.iter() {
+            let bound = addr.send(BoundAddresses).await.unwrap();
+            assert_eq!(bound.len(), 1);
+            let port = bound[0].port();
+            assert_ne!(port, 0);
+            let url = format!("http://127.0.0.1:{}/ping", port);
+            assert!(reqwest::get(url).await.unwrap().status().is_success());
+            ports.push(port);
+        }
+        assert_ne!(ports[0], ports[1]);
+
+        for addr in servers {
+            addr.send(ActorServiceMessage::Stop).await.unwrap().unwrap();
+            assert!(addr.send(BoundAddresses).await.unwrap().is_empty());
+        }
     }
 }
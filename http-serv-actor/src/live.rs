@@ -0,0 +1,455 @@
+use actix::{Message, Recipient};
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE, ContentEncoding};
+use actix_web::web::{self, Bytes, ServiceConfig};
+use actix_web::{Error, HttpRequest, HttpResponse};
+use actix_ws::AggregatedMessage;
+use actor::{ActorError, ActorResult, ActorResultVoid};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+/// How the clients of a live route are served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveKind {
+    WebSocket,
+    /// Server-sent events.
+    Sse,
+}
+
+/// A frame received from a WebSocket client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Forwarded to the subscribers of a WebSocket route for every data frame of its clients.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct WsFrame {
+    pub path: String,
+    /// Tells the connections of the route apart, unique within the server.
+    pub client: u64,
+    pub frame: Frame,
+}
+
+/// Sends the data to every client of a live route, a text frame for WebSocket
+/// or an event (named when `event` is given) for SSE. Returns how many clients got it.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "ActorResult<usize>")]
+pub struct Push {
+    pub path: String,
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl Push {
+    pub fn new(path: impl Into<String>, data: impl Into<String>) -> Self {
+        Push {
+            path: path.into(),
+            event: None,
+            data: data.into(),
+        }
+    }
+
+    pub fn with_event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+}
+
+/// Forwards the frames received on a WebSocket route to the recipient.
+#[derive(Debug, Message)]
+#[rtype(result = "ActorResultVoid")]
+pub struct SubscribeFrames {
+    pub path: String,
+    pub recipient: Recipient<WsFrame>,
+}
+
+struct Route {
+    kind: LiveKind,
+    clients: Vec<(u64, UnboundedSender<String>)>,
+    subscribers: Vec<Recipient<WsFrame>>,
+}
+
+/// The live routes of a server with their clients and subscribers, shared with its workers.
+#[derive(Clone, Default)]
+pub(crate) struct LiveRoutes {
+    routes: Arc<Mutex<BTreeMap<String, Route>>>,
+    next_client: Arc<AtomicU64>,
+}
+
+impl LiveRoutes {
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Route>> {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn add(&self, path: String, kind: LiveKind) {
+        self.lock().insert(
+            path,
+            Route {
+                kind,
+                clients: vec![],
+                subscribers: vec![],
+            },
+        );
+    }
+
+    fn paths(&self) -> Vec<(String, LiveKind)> {
+        self.lock()
+            .iter()
+            .map(|(path, route)| (path.clone(), route.kind))
+            .collect()
+    }
+
+    fn connect(&self, path: &str) -> (u64, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let id = self.next_client.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = unbounded_channel();
+        if let Some(route) = self.lock().get_mut(path) {
+            route.clients.push((id, tx));
+        }
+        (id, rx)
+    }
+
+    fn disconnect(&self, path: &str, client: u64) {
+        if let Some(route) = self.lock().get_mut(path) {
+            route.clients.retain(|(id, _)| *id != client);
+        }
+    }
+
+    /// Drops the clients of every route, which ends their streams and closes their sockets.
+    pub fn close(&self) {
+        for route in self.lock().values_mut() {
+            route.clients.clear();
+        }
+    }
+
+    fn no_route(path: &str) -> ActorError {
+        ActorError::RuntimeError(format!("No live route {}", path))
+    }
+
+    pub fn push(&self, msg: Push) -> ActorResult<usize> {
+        let mut routes = self.lock();
+        let route = routes
+            .get_mut(&msg.path)
+            .ok_or_else(|| Self::no_route(&msg.path))?;
+        let text = match route.kind {
+            LiveKind::WebSocket => msg.data,
+            LiveKind::Sse => {
+                let mut event = msg
+                    .event
+                    .map(|name| format!("event: {}\n", name))
+                    .unwrap_or_default();
+                for line in msg.data.lines() {
+                    event.push_str(&format!("data: {}\n", line));
+                }
+                event + "\n"
+            }
+        };
+        // the clients gone since the last push are dropped
+        route
+            .clients
+            .retain(|(_, tx)| tx.send(text.clone()).is_ok());
+        Ok(route.clients.len())
+    }
+
+    pub fn subscribe(&self, msg: SubscribeFrames) -> ActorResultVoid {
+        let mut routes = self.lock();
+        match routes.get_mut(&msg.path) {
+            Some(route) if route.kind == LiveKind::WebSocket => {
+                route.subscribers.push(msg.recipient);
+                Ok(())
+            }
+            _ => Err(ActorError::RuntimeError(format!(
+                "No WebSocket route {}",
+                msg.path
+            ))),
+        }
+    }
+
+    fn forward(&self, path: &str, client: u64, frame: Frame) {
+        if let Some(route) = self.lock().get_mut(path) {
+            route.subscribers.retain(|s| s.connected());
+            for subscriber in route.subscribers.iter() {
+                subscriber.do_send(WsFrame {
+                    path: path.to_string(),
+                    client,
+                    frame: frame.clone(),
+                });
+            }
+        }
+    }
+}
+
+async fn websocket(
+    routes: LiveRoutes,
+    path: String,
+    req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let (response, mut session, stream) = actix_ws::handle(&req, body)?;
+    let mut stream = stream.aggregate_continuations();
+    let (client, mut outgoing) = routes.connect(&path);
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                text = outgoing.recv() => {
+                    let Some(text) = text else {
+                        break;
+                    };
+                    if session.text(text).await.is_err() {
+                        break;
+                    }
+                }
+                msg = stream.recv() => match msg {
+                    Some(Ok(AggregatedMessage::Text(text))) => {
+                        routes.forward(&path, client, Frame::Text(text.to_string()))
+                    }
+                    Some(Ok(AggregatedMessage::Binary(bytes))) => {
+                        routes.forward(&path, client, Frame::Binary(bytes.to_vec()))
+                    }
+                    Some(Ok(AggregatedMessage::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(AggregatedMessage::Pong(_))) => {}
+                    Some(Ok(AggregatedMessage::Close(_))) | Some(Err(_)) | None => break,
+                },
+            }
+        }
+        routes.disconnect(&path, client);
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+fn events(routes: LiveRoutes, path: String) -> HttpResponse {
+    let (_, outgoing) = routes.connect(&path);
+    // a comment first, the clients see the stream open before the first event
+    let opened = futures_util::stream::once(async { Ok::<_, Error>(Bytes::from(": open\n\n")) });
+    let events = futures_util::stream::unfold(outgoing, |mut outgoing| async move {
+        outgoing
+            .recv()
+            .await
+            .map(|text| (Ok::<_, Error>(Bytes::from(text)), outgoing))
+    });
+    HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        // kept out of the compression, the encoder would hold the events back in its buffer
+        .insert_header(ContentEncoding::Identity)
+        .streaming(futures_util::StreamExt::chain(opened, events))
+}
+
+/// Mounts the live routes.
+pub(crate) fn mount(cfg: &mut ServiceConfig, routes: LiveRoutes) {
+    for (path, kind) in routes.paths() {
+        let (routes, route) = (routes.clone(), path.clone());
+        match kind {
+            LiveKind::WebSocket => cfg.route(
+                &path,
+                web::get().to(move |req: HttpRequest, body: web::Payload| {
+                    websocket(routes.clone(), route.clone(), req, body)
+                }),
+            ),
+            LiveKind::Sse => cfg.route(
+                &path,
+                web::get().to(move || {
+                    let response = events(routes.clone(), route.clone());
+                    async move { response }
+                }),
+            ),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BaseHttpServer, BoundAddresses};
+    use actix::{Actor, Context, Handler};
+    use actor::ActorServiceMessage;
+    use futures_util::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio::sync::mpsc::UnboundedSender;
+    use tokio_tungstenite::tungstenite;
+
+    struct Collector(UnboundedSender<WsFrame>);
+
+    impl Actor for Collector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<WsFrame> for Collector {
+        type Result = ();
+
+        fn handle(&mut self, msg: WsFrame, _ctx: &mut Self::Context) -> Self::Result {
+            let _ = self.0.send(msg);
+        }
+    }
+
+    async fn wait_for_clients(server: &actix::Addr<BaseHttpServer>, path: &str) {
+        for _ in 0..50 {
+            if server.send(Push::new(path, "")).await.unwrap().unwrap() > 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("no client on {}", path);
+    }
+
+    #[actix::test]
+    async fn websocket_and_sse() {
+        let server = BaseHttpServer::new("machine_feed", "127.0.0.1", 0, None)
+            .with_websocket("/telemetry")
+            .with_sse("/events")
+            .start();
+        server
+            .send(ActorServiceMessage::Start)
+            .await
+            .unwrap()
+            .unwrap();
+        let port = server.send(BoundAddresses).await.unwrap()[0].port();
+
+        let (tx, mut frames) = unbounded_channel();
+        let collector = Collector(tx).start();
+        server
+            .send(SubscribeFrames {
+                path: "/telemetry".to_string(),
+                recipient: collector.recipient(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let (tx, _) = unbounded_channel();
+        let not_ws = SubscribeFrames {
+            path: "/events".to_string(),
+            recipient: Collector(tx).start().recipient(),
+        };
+        assert!(server.send(not_ws).await.unwrap().is_err());
+
+        let (mut ws, _) =
+            tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/telemetry", port))
+                .await
+                .unwrap();
+        ws.send(tungstenite::Message::text("spindle=1200"))
+            .await
+            .unwrap();
+        let frame = frames.recv().await.unwrap();
+        assert_eq!(frame.path, "/telemetry");
+        assert_eq!(frame.frame, Frame::Text("spindle=1200".to_string()));
+
+        wait_for_clients(&server, "/telemetry").await;
+        let pushed = server
+            .send(Push::new("/telemetry", "{\"state\":\"RUNNING\"}"))
+            .await
+            .unwrap();
+        assert_eq!(pushed.unwrap(), 1);
+        // the empty frames of wait_for_clients come first
+        let text = loop {
+            match ws.next().await.unwrap().unwrap() {
+                tungstenite::Message::Text(text) if !text.is_empty() => break text,
+                _ => {}
+            }
+        };
+        assert_eq!(text.as_str(), "{\"state\":\"RUNNING\"}");
+
+        let mut events = reqwest::get(format!("http://127.0.0.1:{}/events", port))
+            .await
+            .unwrap();
+        assert_eq!(
+            events.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+        wait_for_clients(&server, "/events").await;
+        server
+            .send(Push::new("/events", "42\n43").with_event("count"))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut received = String::new();
+        while !received.contains("event: count\ndata: 42\ndata: 43\n\n") {
+            let chunk = events.chunk().await.unwrap().unwrap();
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+        assert!(
+            server
+                .send(Push::new("/nothing", ""))
+                .await
+                .unwrap()
+                .is_err()
+        );
+
+        server
+            .send(ActorServiceMessage::Stop)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[actix::test]
+    async fn sse_uncompressed_and_closed_on_stop() {
+        let server = BaseHttpServer::new("machine_events", "127.0.0.1", 0, None)
+            .with_websocket("/telemetry")
+            .with_sse("/events")
+            .start();
+        server
+            .send(ActorServiceMessage::Start)
+            .await
+            .unwrap()
+            .unwrap();
+        let port = server.send(BoundAddresses).await.unwrap()[0].port();
+
+        let (mut ws, _) =
+            tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/telemetry", port))
+                .await
+                .unwrap();
+        let mut events = reqwest::Client::new()
+            .get(format!("http://127.0.0.1:{}/events", port))
+            .header("accept-encoding", "gzip")
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(
+            events
+                .headers()
+                .get("content-encoding")
+                .map(|e| e.to_str().unwrap()),
+            Some("gzip")
+        );
+        wait_for_clients(&server, "/events").await;
+        server
+            .send(Push::new("/events", "RUNNING").with_event("state"))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut received = String::new();
+        while !received.contains("event: state\ndata: RUNNING\n\n") {
+            let chunk = tokio::time::timeout(Duration::from_secs(1), events.chunk()).await;
+            received.push_str(&String::from_utf8_lossy(&chunk.unwrap().unwrap().unwrap()));
+        }
+
+        // the streams end with the server instead of holding its shutdown
+        wait_for_clients(&server, "/telemetry").await;
+        server
+            .send(ActorServiceMessage::Stop)
+            .await
+            .unwrap()
+            .unwrap();
+        let end = Duration::from_secs(2);
+        loop {
+            let chunk = tokio::time::timeout(end, events.chunk()).await.unwrap();
+            if chunk.map(|c| c.is_none()).unwrap_or(true) {
+                break;
+            }
+        }
+        loop {
+            match tokio::time::timeout(end, ws.next()).await.unwrap() {
+                Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            }
+        }
+    }
+}
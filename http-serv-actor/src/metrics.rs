@@ -0,0 +1,42 @@
+use actix_web::web::ServiceConfig;
+use actix_web::{HttpResponse, web};
+use utils::gauges::metrics::Metrics;
+
+/// The content type of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Mounts the Prometheus endpoint of the metrics at the path, e.g. in a `RouterConfig`:
+/// `Arc::new(|cfg| mount(cfg, "/metrics", Metrics::global()))`.
+pub fn mount(cfg: &mut ServiceConfig, path: &str, metrics: Metrics) {
+    cfg.route(
+        path,
+        web::get().to(move || {
+            let body = metrics.to_prometheus();
+            async move {
+                HttpResponse::Ok()
+                    .content_type(PROMETHEUS_CONTENT_TYPE)
+                    .body(body)
+            }
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, test};
+
+    #[actix::test]
+    async fn prometheus_endpoint() {
+        let metrics = Metrics::default();
+        metrics.counter("mes", "orders").add(2);
+        let app =
+            test::init_service(App::new().configure(|cfg| mount(cfg, "/metrics", metrics))).await;
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        assert!(resp.status().is_success());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("parallax_orders{actor=\"mes\"} 2"));
+    }
+}
@@ -0,0 +1,76 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::middleware::Next;
+use actix_web::{Error, error};
+use actor::recording::{Exchange, TrafficRecorder};
+use actor::trace::Direction;
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Records the request and the response while the traffic recorder is on.
+/// The bodies are buffered only then, the event streams and the WebSocket upgrades
+/// are recorded without the response body.
+pub(crate) async fn record_traffic(
+    key: String,
+    body_limit: usize,
+    mut req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let recorder = TrafficRecorder::global();
+    if !recorder.is_recording() {
+        return next.call(req).await;
+    }
+
+    let method = req.method().to_string();
+    let uri = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.to_string())
+        .unwrap_or_else(|| req.path().to_string());
+    let headers = req
+        .headers()
+        .iter()
+        .filter_map(|(k, v)| v.to_str().ok().map(|v| (k.to_string(), v.to_string())))
+        .collect();
+    let body = crate::buffer_body(&mut req, body_limit).await;
+
+    let res = next.call(req).await?;
+    let status = Some(res.status().as_u16());
+    let streaming = res.status() == StatusCode::SWITCHING_PROTOCOLS
+        || res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+    let (res, response) = if streaming {
+        (res, None)
+    } else {
+        let (req, res) = res.into_parts();
+        let (res, res_body) = res.into_parts();
+        let bytes = actix_web::body::to_bytes(res_body)
+            .await
+            .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+        let response = text(&bytes);
+        (
+            ServiceResponse::new(req, res.set_body(bytes.boxed())),
+            Some(response),
+        )
+    };
+    recorder.record(
+        &key,
+        Direction::Inbound,
+        Exchange::Http {
+            method,
+            uri,
+            headers,
+            body: text(&body),
+            status,
+            response,
+        },
+    );
+    Ok(res)
+}
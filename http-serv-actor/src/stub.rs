@@ -0,0 +1,861 @@
+use crate::journal::ADMIN_PATH;
+use actix::Message;
+use actix_web::body::BoxBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{Error, HttpResponse};
+use actor::clock::SimClock;
+use actor::{ActorError, ActorResult, ActorResultVoid};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::Duration;
+
+/// How a header or a query parameter is matched, a plain string is matched exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ValueMatcher {
+    EqualTo(String),
+    Contains {
+        contains: String,
+    },
+    Matches {
+        matches: String,
+    },
+    /// `absent: true` expects the value to be missing, `absent: false` to be present.
+    Absent {
+        absent: bool,
+    },
+}
+
+impl From<&str> for ValueMatcher {
+    fn from(value: &str) -> Self {
+        ValueMatcher::EqualTo(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StubRequest {
+    /// Any method when missing.
+    #[serde(default)]
+    pub method: Option<String>,
+    /// `/orders/{id}` captures a segment as `id`, `*` matches a segment and `**` the rest.
+    /// A pattern starting with `^` is a regular expression, its named groups are captured.
+    pub path: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, ValueMatcher>,
+    #[serde(default)]
+    pub query: BTreeMap<String, ValueMatcher>,
+    /// The request has no other query parameters than the `query` ones.
+    #[serde(default)]
+    pub exact_query: bool,
+    /// The JSON body has to contain these fields, the arrays and the other values are compared as is.
+    #[serde(default)]
+    pub body: Option<Value>,
+    /// Matches the body as text.
+    #[serde(default)]
+    pub body_text: Option<ValueMatcher>,
+}
+
+fn ok() -> u16 {
+    200
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// The response of a stub. The header values and the strings of the body are templates
+/// unless `templated` is false:
+/// `{{request.method}}`, `{{request.path}}`, `{{request.path.<name>}}`, `{{request.query.<name>}}`,
+/// `{{request.headers.<name>}}`, `{{request.body.<field>.<field>}}` and `{{now}}`.
+/// A string that is a single template keeps the JSON type of the value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StubResponse {
+    #[serde(default = "ok")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Sent as text when it is a string, as JSON otherwise.
+    #[serde(default)]
+    pub body: Option<Value>,
+    /// In the simulated time.
+    #[serde(default)]
+    pub delay_ms: u64,
+    #[serde(default = "yes")]
+    pub templated: bool,
+}
+
+impl Default for StubResponse {
+    fn default() -> Self {
+        StubResponse {
+            status: ok(),
+            headers: BTreeMap::new(),
+            body: None,
+            delay_ms: 0,
+            templated: true,
+        }
+    }
+}
+
+/// The state every scenario starts in and returns to on a reset.
+pub const STARTED: &str = "Started";
+
+/// Makes a stub depend on the state of a named scenario, e.g. `GET /status` answers `RUNNING`
+/// only after `POST /start` moved the machine scenario to the state `running`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ScenarioState {
+    pub name: String,
+    /// The stub matches only in this state, in any state when missing.
+    #[serde(default)]
+    pub required_state: Option<String>,
+    /// The state of the scenario after the stub answered.
+    #[serde(default)]
+    pub new_state: Option<String>,
+}
+
+/// A route added at runtime. The stubs with the lowest priority are tried first,
+/// the most recent one wins among the same priority.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stub {
+    pub id: String,
+    #[serde(default)]
+    pub priority: i32,
+    pub request: StubRequest,
+    #[serde(default)]
+    pub response: StubResponse,
+    #[serde(default)]
+    pub scenario: Option<ScenarioState>,
+}
+
+impl Stub {
+    pub fn new(id: impl Into<String>, method: &str, path: impl Into<String>) -> Self {
+        Stub {
+            id: id.into(),
+            priority: 0,
+            request: StubRequest {
+                method: Some(method.to_uppercase()),
+                path: path.into(),
+                headers: BTreeMap::new(),
+                query: BTreeMap::new(),
+                exact_query: false,
+                body: None,
+                body_text: None,
+            },
+            response: StubResponse::default(),
+            scenario: None,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, matcher: impl Into<ValueMatcher>) -> Self {
+        self.request
+            .headers
+            .insert(name.to_lowercase(), matcher.into());
+        self
+    }
+
+    pub fn with_query(mut self, name: &str, matcher: impl Into<ValueMatcher>) -> Self {
+        self.request.query.insert(name.to_string(), matcher.into());
+        self
+    }
+
+    pub fn with_body(mut self, body: Value) -> Self {
+        self.request.body = Some(body);
+        self
+    }
+
+    pub fn respond(mut self, status: u16, body: impl Into<Value>) -> Self {
+        self.response.status = status;
+        self.response.body = Some(body.into());
+        self
+    }
+
+    pub fn with_response_header(mut self, name: &str, value: &str) -> Self {
+        self.response
+            .headers
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.response.delay_ms = delay.as_millis() as u64;
+        self
+    }
+
+    pub fn in_scenario(mut self, name: &str) -> Self {
+        self.scenario.get_or_insert_with(Default::default).name = name.to_string();
+        self
+    }
+
+    pub fn when_state(mut self, state: &str) -> Self {
+        self.scenario
+            .get_or_insert_with(Default::default)
+            .required_state = Some(state.to_string());
+        self
+    }
+
+    pub fn will_set_state(mut self, state: &str) -> Self {
+        self.scenario.get_or_insert_with(Default::default).new_state = Some(state.to_string());
+        self
+    }
+
+    /// Checks the path pattern and the regular expressions of the matchers.
+    pub fn validate(&self) -> ActorResultVoid {
+        CompiledStub::new(self.clone()).map(|_| ())
+    }
+}
+
+/// Adds (or replaces the one with the same id), removes or clears the stubs of an http server.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "ActorResultVoid")]
+pub enum StubMessage {
+    Add(Box<Stub>),
+    Remove(String),
+    Clear,
+}
+
+impl StubMessage {
+    pub fn add(stub: Stub) -> Self {
+        StubMessage::Add(Box::new(stub))
+    }
+}
+
+/// The stubs of an http server in the matching order.
+#[derive(Debug, Message)]
+#[rtype(result = "Vec<Stub>")]
+pub struct ListStubs;
+
+/// Moves every scenario back to [`STARTED`].
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct ResetScenarios;
+
+/// The current state of every scenario of the stubs by name.
+#[derive(Debug, Message)]
+#[rtype(result = "BTreeMap<String, String>")]
+pub struct ListScenarios;
+
+fn invalid(e: regex::Error) -> ActorError {
+    ActorError::RuntimeError(format!("Invalid pattern: {}", e))
+}
+
+fn path_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    if pattern.starts_with('^') {
+        return Regex::new(pattern);
+    }
+    let segments: Vec<String> = pattern
+        .split('/')
+        .map(|segment| match segment {
+            "*" => "[^/]+".to_string(),
+            "**" => ".*".to_string(),
+            s if s.starts_with('{') && s.ends_with('}') => {
+                format!("(?P<{}>[^/]+)", &s[1..s.len() - 1])
+            }
+            s => regex::escape(s),
+        })
+        .collect();
+    Regex::new(&format!("^{}$", segments.join("/")))
+}
+
+#[derive(Debug)]
+enum CompiledMatcher {
+    EqualTo(String),
+    Contains(String),
+    Matches(Regex),
+    Absent(bool),
+}
+
+impl CompiledMatcher {
+    fn new(matcher: &ValueMatcher) -> ActorResult<Self> {
+        Ok(match matcher {
+            ValueMatcher::EqualTo(v) => CompiledMatcher::EqualTo(v.clone()),
+            ValueMatcher::Contains { contains } => CompiledMatcher::Contains(contains.clone()),
+            ValueMatcher::Matches { matches } => CompiledMatcher::Matches(
+                Regex::new(&format!("^(?:{})$", matches)).map_err(invalid)?,
+            ),
+            ValueMatcher::Absent { absent } => CompiledMatcher::Absent(*absent),
+        })
+    }
+
+    fn matches(&self, value: Option<&str>) -> bool {
+        match (self, value) {
+            (CompiledMatcher::Absent(absent), v) => *absent == v.is_none(),
+            (_, None) => false,
+            (CompiledMatcher::EqualTo(e), Some(v)) => e == v,
+            (CompiledMatcher::Contains(e), Some(v)) => v.contains(e.as_str()),
+            (CompiledMatcher::Matches(r), Some(v)) => r.is_match(v),
+        }
+    }
+}
+
+/// The expected JSON is contained in the actual one.
+fn contains_json(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(a), Value::Object(e)) => e
+            .iter()
+            .all(|(k, v)| a.get(k).is_some_and(|a| contains_json(a, v))),
+        (a, e) => a == e,
+    }
+}
+
+/// A compiled [`StubRequest`], used by the stubs and to query the journal.
+#[derive(Debug)]
+pub(crate) struct RequestMatcher {
+    request: StubRequest,
+    path: Regex,
+    headers: Vec<(String, CompiledMatcher)>,
+    query: Vec<(String, CompiledMatcher)>,
+    body_text: Option<CompiledMatcher>,
+}
+
+impl RequestMatcher {
+    pub fn new(request: StubRequest) -> ActorResult<Self> {
+        let compile = |m: &BTreeMap<String, ValueMatcher>| {
+            m.iter()
+                .map(|(k, v)| Ok((k.clone(), CompiledMatcher::new(v)?)))
+                .collect::<ActorResult<Vec<_>>>()
+        };
+        Ok(RequestMatcher {
+            path: path_regex(&request.path).map_err(invalid)?,
+            headers: compile(&request.headers)?,
+            query: compile(&request.query)?,
+            body_text: request
+                .body_text
+                .as_ref()
+                .map(CompiledMatcher::new)
+                .transpose()?,
+            request,
+        })
+    }
+
+    /// The captured path parameters when the request matches.
+    pub fn matches(&self, req: &RequestContext) -> Option<HashMap<String, String>> {
+        if self
+            .request
+            .method
+            .as_ref()
+            .is_some_and(|m| !m.eq_ignore_ascii_case(&req.method))
+        {
+            return None;
+        }
+        let captures = self.path.captures(&req.path)?;
+        let headers_match = self
+            .headers
+            .iter()
+            .all(|(name, m)| m.matches(req.headers.get(&name.to_lowercase()).map(String::as_str)));
+        let query_match = self
+            .query
+            .iter()
+            .all(|(name, m)| m.matches(req.query.get(name).map(String::as_str)))
+            && (!self.request.exact_query
+                || req.query.keys().all(|k| self.request.query.contains_key(k)));
+        let body_match = self.request.body.as_ref().is_none_or(|expected| {
+            req.body
+                .as_ref()
+                .is_some_and(|body| contains_json(body, expected))
+        }) && self
+            .body_text
+            .as_ref()
+            .is_none_or(|m| m.matches(Some(&req.text)));
+        if !(headers_match && query_match && body_match) {
+            return None;
+        }
+        Some(
+            self.path
+                .capture_names()
+                .flatten()
+                .filter_map(|name| {
+                    captures
+                        .name(name)
+                        .map(|m| (name.to_string(), m.as_str().to_string()))
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct CompiledStub {
+    stub: Stub,
+    matcher: RequestMatcher,
+}
+
+impl CompiledStub {
+    fn new(stub: Stub) -> ActorResult<Self> {
+        if stub.scenario.as_ref().is_some_and(|s| s.name.is_empty()) {
+            return Err(ActorError::RuntimeError(format!(
+                "The scenario of the stub {} has no name",
+                stub.id
+            )));
+        }
+        Ok(CompiledStub {
+            matcher: RequestMatcher::new(stub.request.clone())?,
+            stub,
+        })
+    }
+}
+
+/// The parts of a request the stubs are matched against and the templates are filled with.
+#[derive(Debug, Default)]
+pub(crate) struct RequestContext {
+    pub method: String,
+    pub path: String,
+    pub params: HashMap<String, String>,
+    pub query: HashMap<String, String>,
+    /// By the lowercase name.
+    pub headers: HashMap<String, String>,
+    pub body: Option<Value>,
+    pub text: String,
+}
+
+impl RequestContext {
+    pub fn new(req: &ServiceRequest, body: &[u8]) -> Self {
+        RequestContext {
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            params: HashMap::new(),
+            query: url_query(req.query_string()),
+            headers: req
+                .headers()
+                .iter()
+                .filter_map(|(k, v)| v.to_str().ok().map(|v| (k.to_string(), v.to_string())))
+                .collect(),
+            body: serde_json::from_slice(body).ok(),
+            text: String::from_utf8_lossy(body).into_owned(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Value> {
+        let text = |v: Option<&String>| v.map(|v| Value::String(v.clone()));
+        match name.split_once('.') {
+            None if name == "now" => Some(Value::String(SimClock::global().now().to_rfc3339())),
+            Some(("request", "method")) => Some(Value::String(self.method.clone())),
+            Some(("request", "path")) => Some(Value::String(self.path.clone())),
+            Some(("request", field)) => match field.split_once('.')? {
+                ("path", name) => text(self.params.get(name)),
+                ("query", name) => text(self.query.get(name)),
+                ("headers", name) => text(self.headers.get(&name.to_lowercase())),
+                ("body", fields) => fields
+                    .split('.')
+                    .try_fold(self.body.as_ref()?, |v, f| match v {
+                        Value::Array(items) => items.get(f.parse::<usize>().ok()?),
+                        v => v.get(f),
+                    })
+                    .cloned(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Replaces the `{{...}}` templates of the text, the unknown ones become empty.
+    pub fn render(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                break;
+            };
+            out.push_str(&rest[..start]);
+            match self.lookup(rest[start + 2..start + end].trim()) {
+                Some(Value::String(s)) => out.push_str(&s),
+                Some(v) => out.push_str(&v.to_string()),
+                None => {}
+            }
+            rest = &rest[start + end + 2..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    pub fn render_json(&self, value: &Value) -> Value {
+        match value {
+            Value::String(s) => {
+                let trimmed = s.trim();
+                let single = trimmed.starts_with("{{")
+                    && trimmed.ends_with("}}")
+                    && trimmed.matches("{{").count() == 1;
+                match single {
+                    true => self
+                        .lookup(trimmed[2..trimmed.len() - 2].trim())
+                        .unwrap_or(Value::Null),
+                    false => Value::String(self.render(s)),
+                }
+            }
+            Value::Array(items) => {
+                Value::Array(items.iter().map(|v| self.render_json(v)).collect())
+            }
+            Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(k, v)| (k.clone(), self.render_json(v)))
+                    .collect(),
+            ),
+            v => v.clone(),
+        }
+    }
+}
+
+pub(crate) fn url_query(query: &str) -> HashMap<String, String> {
+    web::Query::<HashMap<String, String>>::from_query(query)
+        .map(|q| q.into_inner())
+        .unwrap_or_default()
+}
+
+/// Adds the stub or replaces the one with the same id, the most recent first among the same priority.
+fn insert(stubs: &mut Vec<CompiledStub>, stub: CompiledStub) {
+    stubs.retain(|s| s.stub.id != stub.stub.id);
+    let idx = stubs
+        .iter()
+        .position(|s| s.stub.priority >= stub.stub.priority)
+        .unwrap_or(stubs.len());
+    stubs.insert(idx, stub);
+}
+
+/// The stubs of a server and the states of their scenarios, shared with its workers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Stubs {
+    inner: Arc<RwLock<Vec<CompiledStub>>>,
+    states: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Stubs {
+    pub fn handle(&self, msg: StubMessage) -> ActorResultVoid {
+        let mut stubs = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        match msg {
+            StubMessage::Add(stub) => insert(&mut stubs, CompiledStub::new(*stub)?),
+            StubMessage::Remove(id) => {
+                let before = stubs.len();
+                stubs.retain(|s| s.stub.id != id);
+                if stubs.len() == before {
+                    return Err(ActorError::RuntimeError(format!("No stub {}", id)));
+                }
+            }
+            StubMessage::Clear => stubs.clear(),
+        }
+        Ok(())
+    }
+
+    /// Replaces the stubs with the previous ids by the given ones at once,
+    /// none of them is added when one is invalid.
+    pub fn swap(&self, previous: &[String], replacements: Vec<Stub>) -> ActorResultVoid {
+        let compiled = replacements
+            .into_iter()
+            .map(CompiledStub::new)
+            .collect::<ActorResult<Vec<_>>>()?;
+        let mut stubs = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        stubs.retain(|s| !previous.contains(&s.stub.id));
+        for stub in compiled {
+            insert(&mut stubs, stub);
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<Stub> {
+        let stubs = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        stubs.iter().map(|s| s.stub.clone()).collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_empty()
+    }
+
+    fn lock_states(&self) -> MutexGuard<'_, HashMap<String, String>> {
+        self.states.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn reset_scenarios(&self) {
+        self.lock_states().clear();
+    }
+
+    pub fn scenarios(&self) -> BTreeMap<String, String> {
+        let stubs = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let states = self.lock_states();
+        stubs
+            .iter()
+            .filter_map(|s| s.stub.scenario.as_ref())
+            .map(|s| {
+                let state = states.get(&s.name).map(String::as_str).unwrap_or(STARTED);
+                (s.name.clone(), state.to_string())
+            })
+            .collect()
+    }
+
+    /// The response of the first matching stub, the path parameters are added to the context.
+    /// The scenarios are locked from the match to the transition, the concurrent requests see
+    /// the states one after another.
+    fn find(&self, req: &mut RequestContext) -> Option<StubResponse> {
+        let stubs = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let mut states = self.lock_states();
+        stubs.iter().find_map(|s| {
+            let scenario = s.stub.scenario.as_ref();
+            let in_state = scenario.is_none_or(|sc| {
+                sc.required_state.as_ref().is_none_or(|required| {
+                    states.get(&sc.name).map(String::as_str).unwrap_or(STARTED) == required
+                })
+            });
+            if !in_state {
+                return None;
+            }
+            s.matcher.matches(req).map(|params| {
+                if let Some((name, state)) =
+                    scenario.and_then(|sc| sc.new_state.as_ref().map(|state| (&sc.name, state)))
+                {
+                    states.insert(name.clone(), state.clone());
+                }
+                req.params = params;
+                s.stub.response.clone()
+            })
+        })
+    }
+}
+
+/// Mounts the scenario endpoints: `GET /__admin/scenarios` lists the states
+/// and `POST /__admin/scenarios/reset` moves every scenario back to [`STARTED`].
+pub(crate) fn mount(cfg: &mut ServiceConfig, stubs: Stubs) {
+    let listed = stubs.clone();
+    cfg.route(
+        &format!("{}/scenarios", ADMIN_PATH),
+        web::get().to(move || {
+            let scenarios: Vec<Value> = listed
+                .scenarios()
+                .into_iter()
+                .map(|(name, state)| json!({ "name": name, "state": state }))
+                .collect();
+            async move { HttpResponse::Ok().json(json!({ "scenarios": scenarios })) }
+        }),
+    )
+    .route(
+        &format!("{}/scenarios/reset", ADMIN_PATH),
+        web::post().to(move || {
+            stubs.reset_scenarios();
+            async { HttpResponse::Ok().finish() }
+        }),
+    );
+}
+
+fn respond(response: &StubResponse, req: &RequestContext) -> HttpResponse {
+    let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::OK);
+    let mut builder = HttpResponse::build(status);
+    let render = |text: &str| match response.templated {
+        true => req.render(text),
+        false => text.to_string(),
+    };
+    for (name, value) in response.headers.iter() {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&render(value)),
+        ) {
+            builder.insert_header((name, value));
+        }
+    }
+    match &response.body {
+        None => builder.finish(),
+        Some(Value::String(text)) => builder.body(render(text)),
+        Some(body) if response.templated => builder.json(req.render_json(body)),
+        Some(body) => builder.json(body),
+    }
+}
+
+/// Answers the requests matching a stub, the others go to the routes of the server.
+pub(crate) async fn serve_stubs(
+    stubs: Stubs,
+    body_limit: usize,
+    mut req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if stubs.is_empty() || req.path().starts_with(ADMIN_PATH) {
+        return next.call(req).await;
+    }
+    let body = crate::buffer_body(&mut req, body_limit).await;
+    let mut context = RequestContext::new(&req, &body);
+    match stubs.find(&mut context) {
+        None => next.call(req).await,
+        Some(response) => {
+            if response.delay_ms > 0 {
+                SimClock::global()
+                    .sleep(Duration::from_millis(response.delay_ms))
+                    .await;
+            }
+            Ok(req.into_response(respond(&response, &context)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, test};
+
+    #[actix::test]
+    async fn match_and_template() {
+        let stubs = Stubs::default();
+        let served = stubs.clone();
+        let app = test::init_service(
+            App::new()
+                .route("/orders/7", web::get().to(HttpResponse::NoContent))
+                .wrap(from_fn(move |req, next| {
+                    serve_stubs(served.clone(), crate::DEFAULT_BODY_LIMIT, req, next)
+                })),
+        )
+        .await;
+
+        stubs
+            .handle(StubMessage::add(
+                Stub::new("order", "GET", "/orders/{id}")
+                    .with_query("expand", ValueMatcher::Absent { absent: false })
+                    .respond(
+                        200,
+                        json!({"id": "{{request.path.id}}", "line": "{{request.query.expand}}"}),
+                    ),
+            ))
+            .unwrap();
+        stubs
+            .handle(StubMessage::add(
+                Stub::new("create", "post", "/orders")
+                    .with_header(
+                        "x-plant",
+                        ValueMatcher::Matches {
+                            matches: "line-\\d".into(),
+                        },
+                    )
+                    .with_body(json!({"order": {"product": "gear"}}))
+                    .respond(201, json!({"qty": "{{request.body.order.qty}}"}))
+                    .with_response_header("Location", "/orders/{{request.body.order.qty}}"),
+            ))
+            .unwrap();
+        assert!(
+            stubs
+                .handle(StubMessage::add(Stub::new("bad", "GET", "^(")))
+                .is_err()
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/orders/7?expand=lines")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!({"id": "7", "line": "lines"}));
+
+        // without the query the stub does not match, the route answers
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/orders/7").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let create = |plant: &str, product: &str| {
+            test::TestRequest::post()
+                .uri("/orders")
+                .insert_header(("x-plant", plant))
+                .set_json(json!({"order": {"product": product, "qty": 5}}))
+                .to_request()
+        };
+        let resp = test::call_service(&app, create("line-1", "gear")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get("location").unwrap(), "/orders/5");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!({"qty": 5}));
+        let resp = test::call_service(&app, create("line-1", "shaft")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = test::call_service(&app, create("hall", "gear")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        stubs
+            .handle(StubMessage::Remove("create".to_string()))
+            .unwrap();
+        assert_eq!(stubs.list().len(), 1);
+        assert!(
+            stubs
+                .handle(StubMessage::Remove("create".to_string()))
+                .is_err()
+        );
+    }
+
+    #[actix::test]
+    async fn scenario_states() {
+        let stubs = Stubs::default();
+        let (served, mounted) = (stubs.clone(), stubs.clone());
+        let app = test::init_service(App::new().configure(|cfg| mount(cfg, mounted)).wrap(
+            from_fn(move |req, next| {
+                serve_stubs(served.clone(), crate::DEFAULT_BODY_LIMIT, req, next)
+            }),
+        ))
+        .await;
+        let machine =
+            |id: &str, method: &str, path: &str| Stub::new(id, method, path).in_scenario("machine");
+        for stub in [
+            machine("idle", "GET", "/status").respond(200, "IDLE"),
+            machine("running", "GET", "/status")
+                .when_state("running")
+                .respond(200, "RUNNING"),
+            machine("start", "POST", "/start")
+                .when_state(STARTED)
+                .will_set_state("running")
+                .respond(202, "started"),
+            machine("stop", "POST", "/stop")
+                .when_state("running")
+                .will_set_state(STARTED)
+                .respond(202, "stopped"),
+        ] {
+            stubs.handle(StubMessage::add(stub)).unwrap();
+        }
+        assert!(
+            stubs
+                .handle(StubMessage::add(
+                    Stub::new("anonymous", "GET", "/").when_state("x")
+                ))
+                .is_err()
+        );
+
+        let call = |method: &str, path: &str| {
+            let req = match method {
+                "GET" => test::TestRequest::get(),
+                _ => test::TestRequest::post(),
+            };
+            test::call_and_read_body(&app, req.uri(path).to_request())
+        };
+        assert_eq!(call("GET", "/status").await, "IDLE");
+        assert_eq!(call("POST", "/start").await, "started");
+        assert_eq!(call("GET", "/status").await, "RUNNING");
+        assert_eq!(call("GET", "/status").await, "RUNNING");
+        // already running
+        assert_eq!(call("POST", "/start").await, "");
+        assert_eq!(call("POST", "/stop").await, "stopped");
+        assert_eq!(call("GET", "/status").await, "IDLE");
+
+        assert_eq!(call("POST", "/start").await, "started");
+        let scenarios: Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri("/__admin/scenarios")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(
+            scenarios,
+            json!({"scenarios": [{"name": "machine", "state": "running"}]})
+        );
+        let req = test::TestRequest::post()
+            .uri("/__admin/scenarios/reset")
+            .to_request();
+        test::call_service(&app, req).await;
+        assert_eq!(call("GET", "/status").await, "IDLE");
+    }
+}
@@ -0,0 +1,230 @@
+use actix::Message;
+use actor::{ActorError, ActorResult};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose,
+};
+use rustls::RootCertStore;
+use rustls::pki_types::CertificateDer;
+use rustls::server::{ServerConfig, WebPkiClientVerifier};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The names of the generated server certificate when none are given.
+const LOCAL_NAMES: [&str; 2] = ["localhost", "127.0.0.1"];
+
+/// The HTTPS settings of a server.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// The PEM files of the certificate chain and of its private key.
+    /// A certificate signed by a generated CA is used when they are missing.
+    #[serde(default)]
+    pub cert: Option<PathBuf>,
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+    /// The names of the generated certificate, `localhost` and `127.0.0.1` when empty.
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// The clients have to present a certificate signed by a CA of this PEM file,
+    /// or by the generated CA when `require_client_cert` is set without it.
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    #[serde(default)]
+    pub require_client_cert: bool,
+    /// Where the PEM of the generated CA is written for the system under test to trust it.
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// A certificate signed by a generated CA.
+    pub fn self_signed() -> Self {
+        TlsConfig::default()
+    }
+
+    pub fn from_files(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        TlsConfig {
+            cert: Some(cert.into()),
+            key: Some(key.into()),
+            ..TlsConfig::default()
+        }
+    }
+
+    pub fn with_names(mut self, names: &[&str]) -> Self {
+        self.names = names.iter().map(|n| n.to_string()).collect();
+        self
+    }
+
+    /// Verifies the client certificates against the CA of the PEM file.
+    pub fn with_client_ca(mut self, client_ca: impl Into<PathBuf>) -> Self {
+        self.client_ca = Some(client_ca.into());
+        self.require_client_cert = true;
+        self
+    }
+
+    /// Verifies the client certificates against the generated CA, see [`CertificateAuthority::issue`].
+    pub fn with_client_cert(mut self) -> Self {
+        self.require_client_cert = true;
+        self
+    }
+
+    pub fn with_ca_file(mut self, ca_file: impl Into<PathBuf>) -> Self {
+        self.ca_file = Some(ca_file.into());
+        self
+    }
+
+    fn generates(&self) -> bool {
+        self.cert.is_none() || (self.require_client_cert && self.client_ca.is_none())
+    }
+}
+
+/// The PEM of a certificate and of its private key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IssuedCertificate {
+    pub cert_pem: String,
+    pub key_pem: String,
+}
+
+fn failed(e: impl std::fmt::Display) -> ActorError {
+    ActorError::StartupError(format!("TLS: {}", e))
+}
+
+/// A CA generated for the tests, it signs the server certificate and the client ones.
+#[derive(Clone)]
+pub struct CertificateAuthority {
+    cert: Arc<Certificate>,
+    key: Arc<KeyPair>,
+}
+
+impl CertificateAuthority {
+    pub fn generate(name: &str) -> ActorResult<Self> {
+        let mut params = CertificateParams::new(Vec::<String>::new()).map_err(failed)?;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, format!("Parallax {} CA", name));
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let key = KeyPair::generate().map_err(failed)?;
+        let cert = params.self_signed(&key).map_err(failed)?;
+        Ok(CertificateAuthority {
+            cert: Arc::new(cert),
+            key: Arc::new(key),
+        })
+    }
+
+    /// The certificate to trust.
+    pub fn pem(&self) -> String {
+        self.cert.pem()
+    }
+
+    fn der(&self) -> CertificateDer<'static> {
+        self.cert.der().clone()
+    }
+
+    /// A certificate for the names (DNS names or IP addresses),
+    /// usable by a server or, when `client` is set, by a client.
+    pub fn issue(&self, names: &[&str], client: bool) -> ActorResult<IssuedCertificate> {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let mut params = CertificateParams::new(names.clone()).map_err(failed)?;
+        if let Some(name) = names.first() {
+            params.distinguished_name.push(DnType::CommonName, name);
+        }
+        params.extended_key_usages = vec![match client {
+            true => ExtendedKeyUsagePurpose::ClientAuth,
+            false => ExtendedKeyUsagePurpose::ServerAuth,
+        }];
+        let key = KeyPair::generate().map_err(failed)?;
+        let cert = params
+            .signed_by(&key, &self.cert, &self.key)
+            .map_err(failed)?;
+        Ok(IssuedCertificate {
+            cert_pem: cert.pem(),
+            key_pem: key.serialize_pem(),
+        })
+    }
+}
+
+/// The CA generated by an https server, none before its start or with given certificates.
+/// Its [`CertificateAuthority::pem`] is trusted by the clients,
+/// which get their certificates from [`CertificateAuthority::issue`].
+#[derive(Debug, Message)]
+#[rtype(result = "Option<CertificateAuthority>")]
+pub struct GetCertificateAuthority;
+
+fn read_pem(path: &Path) -> ActorResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| failed(format!("Can not read {}: {}", path.display(), e)))
+}
+
+fn certs(pem: &[u8]) -> ActorResult<Vec<CertificateDer<'static>>> {
+    rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(failed)
+}
+
+/// The rustls configuration of a server, the CA is generated when the config needs one.
+pub(crate) fn server_config(
+    key: &str,
+    config: &TlsConfig,
+    ca: &mut Option<CertificateAuthority>,
+) -> ActorResult<ServerConfig> {
+    if config.generates() && ca.is_none() {
+        let generated = CertificateAuthority::generate(key)?;
+        if let Some(path) = &config.ca_file {
+            std::fs::write(path, generated.pem()).map_err(|e| {
+                failed(format!("Can not write the CA to {}: {}", path.display(), e))
+            })?;
+        }
+        *ca = Some(generated);
+    }
+
+    let (chain, private_key) = match (&config.cert, &config.key, ca.as_ref()) {
+        (Some(cert), Some(key), _) => {
+            let chain = certs(&read_pem(cert)?)?;
+            let key = rustls_pemfile::private_key(&mut &read_pem(key)?[..])
+                .map_err(failed)?
+                .ok_or_else(|| failed(format!("No private key in {}", key.display())))?;
+            (chain, key)
+        }
+        (None, None, Some(ca)) => {
+            let names: Vec<&str> = match config.names.is_empty() {
+                true => LOCAL_NAMES.to_vec(),
+                false => config.names.iter().map(String::as_str).collect(),
+            };
+            let issued = ca.issue(&names, false)?;
+            let key = rustls_pemfile::private_key(&mut issued.key_pem.as_bytes())
+                .map_err(failed)?
+                .ok_or_else(|| failed("The generated key is not a PEM"))?;
+            (certs(issued.cert_pem.as_bytes())?, key)
+        }
+        _ => return Err(failed("The certificate and the key go together")),
+    };
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(failed)?;
+    let builder = if config.require_client_cert {
+        let mut roots = RootCertStore::empty();
+        match (&config.client_ca, ca.as_ref()) {
+            (Some(path), _) => {
+                for cert in certs(&read_pem(path)?)? {
+                    roots.add(cert).map_err(failed)?;
+                }
+            }
+            (None, Some(ca)) => roots.add(ca.der()).map_err(failed)?,
+            (None, None) => return Err(failed("No CA to verify the client certificates")),
+        }
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(failed)?;
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    builder.with_single_cert(chain, private_key).map_err(failed)
+}
@@ -0,0 +1,487 @@
+use crate::stub::{ScenarioState, Stub, StubRequest, StubResponse, Stubs, ValueMatcher, url_query};
+use actor::{ActorError, ActorResult, ActorResultVoid};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The priority of the mappings without one, as in WireMock.
+const DEFAULT_PRIORITY: i32 = 5;
+
+/// A mapping file holds a single mapping or a list of them.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MappingFile {
+    Many { mappings: Vec<Mapping> },
+    One(Box<Mapping>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Mapping {
+    id: Option<String>,
+    uuid: Option<String>,
+    priority: Option<i32>,
+    request: MappingRequest,
+    #[serde(default)]
+    response: MappingResponse,
+    scenario_name: Option<String>,
+    required_scenario_state: Option<String>,
+    new_scenario_state: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MappingRequest {
+    method: Option<String>,
+    url: Option<String>,
+    url_path: Option<String>,
+    url_pattern: Option<String>,
+    url_path_pattern: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, Value>,
+    #[serde(default)]
+    query_parameters: BTreeMap<String, Value>,
+    #[serde(default)]
+    body_patterns: Vec<Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MappingResponse {
+    status: Option<u16>,
+    #[serde(default)]
+    headers: BTreeMap<String, Value>,
+    body: Option<String>,
+    json_body: Option<Value>,
+    body_file_name: Option<String>,
+    fixed_delay_milliseconds: Option<u64>,
+    /// The bodies and the headers are templates with `response-template` only.
+    #[serde(default)]
+    transformers: Vec<String>,
+}
+
+fn unsupported(what: impl std::fmt::Display) -> ActorError {
+    ActorError::StartupError(format!("Unsupported matcher {}", what))
+}
+
+fn exact_path(path: &str) -> String {
+    format!("^{}$", regex::escape(path))
+}
+
+fn value_matcher(value: &Value) -> ActorResult<ValueMatcher> {
+    let text = |key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+    let case_insensitive = value
+        .get("caseInsensitive")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    if let Some(expected) = text("equalTo") {
+        return Ok(match case_insensitive {
+            true => ValueMatcher::Matches {
+                matches: format!("(?i){}", regex::escape(&expected)),
+            },
+            false => ValueMatcher::EqualTo(expected),
+        });
+    }
+    if let Some(contains) = text("contains") {
+        return Ok(ValueMatcher::Contains { contains });
+    }
+    if let Some(matches) = text("matches") {
+        return Ok(ValueMatcher::Matches { matches });
+    }
+    if let Some(absent) = value.get("absent").and_then(Value::as_bool) {
+        return Ok(ValueMatcher::Absent { absent });
+    }
+    Err(unsupported(value))
+}
+
+fn matchers(values: &BTreeMap<String, Value>) -> ActorResult<BTreeMap<String, ValueMatcher>> {
+    values
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), value_matcher(value)?)))
+        .collect()
+}
+
+impl MappingRequest {
+    fn into_request(self) -> ActorResult<StubRequest> {
+        let mut query = matchers(&self.query_parameters)?;
+        // the exact url rejects the query parameters it does not list
+        let exact_query = self.url.is_some();
+        let path = match (
+            self.url,
+            self.url_path,
+            self.url_pattern,
+            self.url_path_pattern,
+        ) {
+            (Some(url), ..) => {
+                let (path, params) = url.split_once('?').unwrap_or((&url, ""));
+                for (name, value) in url_query(params) {
+                    query.insert(name, ValueMatcher::EqualTo(value));
+                }
+                exact_path(path)
+            }
+            (_, Some(path), ..) => exact_path(&path),
+            // the query is matched separately, the patterns apply to the path only
+            (_, _, Some(pattern), _) | (.., Some(pattern)) => format!("^(?:{})$", pattern),
+            _ => "^.*$".to_string(),
+        };
+        let mut request = StubRequest {
+            method: self.method.filter(|m| !m.eq_ignore_ascii_case("ANY")),
+            path,
+            headers: matchers(&self.headers)?
+                .into_iter()
+                .map(|(name, m)| (name.to_lowercase(), m))
+                .collect(),
+            query,
+            exact_query,
+            body: None,
+            body_text: None,
+        };
+        for pattern in self.body_patterns {
+            match pattern.get("equalToJson") {
+                // the extra fields of the request are ignored
+                Some(json) if request.body.is_none() => {
+                    request.body = Some(match json {
+                        Value::String(text) => serde_json::from_str(text).map_err(|e| {
+                            ActorError::StartupError(format!("Invalid equalToJson: {}", e))
+                        })?,
+                        json => json.clone(),
+                    });
+                }
+                None if request.body_text.is_none() => {
+                    request.body_text = Some(value_matcher(&pattern)?);
+                }
+                _ => return Err(unsupported(&pattern)),
+            }
+        }
+        Ok(request)
+    }
+}
+
+impl MappingResponse {
+    fn into_response(self, files: &Path) -> ActorResult<StubResponse> {
+        let body = match (self.body, self.json_body, self.body_file_name) {
+            (Some(text), ..) => Some(Value::String(text)),
+            (_, Some(json), _) => Some(json),
+            (.., Some(name)) => {
+                let path = files.join(&name);
+                let bytes = std::fs::read(&path).map_err(|e| {
+                    ActorError::StartupError(format!("Can not read {}: {}", path.display(), e))
+                })?;
+                let text = String::from_utf8(bytes).map_err(|_| {
+                    ActorError::StartupError(format!(
+                        "Unsupported binary body file {}",
+                        path.display()
+                    ))
+                })?;
+                Some(Value::String(text))
+            }
+            _ => None,
+        };
+        Ok(StubResponse {
+            status: self.status.unwrap_or(200),
+            headers: self
+                .headers
+                .into_iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        Value::String(text) => text,
+                        Value::Array(values) => values
+                            .iter()
+                            .map(|v| v.as_str().map(str::to_string).unwrap_or(v.to_string()))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        value => value.to_string(),
+                    };
+                    (name, value)
+                })
+                .collect(),
+            body,
+            delay_ms: self.fixed_delay_milliseconds.unwrap_or(0),
+            templated: self.transformers.iter().any(|t| t == "response-template"),
+        })
+    }
+}
+
+fn json_files(dir: &Path, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if !path.ends_with("__files") {
+                json_files(&path, found)?;
+            }
+        } else if path.extension().is_some_and(|e| e == "json") {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Converts the WireMock mappings of a directory to stubs. The mappings are read from
+/// `<root>/mappings` (or `<root>` itself without it) and the body files from `<root>/__files`.
+///
+/// The url, the path and the patterns, the `equalTo`, `contains`, `matches` and `absent` matchers
+/// of the headers, the query and the body, `equalToJson` (ignoring the extra fields), the status,
+/// the headers, the bodies, the fixed delay, the `response-template` transformer and the scenarios
+/// are supported, the other matchers and the binary body files fail the load.
+pub fn load_mappings(root: &Path) -> ActorResult<Vec<Stub>> {
+    let mappings = root.join("mappings");
+    let dir = if mappings.is_dir() { &mappings } else { root };
+    let files = root.join("__files");
+    let mut paths = vec![];
+    json_files(dir, &mut paths)
+        .map_err(|e| ActorError::StartupError(format!("Can not read {}: {}", dir.display(), e)))?;
+    paths.sort();
+
+    let mut stubs = vec![];
+    for path in paths {
+        let failed = |e: String| ActorError::StartupError(format!("{}: {}", path.display(), e));
+        let text = std::fs::read_to_string(&path).map_err(|e| failed(e.to_string()))?;
+        let file: MappingFile = serde_json::from_str(&text).map_err(|e| failed(e.to_string()))?;
+        let mappings = match file {
+            MappingFile::Many { mappings } => mappings,
+            MappingFile::One(mapping) => vec![*mapping],
+        };
+        let stem = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .with_extension("")
+            .display()
+            .to_string();
+        for (idx, mapping) in mappings.into_iter().enumerate() {
+            let id = mapping
+                .id
+                .or(mapping.uuid)
+                .unwrap_or_else(|| format!("{}#{}", stem, idx));
+            stubs.push(Stub {
+                id,
+                priority: mapping.priority.unwrap_or(DEFAULT_PRIORITY),
+                request: mapping
+                    .request
+                    .into_request()
+                    .map_err(|e| failed(e.to_string()))?,
+                response: mapping
+                    .response
+                    .into_response(&files)
+                    .map_err(|e| failed(e.to_string()))?,
+                scenario: mapping.scenario_name.map(|name| ScenarioState {
+                    name,
+                    required_state: mapping.required_scenario_state,
+                    new_state: mapping.new_scenario_state,
+                }),
+            });
+        }
+    }
+    Ok(stubs)
+}
+
+/// The files under the root with their modification times and sizes.
+fn snapshot(root: &Path, found: &mut Vec<(PathBuf, Option<SystemTime>, u64)>) {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return;
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.is_dir() {
+            snapshot(&path, found);
+        } else if let Ok(meta) = path.metadata() {
+            found.push((path, meta.modified().ok(), meta.len()));
+        }
+    }
+}
+
+/// The stubs loaded from a mapping directory, reloaded when a file changes.
+#[derive(Debug)]
+pub(crate) struct Mappings {
+    root: PathBuf,
+    loaded: Vec<String>,
+    files: Vec<(PathBuf, Option<SystemTime>, u64)>,
+}
+
+impl Mappings {
+    pub fn new(root: PathBuf) -> Self {
+        Mappings {
+            root,
+            loaded: vec![],
+            files: vec![],
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn files(&self) -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+        let mut files = vec![];
+        snapshot(&self.root, &mut files);
+        files.sort();
+        files
+    }
+
+    pub fn changed(&self) -> bool {
+        self.files() != self.files
+    }
+
+    /// Replaces the stubs loaded before, they are kept when the mappings are invalid.
+    pub fn load(&mut self, stubs: &Stubs) -> ActorResultVoid {
+        self.files = self.files();
+        let loaded = load_mappings(&self.root)?;
+        let ids = loaded.iter().map(|s| s.id.clone()).collect();
+        stubs.swap(&self.loaded, loaded)?;
+        self.loaded = ids;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::serve_stubs;
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, test};
+    use serde_json::json;
+
+    #[actix::test]
+    async fn load_and_reload() {
+        let root = std::env::temp_dir().join(format!("parallax-wiremock-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("mappings/orders")).unwrap();
+        std::fs::create_dir_all(root.join("__files")).unwrap();
+        std::fs::write(root.join("__files/recipe.xml"), "<recipe speed=\"10\"/>").unwrap();
+        std::fs::write(
+            root.join("mappings/recipe.json"),
+            json!({
+                "request": { "method": "GET", "url": "/recipes/7?format=xml" },
+                "response": { "status": 200, "bodyFileName": "recipe.xml",
+                              "headers": { "Content-Type": "application/xml" } }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let orders = json!({ "mappings": [
+            {
+                "id": "create",
+                "request": {
+                    "method": "POST",
+                    "urlPathPattern": "/orders/[0-9]+",
+                    "headers": { "X-Plant": { "equalTo": "LINE-1", "caseInsensitive": true } },
+                    "bodyPatterns": [ { "equalToJson": "{\"product\": \"gear\"}" } ]
+                },
+                "response": { "status": 201, "jsonBody": { "created": true } }
+            },
+            {
+                "id": "echo",
+                "request": { "method": "GET", "urlPath": "/echo" },
+                "response": { "body": "{{request.path}}" }
+            },
+            {
+                "id": "echo-templated",
+                "request": { "method": "GET", "urlPath": "/echo/templated" },
+                "response": { "body": "{{request.path}}", "transformers": ["response-template"] }
+            },
+            {
+                "id": "fallback",
+                "priority": 10,
+                "request": { "method": "ANY" },
+                "response": { "status": 404, "body": "unknown" }
+            }
+        ]});
+        std::fs::write(root.join("mappings/orders/create.json"), orders.to_string()).unwrap();
+
+        let stubs = Stubs::default();
+        let mut mappings = Mappings::new(root.clone());
+        mappings.load(&stubs).unwrap();
+        assert_eq!(stubs.list().len(), 5);
+        assert!(!mappings.changed());
+
+        let served = stubs.clone();
+        let app = test::init_service(App::new().wrap(from_fn(move |req, next| {
+            serve_stubs(served.clone(), crate::DEFAULT_BODY_LIMIT, req, next)
+        })))
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/recipes/7?format=xml")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "application/xml"
+        );
+        assert_eq!(test::read_body(resp).await, "<recipe speed=\"10\"/>");
+        // the exact url takes no other query parameters
+        let req = test::TestRequest::get()
+            .uri("/recipes/7?format=xml&line=1")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        // the bodies are templates with the response-template transformer only
+        let req = test::TestRequest::get().uri("/echo").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(test::read_body(resp).await, "{{request.path}}");
+        let req = test::TestRequest::get().uri("/echo/templated").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(test::read_body(resp).await, "/echo/templated");
+        let req = test::TestRequest::post()
+            .uri("/orders/12")
+            .insert_header(("x-plant", "line-1"))
+            .set_json(json!({"product": "gear", "qty": 3}))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::CREATED
+        );
+
+        std::fs::remove_file(root.join("mappings/orders/create.json")).unwrap();
+        std::fs::write(
+            root.join("mappings/broken.json"),
+            json!({ "request": { "bodyPatterns": [ { "matchesXPath": "/recipe" } ] } }).to_string(),
+        )
+        .unwrap();
+        assert!(mappings.changed());
+        assert!(
+            mappings
+                .load(&stubs)
+                .unwrap_err()
+                .to_string()
+                .contains("broken.json")
+        );
+        assert_eq!(stubs.list().len(), 5);
+
+        std::fs::remove_file(root.join("mappings/broken.json")).unwrap();
+        std::fs::write(
+            root.join("__files/logo.png"),
+            [0x89, b'P', b'N', b'G', 0xff],
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("mappings/logo.json"),
+            json!({
+                "request": { "url": "/logo.png" },
+                "response": { "bodyFileName": "logo.png" }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert!(
+            mappings
+                .load(&stubs)
+                .unwrap_err()
+                .to_string()
+                .contains("Unsupported binary body file")
+        );
+
+        std::fs::remove_file(root.join("mappings/logo.json")).unwrap();
+        mappings.load(&stubs).unwrap();
+        assert_eq!(stubs.list().len(), 1);
+        let req = test::TestRequest::post().uri("/orders/12").to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
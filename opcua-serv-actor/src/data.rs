@@ -1,14 +1,9 @@
 use actor::{ActorError, ActorResultVoid};
 use opcua::server::address_space::AddressSpace;
-use opcua::server::prelude::{
-    NodeBase, ObjectBuilder, ObjectTypeBuilder, VariableBuilder, VariableTypeBuilder,
-};
+use opcua::server::prelude::{ObjectBuilder, VariableBuilder};
 use opcua::server::server::Server;
 use opcua::sync::RwLock;
-use opcua::types::{
-    DataTypeId, LocalizedText, MethodAttributes, NodeId, QualifiedName, VariableAttributes, Variant,
-};
-use std::collections::HashMap;
+use opcua::types::{DataTypeId, LocalizedText, NodeId, QualifiedName, Variant};
 use std::sync::Arc;
 
 pub type NamespaceIndex = u16;
@@ -17,16 +12,14 @@ pub type NamespaceUri = String;
 #[derive(Debug, Clone)]
 pub struct Namespace(pub NamespaceIndex, pub NamespaceUri);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct IdGenerator {
     nesting: Vec<String>,
 }
 
 impl IdGenerator {
     pub fn new() -> Self {
-        Self {
-            nesting: Vec::new(),
-        }
+        Self::default()
     }
     pub fn push<T: Into<String>>(&mut self, node: T) {
         self.nesting.push(node.into());
@@ -307,14 +300,14 @@ impl ServerStructure {
     pub fn process_server(&self, server: Arc<RwLock<Server>>) -> ActorResultVoid {
         for namespace in &self.namespaces {
             if namespace.0 == 0 {
-                return Err(
-                    ActorError::StartupError("Namespace index cannot be 0".to_string()).into(),
-                );
+                return Err(ActorError::StartupError(
+                    "Namespace index cannot be 0".to_string(),
+                ));
             }
             if namespace.1.is_empty() {
-                return Err(
-                    ActorError::StartupError("Namespace URI  cannot be empty".to_string()).into(),
-                );
+                return Err(ActorError::StartupError(
+                    "Namespace URI  cannot be empty".to_string(),
+                ));
             }
         }
 
@@ -1,33 +1,108 @@
 use crate::data::ServerStructure;
-use actix::{Actor, ActorContext, AsyncContext, Context, Handler, Message, WrapFuture};
+use actix::{
+    Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, Message, MessageResult,
+    WrapFuture,
+};
+use actor::clock::SimClock;
+use actor::fault::{Fault, FaultMessage, Faults};
+use actor::recording::{Exchange, TrafficRecorder};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
+use actor::trace::{Direction, Protocol, Traced};
 use actor::{ActorError, ActorResult, ActorResultVoid, ActorServiceMessage};
 use opcua::client::prelude::Config;
 use opcua::server::config::ServerConfig;
 use opcua::server::prelude::Server;
 use opcua::server::server::Server as InnerServer;
 use opcua::sync::RwLock;
-use opcua::types::{DateTime, NodeId, Variant};
-use std::path::{Path, PathBuf};
+use opcua::types::{DataTypeId, DateTime, Identifier, NodeId, StatusCode, Variant, VariantTypeId};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
+use utils::gauges::metrics::Metrics;
 
 pub mod data;
 pub mod macros;
+#[cfg(test)]
+mod tests;
+
+/// How often the server checks whether it has to go offline or back online, in the real time
+/// since the fault windows themselves follow the simulated one.
+const OFFLINE_POLL: Duration = Duration::from_millis(100);
 
 pub struct OpcuaServer {
     key: String,
     server: Arc<RwLock<InnerServer>>,
+    lifecycle: Lifecycle,
+    clock: SimClock,
+    faults: Faults,
+    /// The server task is running.
+    serving: bool,
+    offline: bool,
 }
 
 impl OpcuaServer {
-    pub fn new(
-        id: impl Into<String>,
-        cfg: &PathBuf,
-        structure: ServerStructure,
-    ) -> ActorResult<Self> {
+    pub fn new(id: impl Into<String>, cfg: &Path, structure: ServerStructure) -> ActorResult<Self> {
         let key = id.into();
         let server = Arc::new(RwLock::new(try_to_build_server(key.as_str(), cfg)?));
         structure.process_server(server.clone())?;
-        Ok(OpcuaServer { key, server })
+        Ok(OpcuaServer {
+            lifecycle: Lifecycle::new(&key),
+            faults: Faults::new(&key),
+            key,
+            server,
+            clock: SimClock::global(),
+            serving: false,
+            offline: false,
+        })
+    }
+
+    /// Stamps the value updates with the given clock instead of the global one.
+    pub fn with_clock(mut self, clock: SimClock) -> Self {
+        self.faults = self.faults.with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    fn is_listening(&self) -> bool {
+        let serv = self.server.read();
+        let state = serv.server_state();
+        state.read().is_running()
+    }
+
+    fn serve(&mut self, ctx: &mut Context<Self>) {
+        self.serving = true;
+        let serv = self.server.clone();
+        ctx.spawn(
+            async move {
+                InnerServer::new_server_task(serv).await;
+            }
+            .into_actor(self)
+            .map(|_, act, _| act.serving = false),
+        );
+    }
+
+    /// Aborts the server while an offline fault is active and starts it again afterwards.
+    fn check_offline(&mut self, ctx: &mut Context<Self>) {
+        if !matches!(
+            self.lifecycle.state(),
+            ActorState::Running | ActorState::Degraded
+        ) {
+            return;
+        }
+        let offline = self.faults.active().contains(&Fault::Offline);
+        if offline && !self.offline {
+            log::info!("[{}] Going offline", self.key);
+            self.offline = true;
+            self.server.write().abort();
+            self.lifecycle.degrade("offline");
+        } else if !offline && self.offline && !self.serving {
+            log::info!("[{}] Going back online", self.key);
+            self.offline = false;
+            self.server.read().server_state().write().abort = false;
+            self.lifecycle.set(ActorState::Starting);
+            self.serve(ctx);
+        }
     }
 }
 fn try_to_build_server(id: &str, config: &Path) -> ActorResult<InnerServer> {
@@ -38,6 +113,23 @@ fn try_to_build_server(id: &str, config: &Path) -> ActorResult<InnerServer> {
 
 impl Actor for OpcuaServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>())
+                .with(ctx.address().recipient::<UpdateValueMessage>())
+                .with(ctx.address().recipient::<Traced<UpdateValueMessage>>())
+                .with(ctx.address().recipient::<DataTypeOf>())
+                .with(ctx.address().recipient::<FaultMessage>()),
+        );
+        ctx.run_interval(OFFLINE_POLL, |act, ctx| act.check_offline(ctx));
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.lifecycle.set(ActorState::Stopped);
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
 }
 
 impl Handler<ActorServiceMessage> for OpcuaServer {
@@ -47,16 +139,13 @@ impl Handler<ActorServiceMessage> for OpcuaServer {
         match msg {
             ActorServiceMessage::Start => {
                 log::info!("Starting server {}", self.key);
-                let serv = self.server.clone();
-                ctx.spawn(
-                    async move {
-                        InnerServer::new_server_task(serv).await;
-                    }
-                    .into_actor(self),
-                );
+                self.lifecycle.set(ActorState::Starting);
+                self.serve(ctx);
             }
             ActorServiceMessage::Stop => {
                 log::info!("Stopping server {}", self.key);
+                self.lifecycle.set(ActorState::Stopping);
+                self.server.write().abort();
                 ctx.stop();
             }
         }
@@ -65,19 +154,99 @@ impl Handler<ActorServiceMessage> for OpcuaServer {
     }
 }
 
+impl Handler<ActorStatusMessage> for OpcuaServer {
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Self::Context) -> Self::Result {
+        // the server task does not report back, so the readiness is taken from the server state
+        if self.lifecycle.state() == ActorState::Starting && self.is_listening() {
+            self.lifecycle.set(ActorState::Running);
+        }
+        MessageResult(self.lifecycle.status())
+    }
+}
+
+impl Handler<FaultMessage> for OpcuaServer {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: FaultMessage, ctx: &mut Self::Context) -> Self::Result {
+        self.faults.handle(msg)?;
+        self.check_offline(ctx);
+        Ok(())
+    }
+}
+
 impl Handler<UpdateValueMessage> for OpcuaServer {
     type Result = ActorResultVoid;
 
     fn handle(&mut self, msg: UpdateValueMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.update_value(msg)
+    }
+}
+
+impl Handler<Traced<UpdateValueMessage>> for OpcuaServer {
+    type Result = ActorResultVoid;
+
+    fn handle(
+        &mut self,
+        msg: Traced<UpdateValueMessage>,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        msg.trace.record(
+            &self.key,
+            Protocol::OpcUa,
+            Direction::Inbound,
+            format!("write {}", msg.message.node_id),
+        );
+        self.update_value(msg.message)
+    }
+}
+
+impl OpcuaServer {
+    fn update_value(&mut self, msg: UpdateValueMessage) -> ActorResultVoid {
         let UpdateValueMessage { node_id, value } = msg;
         let serv = self.server.write();
         let addr_space_ref = serv.address_space();
         let mut space = addr_space_ref.write();
-        let res =
-            space.set_variable_value(node_id.clone(), value, &DateTime::now(), &DateTime::now());
+        let now = DateTime::from(self.clock.now());
+        let (data_type, json) = variant_json(&value);
+        let metrics = Metrics::global();
+        metrics.counter(&self.key, "opcua_writes").inc();
+        // the address space takes any variant, a mismatch would change the type the clients see
+        if let Some(declared) = space.find_variable(node_id.clone()).map(|v| v.data_type())
+            && let Ok(expected) = VariantTypeId::try_from(&declared)
+            && expected != VariantTypeId::Variant
+            && expected != value.type_id()
+        {
+            metrics.counter(&self.key, "opcua_write_errors").inc();
+            return Err(ActorError::RuntimeError(format!(
+                "The node {} is a {:?}, not a {:?}",
+                node_id,
+                expected,
+                value.type_id()
+            )));
+        }
+        let res = if self.faults.draw().contains(&Fault::BadStatus) {
+            space.find_variable_mut(node_id.clone()).is_some_and(|v| {
+                v.set_value_direct(value, StatusCode::BadCommunicationError, &now, &now)
+                    .is_ok()
+            })
+        } else {
+            space.set_variable_value(node_id.clone(), value, &now, &now)
+        };
         if res {
+            TrafficRecorder::global().record(
+                &self.key,
+                Direction::Inbound,
+                Exchange::OpcUa {
+                    node: node_id.to_string(),
+                    data_type: data_type.to_string(),
+                    value: json,
+                },
+            );
             Ok(())
         } else {
+            metrics.counter(&self.key, "opcua_write_errors").inc();
             Err(ActorError::RuntimeError(
                 "Failed to update the value".into(),
             ))
@@ -85,9 +254,51 @@ impl Handler<UpdateValueMessage> for OpcuaServer {
     }
 }
 
+impl Handler<DataTypeOf> for OpcuaServer {
+    type Result = ActorResult<DataTypeId>;
+
+    fn handle(&mut self, msg: DataTypeOf, _ctx: &mut Self::Context) -> Self::Result {
+        let serv = self.server.read();
+        let addr_space_ref = serv.address_space();
+        let space = addr_space_ref.read();
+        let declared = space
+            .find_variable(msg.0.clone())
+            .map(|v| v.data_type())
+            .ok_or_else(|| ActorError::RuntimeError(format!("No variable {}", msg.0)))?;
+        match declared.identifier {
+            Identifier::Numeric(id) if declared.namespace == 0 => DataTypeId::try_from(id).ok(),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            ActorError::RuntimeError(format!("The data type {} is not a built-in one", declared))
+        })
+    }
+}
+
+/// The OPC UA type name and the json form of the value, the other types are kept as text.
+fn variant_json(value: &Variant) -> (&'static str, serde_json::Value) {
+    match value {
+        Variant::Boolean(v) => ("Boolean", (*v).into()),
+        Variant::Int16(v) => ("Int16", (*v).into()),
+        Variant::Int32(v) => ("Int32", (*v).into()),
+        Variant::Int64(v) => ("Int64", (*v).into()),
+        Variant::UInt16(v) => ("UInt16", (*v).into()),
+        Variant::UInt32(v) => ("UInt32", (*v).into()),
+        Variant::UInt64(v) => ("UInt64", (*v).into()),
+        Variant::Float(v) => ("Float", (*v).into()),
+        Variant::Double(v) => ("Double", (*v).into()),
+        v => ("String", v.to_string().into()),
+    }
+}
+
 #[derive(Debug, Message)]
 #[rtype(result = "ActorResultVoid")]
-struct UpdateValueMessage {
-    node_id: NodeId,
-    value: Variant,
+pub struct UpdateValueMessage {
+    pub node_id: NodeId,
+    pub value: Variant,
 }
+
+/// Asks for the declared data type of a variable, e.g. to convert a value before writing it.
+#[derive(Debug, Message)]
+#[rtype(result = "ActorResult<DataTypeId>")]
+pub struct DataTypeOf(pub NodeId);
@@ -0,0 +1,158 @@
+use crate::data::{Namespace, Node, ServerStructure};
+use crate::{OpcuaServer, UpdateValueMessage};
+use actix::{Actor, Addr};
+use actor::ActorServiceMessage;
+use actor::fault::{Fault, FaultInjection, FaultMessage};
+use actor::status::{ActorState, ActorStatusMessage, wait_for_state};
+use opcua::server::prelude::{Config, ServerBuilder};
+use opcua::server::server::Server;
+use opcua::sync::RwLock;
+use opcua::types::{
+    DataTypeId, NodeId, NumericRange, QualifiedName, StatusCode, TimestampsToReturn, Variant,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+/// A free port, released for the server to bind it.
+async fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// An anonymous server config without security in a directory of its own.
+fn config(name: &str, port: u16) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("parallax-opcua-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("server.conf");
+    ServerBuilder::new_anonymous(name)
+        .host_and_port("127.0.0.1", port)
+        .pki_dir(dir.join("pki"))
+        .create_sample_keypair(false)
+        .config()
+        .save(&path)
+        .unwrap();
+    path
+}
+
+fn speed() -> NodeId {
+    NodeId::new(1, "Line1.Speed")
+}
+
+/// The value and the status code of the speed node.
+fn read_speed(server: &RwLock<Server>) -> (Option<Variant>, Option<StatusCode>) {
+    let space = server.read().address_space();
+    let space = space.read();
+    let value = space.find_variable(speed()).unwrap().value(
+        TimestampsToReturn::Neither,
+        NumericRange::None,
+        &QualifiedName::null(),
+        0.0,
+    );
+    (value.value, value.status)
+}
+
+async fn start(name: &str, port: u16) -> (Addr<OpcuaServer>, Arc<RwLock<Server>>, PathBuf) {
+    let path = config(name, port);
+    let structure = ServerStructure::new(
+        vec![Namespace(1, "urn:line".to_string())],
+        vec![Node::variable(
+            speed(),
+            QualifiedName::new(1, "Speed"),
+            "Speed".into(),
+            DataTypeId::Double,
+            -1,
+            Variant::Double(10.0),
+        )],
+    );
+    let actor = OpcuaServer::new(name, &path, structure).unwrap();
+    let inner = actor.server.clone();
+    let server = actor.start();
+    server
+        .send(ActorServiceMessage::Start)
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_state(
+        &server.clone().recipient(),
+        ActorState::Running,
+        Duration::from_secs(2),
+    )
+    .await
+    .unwrap();
+    (server, inner, path)
+}
+
+#[actix::test]
+async fn bad_status() {
+    let (server, inner, path) = start("faulty_plc", free_port().await).await;
+    let write = |value| UpdateValueMessage {
+        node_id: speed(),
+        value: Variant::Double(value),
+    };
+    server
+        .send(FaultMessage::Inject(FaultInjection::new(Fault::BadStatus)))
+        .await
+        .unwrap()
+        .unwrap();
+    server.send(write(20.0)).await.unwrap().unwrap();
+    assert_eq!(
+        read_speed(&inner),
+        (
+            Some(Variant::Double(20.0)),
+            Some(StatusCode::BadCommunicationError)
+        )
+    );
+
+    server.send(FaultMessage::Clear).await.unwrap().unwrap();
+    server.send(write(30.0)).await.unwrap().unwrap();
+    let written = read_speed(&inner);
+    assert_eq!(written.0, Some(Variant::Double(30.0)));
+    assert!(written.1.is_none_or(|s| s.is_good()));
+
+    server
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
+
+#[actix::test]
+async fn offline_and_back_online() {
+    let port = free_port().await;
+    let (server, _, path) = start("offline_plc", port).await;
+    let addr = format!("127.0.0.1:{}", port);
+    assert!(TcpStream::connect(&addr).await.is_ok());
+
+    server
+        .send(FaultMessage::Inject(FaultInjection::new(Fault::Offline)))
+        .await
+        .unwrap()
+        .unwrap();
+    let status = server.clone().recipient();
+    wait_for_state(&status, ActorState::Degraded, Duration::from_secs(1))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(TcpStream::connect(&addr).await.is_err());
+
+    // the server task is started again once the fault is cleared
+    server.send(FaultMessage::Clear).await.unwrap().unwrap();
+    wait_for_state(&status, ActorState::Running, Duration::from_secs(2))
+        .await
+        .unwrap();
+    assert!(TcpStream::connect(&addr).await.is_ok());
+    assert_eq!(
+        server.send(ActorStatusMessage).await.unwrap().state,
+        ActorState::Running
+    );
+
+    server
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(path.parent().unwrap());
+}
@@ -0,0 +1,80 @@
+use actor::trace::TraceRecorder;
+use clap::Args;
+use std::path::PathBuf;
+use std::time::Duration;
+use topology::error::{TopologyError, TopologyResult};
+use utils::gauges::metrics::Metrics;
+use utils::gauges::report::{BenchmarkReport, Thresholds};
+
+#[derive(Args, Debug, Clone)]
+pub struct ReportArgs {
+    /// Writes the benchmark report (JSON, Markdown and HTML) to the directory when the topology
+    /// is stopped or the scenario is finished.
+    #[arg(long)]
+    report_dir: Option<PathBuf>,
+    /// The JSON report of a previous run to compare with, the run fails on regressions.
+    #[arg(long, requires = "report_dir")]
+    baseline: Option<PathBuf>,
+    /// The allowed growth of the latency percentiles in percent.
+    #[arg(long, default_value_t = 10.0)]
+    latency_threshold: f64,
+    /// The allowed drop of the throughput in percent.
+    #[arg(long, default_value_t = 10.0)]
+    throughput_threshold: f64,
+    /// The allowed number of new errors per actor.
+    #[arg(long, default_value_t = 0)]
+    error_threshold: u64,
+}
+
+impl ReportArgs {
+    fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            latency_pct: self.latency_threshold,
+            throughput_pct: self.throughput_threshold,
+            errors: self.error_threshold,
+        }
+    }
+
+    /// Writes the report of the run and fails when it regressed against the baseline.
+    pub fn write(&self, name: &str, elapsed: Duration) -> TopologyResult<()> {
+        let Some(dir) = self.report_dir.as_ref() else {
+            return Ok(());
+        };
+        let report = BenchmarkReport::from_metrics(name, &Metrics::global(), elapsed)
+            .with_protocols(TraceRecorder::global().message_counts());
+        let comparison = match self.baseline.as_ref() {
+            Some(path) => {
+                let baseline = BenchmarkReport::load(path).map_err(|e| {
+                    TopologyError(format!("Can not read the baseline {:?}: {}", path, e))
+                })?;
+                Some(report.compare(&baseline, &self.thresholds()))
+            }
+            None => None,
+        };
+        let paths = report
+            .write(dir, comparison.as_ref())
+            .map_err(|e| TopologyError(format!("Can not write the report to {:?}: {}", dir, e)))?;
+        log::info!("[{}] The benchmark report is written to {:?}", name, paths);
+
+        match comparison {
+            Some(c) if c.is_regression() => {
+                for r in c.regressions.iter() {
+                    log::warn!(
+                        "[{}] {} regressed: {:.2} -> {:.2} ({:+.1}%)",
+                        r.actor,
+                        r.metric,
+                        r.baseline,
+                        r.current,
+                        r.change_pct
+                    );
+                }
+                Err(TopologyError(format!(
+                    "{} KPIs regressed against the baseline {}",
+                    c.regressions.len(),
+                    c.baseline
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use actix_web::{HttpResponse, web};
+use actor::status::ActorStatus;
+use http_serv_actor::RouterConfig;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use topology::error::{TopologyError, TopologyResult};
+use topology::launch::LaunchedActor;
+
+/// The lifecycle state of an actor as it is reported by the control endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub key: String,
+    pub kind: String,
+    pub state: String,
+    pub uptime_ms: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+impl StatusReport {
+    fn new(kind: &str, status: ActorStatus) -> Self {
+        StatusReport {
+            key: status.key,
+            kind: kind.to_string(),
+            state: status.state.to_string(),
+            uptime_ms: status.uptime.map(|d| d.as_millis() as u64),
+            last_error: status.last_error,
+        }
+    }
+}
+
+pub async fn collect(actors: &[LaunchedActor]) -> Vec<StatusReport> {
+    let mut reports = Vec::with_capacity(actors.len());
+    for actor in actors {
+        let report = match actor.status().await {
+            Ok(status) => StatusReport::new(actor.kind, status),
+            Err(e) => StatusReport {
+                key: actor.key.clone(),
+                kind: actor.kind.to_string(),
+                state: "Unreachable".to_string(),
+                uptime_ms: None,
+                last_error: Some(e.to_string()),
+            },
+        };
+        reports.push(report);
+    }
+    reports
+}
+
+pub fn print(reports: &[StatusReport]) {
+    let width = reports
+        .iter()
+        .map(|r| r.key.len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    println!(
+        "{:width$}  {:14}  {:9}  {:>9}  ERROR",
+        "KEY", "KIND", "STATE", "UPTIME"
+    );
+    for r in reports {
+        let uptime = r
+            .uptime_ms
+            .map(|ms| format!("{:.1}s", ms as f64 / 1000.0))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:width$}  {:14}  {:9}  {:>9}  {}",
+            r.key,
+            r.kind,
+            r.state,
+            uptime,
+            r.last_error.as_deref().unwrap_or("")
+        );
+    }
+}
+
+struct ControlState {
+    actors: Vec<LaunchedActor>,
+    shutdown: UnboundedSender<()>,
+}
+
+/// Routes of the control server started by `parallax run`:
+/// `GET /status` lists the actor states and `POST /stop` shuts the topology down.
+pub fn routes(actors: Vec<LaunchedActor>, shutdown: UnboundedSender<()>) -> RouterConfig {
+    let state = web::Data::new(ControlState { actors, shutdown });
+    Arc::new(move |cfg| {
+        cfg.app_data(state.clone())
+            .route("/status", web::get().to(status_handler))
+            .route("/stop", web::post().to(stop_handler));
+    })
+}
+
+async fn status_handler(state: web::Data<ControlState>) -> HttpResponse {
+    HttpResponse::Ok().json(collect(&state.actors).await)
+}
+
+async fn stop_handler(state: web::Data<ControlState>) -> HttpResponse {
+    match state.shutdown.send(()) {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(_) => HttpResponse::Conflict().body("The topology is already stopping"),
+    }
+}
+
+fn unreachable(addr: SocketAddr, e: reqwest::Error) -> TopologyError {
+    TopologyError(format!("No topology is running at {}: {}", addr, e))
+}
+
+pub async fn remote_status(addr: SocketAddr) -> TopologyResult<Vec<StatusReport>> {
+    reqwest::get(format!("http://{}/status", addr))
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| unreachable(addr, e))?
+        .json()
+        .await
+        .map_err(|e| TopologyError(format!("Invalid status response: {}", e)))
+}
+
+pub async fn remote_stop(addr: SocketAddr) -> TopologyResult<()> {
+    reqwest::Client::new()
+        .post(format!("http://{}/stop", addr))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| unreachable(addr, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::Actor;
+    use actor::ActorServiceMessage;
+    use actor::status::{ActorState, wait_for_state};
+    use http_serv_actor::{BaseHttpServer, BoundAddresses};
+    use std::time::Duration;
+    use topology::Topology;
+
+    #[actix::test]
+    async fn status_and_stop() {
+        let topology = Topology::from_yaml(
+            r#"
+name: control
+actors:
+  - key: worker
+    kind: process
+    exe: sleep
+    cmd: "10"
+"#,
+        )
+        .unwrap();
+        let running = topology.start().await.unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let control = BaseHttpServer::new(
+            "parallax-control",
+            "127.0.0.1",
+            0,
+            Some(routes(running.actors().to_vec(), tx)),
+        )
+        .start();
+        control
+            .send(ActorServiceMessage::Start)
+            .await
+            .unwrap()
+            .unwrap();
+        wait_for_state(
+            &control.clone().recipient(),
+            ActorState::Running,
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+        let addr = control.send(BoundAddresses).await.unwrap()[0];
+
+        let reports = remote_status(addr).await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].key, "worker");
+        assert_eq!(reports[0].state, "Running");
+
+        remote_stop(addr).await.unwrap();
+        assert!(rx.recv().await.is_some());
+
+        running.stop().await;
+        control
+            .send(ActorServiceMessage::Stop)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+mod bench;
+mod control;
+
+use actix::Actor;
+use actor::ActorServiceMessage;
+use actor::diagram::SequenceDiagram;
+use actor::recording::TrafficRecorder;
+use actor::trace::TraceRecorder;
+use bench::ReportArgs;
+use clap::{Parser, Subcommand};
+use http_serv_actor::BaseHttpServer;
+use scenario::Scenario;
+use scenario::replay::{Replay, Timing};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+use topology::Topology;
+use topology::error::{TopologyError, TopologyResult};
+use utils::logging::{self, LogConfig};
+
+/// Runs simulated factories described in a topology file.
+#[derive(Parser)]
+#[command(name = "parallax", version, about)]
+struct Cli {
+    /// Address of the control endpoint of a running topology.
+    #[arg(long, global = true, default_value = "127.0.0.1:7878")]
+    control: SocketAddr,
+    /// Logs JSON lines instead of text.
+    #[arg(long, global = true)]
+    log_json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Starts the actors of the topology and runs until SIGINT or `parallax stop`.
+    Run {
+        topology: PathBuf,
+        /// Records the protocol traffic to the file (json lines) when the topology is stopped.
+        #[arg(long)]
+        record: Option<PathBuf>,
+        /// Writes the PlantUML and Mermaid sequence diagrams of the run to the directory
+        /// when the topology is stopped.
+        #[arg(long)]
+        diagram: Option<PathBuf>,
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    /// Prints the lifecycle states of the actors of the running topology.
+    Status,
+    /// Gracefully stops the running topology.
+    Stop,
+    /// Runs the steps of a scenario file and reports the result of each step.
+    Scenario {
+        scenario: PathBuf,
+        /// Writes the PlantUML and Mermaid sequence diagrams of the scenario to the directory.
+        #[arg(long)]
+        diagram: Option<PathBuf>,
+        #[command(flatten)]
+        report: ReportArgs,
+    },
+    /// Replays a recording against a system under test and compares the responses.
+    Replay {
+        recording: PathBuf,
+        /// The target of an actor of the recording, e.g. `machine=http://10.0.0.5:8080`.
+        #[arg(long = "target", value_parser = parse_target)]
+        targets: Vec<(String, String)>,
+        /// Replays the pauses faster by the factor, `0` replays without pauses.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+    /// Checks the topology file without starting anything.
+    Validate { topology: PathBuf },
+}
+
+#[actix::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let log_config = LogConfig::default();
+    logging::init(if cli.log_json {
+        log_config.json()
+    } else {
+        log_config
+    });
+    let result = match cli.command {
+        Command::Run {
+            topology,
+            record,
+            diagram,
+            report,
+        } => run(topology, cli.control, record, diagram, report).await,
+        Command::Status => control::remote_status(cli.control)
+            .await
+            .map(|reports| control::print(&reports)),
+        Command::Stop => control::remote_stop(cli.control).await.map(|_| {
+            println!("The topology at {} is stopping", cli.control);
+        }),
+        Command::Scenario {
+            scenario,
+            diagram,
+            report,
+        } => run_scenario(scenario, diagram, report).await,
+        Command::Replay {
+            recording,
+            targets,
+            speed,
+        } => replay(recording, targets, speed).await,
+        Command::Validate { topology } => Topology::load(&topology)
+            .and_then(|t| t.validate().map(|_| t))
+            .map(|t| {
+                let spec = t.spec();
+                println!(
+                    "{} is valid: {} actors, {} links",
+                    spec.name,
+                    spec.actors.len(),
+                    spec.links.len()
+                );
+            }),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(
+    path: PathBuf,
+    control_addr: SocketAddr,
+    record: Option<PathBuf>,
+    diagram: Option<PathBuf>,
+    report: ReportArgs,
+) -> TopologyResult<()> {
+    let topology = Topology::load(&path)?;
+    if record.is_some() {
+        TrafficRecorder::global().start();
+    }
+    let running = topology.start().await?;
+    let started = Instant::now();
+    log::info!("[{}] The topology is started", running.name());
+    control::print(&control::collect(running.actors()).await);
+
+    let (shutdown_tx, mut shutdown_rx) = tokio::sync::mpsc::unbounded_channel();
+    let control = BaseHttpServer::new(
+        "parallax-control",
+        control_addr.ip().to_string(),
+        control_addr.port(),
+        Some(control::routes(running.actors().to_vec(), shutdown_tx)),
+    )
+    .start();
+    if let Err(e) = control.send(ActorServiceMessage::Start).await? {
+        running.stop().await;
+        return Err(TopologyError(format!(
+            "Can not start the control endpoint on {}: {}",
+            control_addr, e
+        )));
+    }
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => log::info!("[{}] SIGINT received", running.name()),
+        _ = shutdown_rx.recv() => log::info!("[{}] Stop requested", running.name()),
+    }
+
+    log::info!("[{}] Stopping the topology", running.name());
+    let name = running.name().to_string();
+    running.stop().await;
+    let _ = control.send(ActorServiceMessage::Stop).await;
+    let elapsed = started.elapsed();
+    if let Some(path) = record {
+        let recorder = TrafficRecorder::global();
+        recorder.stop();
+        recorder
+            .save(&path)
+            .map_err(|e| TopologyError(format!("Can not write the recording {:?}: {}", path, e)))?;
+        log::info!("The recording is written to {:?}", path);
+    }
+    if let Some(dir) = diagram {
+        write_diagrams(&dir, &name)?;
+    }
+    report.write(&name, elapsed)
+}
+
+/// Writes the sequence diagrams of every span recorded so far.
+fn write_diagrams(dir: &Path, name: &str) -> TopologyResult<()> {
+    let paths = SequenceDiagram::for_run(&TraceRecorder::global(), name)
+        .write_all(dir, name)
+        .map_err(|e| TopologyError(format!("Can not write the diagrams to {:?}: {}", dir, e)))?;
+    log::info!("The diagrams are written to {:?}", paths);
+    Ok(())
+}
+
+async fn run_scenario(
+    path: PathBuf,
+    diagram: Option<PathBuf>,
+    bench: ReportArgs,
+) -> TopologyResult<()> {
+    let scenario = Scenario::load(&path).map_err(|e| TopologyError(e.0))?;
+    let started = Instant::now();
+    let report = scenario.run().await;
+    let elapsed = started.elapsed();
+    print!("{}", report);
+    if let Some(dir) = diagram {
+        write_diagrams(&dir, &report.name)?;
+    }
+    // written for the failed scenarios too, their KPIs tell where they got stuck
+    let written = bench.write(&report.name, elapsed);
+    if report.passed() {
+        written
+    } else {
+        Err(TopologyError(format!(
+            "The scenario {} failed",
+            report.name
+        )))
+    }
+}
+
+fn parse_target(text: &str) -> Result<(String, String), String> {
+    text.split_once('=')
+        .map(|(actor, url)| (actor.to_string(), url.to_string()))
+        .ok_or_else(|| format!("Expected actor=url, got {}", text))
+}
+
+async fn replay(path: PathBuf, targets: Vec<(String, String)>, speed: f64) -> TopologyResult<()> {
+    let timing = if speed == 1.0 {
+        Timing::Original
+    } else if speed > 0.0 {
+        Timing::Compressed(speed)
+    } else {
+        Timing::Asap
+    };
+    let mut replay = Replay::load(&path)
+        .map_err(|e| TopologyError(e.0))?
+        .with_timing(timing);
+    for (actor, url) in targets {
+        replay = replay.target(actor, &url).map_err(|e| TopologyError(e.0))?;
+    }
+    let report = replay.run().await;
+    print!("{}", report);
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(TopologyError(format!(
+            "The replay of {:?} differs from the recording",
+            path
+        )))
+    }
+}
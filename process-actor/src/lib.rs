@@ -1,11 +1,9 @@
-use actix::{Actor, ActorContext, AsyncContext, Context, Handler, SpawnHandle, WrapFuture};
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, MessageResult, WrapFuture};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
 use actor::{ActorResultVoid, ActorServiceMessage};
-use std::sync::Arc;
-use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
-use tokio::time::sleep;
-use utils::logger_on;
 
 pub struct ProcessActor {
     name: String,
@@ -13,12 +11,15 @@ pub struct ProcessActor {
     cmd: String,
     env: Vec<(String, String)>,
     child_abort: Option<tokio::sync::oneshot::Sender<()>>,
+    lifecycle: Lifecycle,
 }
 
 impl ProcessActor {
     pub fn new<T: Into<String>>(name: T, exe: T, cmd: T, env: Vec<(T, T)>) -> Self {
+        let name = name.into();
         Self {
-            name: name.into(),
+            lifecycle: Lifecycle::new(&name),
+            name,
             exe: exe.into(),
             cmd: cmd.into(),
             env: env.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
@@ -31,23 +32,36 @@ impl Actor for ProcessActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::register(
+            Registration::new(&self.name, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>()),
+        );
         let exe = self.exe.clone();
         let cmd = self.cmd.clone();
         let env = self.env.clone();
         let name = self.name.clone();
+        let lifecycle = self.lifecycle.clone();
         let (abort_tx, mut abort_rx) = tokio::sync::oneshot::channel();
         self.child_abort = Some(abort_tx);
-        let handle = ctx.spawn(
+        self.lifecycle.set(ActorState::Starting);
+        ctx.spawn(
             async move {
-                let mut child = Command::new(exe)
+                let mut child = match Command::new(exe)
                     .arg(cmd)
                     .envs(env)
                     .stdout(std::process::Stdio::piped())
                     .stderr(std::process::Stdio::piped())
                     .kill_on_drop(true)
                     .spawn()
-                    .expect("Failed to spawn the process");
-
+                {
+                    Ok(child) => child,
+                    Err(e) => {
+                        log::error!(target:&name, "Failed to spawn the process: {}", e);
+                        lifecycle.fail(format!("Failed to spawn the process: {}", e));
+                        return;
+                    }
+                };
+                lifecycle.set(ActorState::Running);
 
                 let stdout = child.stdout.take().expect("Failed to capture stdout");
                 let stderr = child.stderr.take().expect("Failed to capture stderr");
@@ -64,12 +78,25 @@ impl Actor for ProcessActor {
                                         log::error!(target:&name,"Error: {}", line);
                                     }
                                     status = child.wait() => {
+                                        // the output written right before the exit may be still unread
+                                        while let Ok(Some(line)) = stdout_reader.next_line().await {
+                                            log::info!(target:&name, "Output: {}", line);
+                                        }
+                                        while let Ok(Some(line)) = stderr_reader.next_line().await {
+                                            log::error!(target:&name, "Error: {}", line);
+                                        }
                                         log::info!(target:&name,"The process exited with status: {:?}", status);
+                                        match status {
+                                            Ok(s) if s.success() => lifecycle.set(ActorState::Stopped),
+                                            Ok(s) => lifecycle.fail(format!("The process exited with {}", s)),
+                                            Err(e) => lifecycle.fail(e),
+                                        }
                                         break;
                                     }
                                     _ = &mut abort_rx => {
                                         log::info!(target:&name,"Received abort signal, killing Python process...");
                                         let _ = child.kill().await;
+                                        lifecycle.set(ActorState::Stopped);
                                         break;
                                     }
                                 }
@@ -78,6 +105,13 @@ impl Actor for ProcessActor {
                 .into_actor(self),
         );
     }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if self.lifecycle.state() != ActorState::Failed {
+            self.lifecycle.set(ActorState::Stopped);
+        }
+        ActorRegistry::deregister(&self.name, ctx.address().recipient());
+    }
 }
 impl Handler<ActorServiceMessage> for ProcessActor {
     type Result = ActorResultVoid;
@@ -85,14 +119,15 @@ impl Handler<ActorServiceMessage> for ProcessActor {
     fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Context<Self>) -> Self::Result {
         match msg {
             ActorServiceMessage::Stop => {
+                self.lifecycle.set(ActorState::Stopping);
                 if let Some(abort_tx) = self.child_abort.take() {
                     let _ = abort_tx.send(());
                 }
                 ctx.stop();
-                log::info!("SSH server stopped");
+                log::info!("[{}] Process actor stopped", self.name);
             }
             ActorServiceMessage::Start => {
-                log::info!("Process actor started");
+                log::info!("[{}] Process actor started", self.name);
             }
         }
 
@@ -100,14 +135,32 @@ impl Handler<ActorServiceMessage> for ProcessActor {
     }
 }
 
+impl Handler<ActorStatusMessage> for ProcessActor {
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
 #[actix::test]
 async fn test_process() -> ActorResultVoid {
-    logger_on();
+    use actor::status::wait_for_state;
+    use std::time::Duration;
+    let logs = utils::logging::LogCapture::start();
 
     let actor = ProcessActor::new("Test", "python3", "--version", vec![]).start();
+    assert!(
+        logs.wait_for("Test", "Output: Python", Duration::from_secs(5))
+            .await
+    );
 
-    tokio::time::sleep(Duration::from_secs(1)).await;
+    wait_for_state(
+        &actor.clone().recipient(),
+        ActorState::Stopped,
+        Duration::from_secs(5),
+    )
+    .await?;
 
-    actor.send(ActorServiceMessage::Stop);
-    Ok(())
+    actor.send(ActorServiceMessage::Stop).await.unwrap()
 }
@@ -0,0 +1,282 @@
+#[cfg(test)]
+mod tests;
+pub mod toxic;
+
+use crate::toxic::{Ending, Stream, Toxic, Toxics, pump};
+use actix::{
+    Actor, ActorContext, AsyncContext, Context, Handler, Message, MessageResult, SpawnHandle,
+    WrapFuture,
+};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
+use actor::{ActorError, ActorResultVoid, ActorServiceMessage};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::AbortHandle;
+use utils::gauges::metrics::Metrics;
+
+/// A TCP proxy between the system under test and a simulated endpoint
+/// degrading the link with the toxics given at runtime through [`ToxicMessage`].
+pub struct TcpProxy {
+    key: String,
+    host: String,
+    port: u16,
+    upstream: String,
+    toxics: Toxics,
+    listener: Option<SpawnHandle>,
+    connections: Arc<Mutex<Vec<AbortHandle>>>,
+    bound: Arc<Mutex<Option<SocketAddr>>>,
+    lifecycle: Lifecycle,
+}
+
+impl TcpProxy {
+    /// Listens on `host:port` and forwards the connections to the upstream `host:port`.
+    pub fn new(
+        key: impl Into<String>,
+        host: impl Into<String>,
+        port: u16,
+        upstream: impl Into<String>,
+    ) -> Self {
+        let key = key.into();
+        TcpProxy {
+            lifecycle: Lifecycle::new(&key),
+            key,
+            host: host.into(),
+            port,
+            upstream: upstream.into(),
+            toxics: Toxics::default(),
+            listener: None,
+            connections: Arc::new(Mutex::new(vec![])),
+            bound: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn close_connections(&self) {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        for connection in connections.drain(..) {
+            connection.abort();
+        }
+    }
+}
+
+async fn serve(key: String, mut client: TcpStream, upstream: String, toxics: Toxics) {
+    let metrics = Metrics::global();
+    metrics.counter(&key, "tcp_connections").inc();
+    if toxics.resets() {
+        let _ = client.set_zero_linger();
+        return;
+    }
+    let mut upstream = match TcpStream::connect(&upstream).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            log::error!("[{}] Can not connect to {}: {}", key, upstream, e);
+            metrics.counter(&key, "tcp_connect_errors").inc();
+            return;
+        }
+    };
+    let (ending_up, ending_down) = {
+        let (mut client_read, mut client_write) = client.split();
+        let (mut upstream_read, mut upstream_write) = upstream.split();
+        tokio::join!(
+            pump(
+                &mut client_read,
+                &mut upstream_write,
+                Stream::Upstream,
+                &toxics
+            ),
+            pump(
+                &mut upstream_read,
+                &mut client_write,
+                Stream::Downstream,
+                &toxics
+            ),
+        )
+    };
+    if matches!(ending_up, Ok(Ending::Reset)) || matches!(ending_down, Ok(Ending::Reset)) {
+        log::info!("[{}] Reset the connection", key);
+        let _ = client.set_zero_linger();
+        let _ = upstream.set_zero_linger();
+    } else if let Err(e) = ending_up.and(ending_down) {
+        log::debug!("[{}] The connection is closed: {}", key, e);
+    }
+}
+
+impl Actor for TcpProxy {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>())
+                .with(ctx.address().recipient::<ToxicMessage>())
+                .with(ctx.address().recipient::<ListToxics>())
+                .with(ctx.address().recipient::<BoundAddress>()),
+        );
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.close_connections();
+        if self.lifecycle.state() != ActorState::Failed {
+            self.lifecycle.set(ActorState::Stopped);
+        }
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
+}
+
+impl Handler<ActorServiceMessage> for TcpProxy {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Context<Self>) -> Self::Result {
+        match msg {
+            ActorServiceMessage::Start => {
+                if self.listener.is_some() {
+                    return Err(ActorError::StartupError(
+                        "The proxy is already running".to_string(),
+                    ));
+                }
+                let addr = format!("{}:{}", self.host, self.port);
+                log::info!(
+                    "[{}] Starting the proxy on {} to {}",
+                    self.key,
+                    addr,
+                    self.upstream
+                );
+                self.lifecycle.set(ActorState::Starting);
+                let key = self.key.clone();
+                let upstream = self.upstream.clone();
+                let toxics = self.toxics.clone();
+                let connections = self.connections.clone();
+                let lifecycle = self.lifecycle.clone();
+                let bound = self.bound.clone();
+                let listener = ctx.spawn(
+                    async move {
+                        let listener = match TcpListener::bind(&addr).await {
+                            Ok(l) => l,
+                            Err(e) => {
+                                log::error!("[{}] Failed to bind to {}: {}", key, addr, e);
+                                lifecycle.fail(format!("Failed to bind to {}: {}", addr, e));
+                                return;
+                            }
+                        };
+                        *bound.lock().unwrap_or_else(|e| e.into_inner()) =
+                            listener.local_addr().ok();
+                        lifecycle.set(ActorState::Running);
+                        loop {
+                            match listener.accept().await {
+                                Ok((client, peer)) => {
+                                    log::debug!("[{}] New connection from {}", key, peer);
+                                    let task = tokio::spawn(serve(
+                                        key.clone(),
+                                        client,
+                                        upstream.clone(),
+                                        toxics.clone(),
+                                    ));
+                                    let mut connections =
+                                        connections.lock().unwrap_or_else(|e| e.into_inner());
+                                    connections.retain(|c| !c.is_finished());
+                                    connections.push(task.abort_handle());
+                                }
+                                Err(e) => {
+                                    log::error!("[{}] Failed to accept a connection: {}", key, e);
+                                    tokio::time::sleep(Duration::from_millis(100)).await;
+                                }
+                            }
+                        }
+                    }
+                    .into_actor(self),
+                );
+                self.listener = Some(listener);
+            }
+            ActorServiceMessage::Stop => {
+                log::info!("[{}] Stopping the proxy", self.key);
+                self.lifecycle.set(ActorState::Stopping);
+                ctx.stop();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Handler<ActorStatusMessage> for TcpProxy {
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
+/// Changes the toxics of a proxy, they apply to the open connections immediately.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "ActorResultVoid")]
+pub enum ToxicMessage {
+    /// Adds the toxic or replaces the one with the same name.
+    Add {
+        name: String,
+        toxic: Toxic,
+        stream: Stream,
+    },
+    Remove(String),
+    Clear,
+}
+
+impl Handler<ToxicMessage> for TcpProxy {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: ToxicMessage, _ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            ToxicMessage::Add {
+                name,
+                toxic,
+                stream,
+            } => {
+                log::info!(
+                    "[{}] Add the toxic {}: {:?} {:?}",
+                    self.key,
+                    name,
+                    toxic,
+                    stream
+                );
+                self.toxics.add(name, toxic, stream);
+            }
+            ToxicMessage::Remove(name) => {
+                log::info!("[{}] Remove the toxic {}", self.key, name);
+                if !self.toxics.remove(&name) {
+                    return Err(ActorError::RuntimeError(format!("No toxic {}", name)));
+                }
+            }
+            ToxicMessage::Clear => {
+                log::info!("[{}] Clear the toxics", self.key);
+                self.toxics.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The toxics of a proxy with their names and streams.
+#[derive(Debug, Message)]
+#[rtype(result = "Vec<(String, Toxic, Stream)>")]
+pub struct ListToxics;
+
+impl Handler<ListToxics> for TcpProxy {
+    type Result = MessageResult<ListToxics>;
+
+    fn handle(&mut self, _msg: ListToxics, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.toxics.list())
+    }
+}
+
+/// The socket address the running proxy listens on, e.g. the port chosen for the port 0.
+#[derive(Debug, Message)]
+#[rtype(result = "Option<SocketAddr>")]
+pub struct BoundAddress;
+
+impl Handler<BoundAddress> for TcpProxy {
+    type Result = MessageResult<BoundAddress>;
+
+    fn handle(&mut self, _msg: BoundAddress, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(*self.bound.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
@@ -0,0 +1,233 @@
+use crate::toxic::{Stream, Toxic};
+use crate::{BoundAddress, ListToxics, TcpProxy, ToxicMessage};
+use actix::{Actor, Addr};
+use actor::ActorServiceMessage;
+use actor::status::{ActorState, wait_for_state};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn echo_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut read, mut write) = socket.split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+        }
+    });
+    addr
+}
+
+async fn echo(stream: &mut TcpStream, text: &str) -> std::io::Result<String> {
+    stream.write_all(text.as_bytes()).await?;
+    let mut buf = vec![0u8; text.len()];
+    stream.read_exact(&mut buf).await?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn add(name: &str, toxic: Toxic, stream: Stream) -> ToxicMessage {
+    ToxicMessage::Add {
+        name: name.to_string(),
+        toxic,
+        stream,
+    }
+}
+
+/// A running proxy to an echo server and its address.
+async fn echo_proxy(key: &str) -> (Addr<TcpProxy>, SocketAddr) {
+    let proxy = TcpProxy::new(key, "127.0.0.1", 0, echo_server().await).start();
+    proxy
+        .send(ActorServiceMessage::Start)
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_state(
+        &proxy.clone().recipient(),
+        ActorState::Running,
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+    let bound = proxy.send(BoundAddress).await.unwrap().unwrap();
+    (proxy, bound)
+}
+
+#[actix::test]
+async fn toxics() {
+    let upstream = echo_server().await;
+    let proxy = TcpProxy::new("plc-link", "127.0.0.1", 0, upstream).start();
+    assert_eq!(proxy.send(BoundAddress).await.unwrap(), None);
+    proxy
+        .send(ActorServiceMessage::Start)
+        .await
+        .unwrap()
+        .unwrap();
+    wait_for_state(
+        &proxy.clone().recipient(),
+        ActorState::Running,
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+    let bound = proxy.send(BoundAddress).await.unwrap().unwrap();
+    assert_ne!(bound.port(), 0);
+
+    let mut stream = TcpStream::connect(bound).await.unwrap();
+    assert_eq!(echo(&mut stream, "ping").await.unwrap(), "ping");
+
+    proxy
+        .send(add(
+            "slow",
+            Toxic::Latency {
+                latency_ms: 200,
+                jitter_ms: 0,
+            },
+            Stream::Downstream,
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let started = Instant::now();
+    assert_eq!(echo(&mut stream, "slow").await.unwrap(), "slow");
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert_eq!(proxy.send(ListToxics).await.unwrap().len(), 1);
+
+    proxy
+        .send(add("hole", Toxic::Blackhole, Stream::Both))
+        .await
+        .unwrap()
+        .unwrap();
+    let lost = tokio::time::timeout(Duration::from_millis(300), echo(&mut stream, "lost")).await;
+    assert!(lost.is_err());
+
+    proxy
+        .send(add("reset", Toxic::ResetPeer, Stream::Both))
+        .await
+        .unwrap()
+        .unwrap();
+    let mut buf = [0u8; 8];
+    let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+
+    proxy.send(ToxicMessage::Clear).await.unwrap().unwrap();
+    assert!(
+        proxy
+            .send(ToxicMessage::Remove("slow".to_string()))
+            .await
+            .unwrap()
+            .is_err()
+    );
+    let mut stream = TcpStream::connect(bound).await.unwrap();
+    assert_eq!(echo(&mut stream, "back").await.unwrap(), "back");
+
+    proxy
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[actix::test]
+async fn bandwidth_and_slicer() {
+    let (proxy, bound) = echo_proxy("plc-slow-link").await;
+    let mut stream = TcpStream::connect(bound).await.unwrap();
+    proxy
+        .send(add(
+            "narrow",
+            Toxic::Bandwidth {
+                bytes_per_sec: 10_000,
+            },
+            Stream::Downstream,
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let data = "x".repeat(5_000);
+    let started = Instant::now();
+    assert_eq!(echo(&mut stream, &data).await.unwrap(), data);
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(450), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+
+    // the chunks arrive one by one, far enough apart to be read separately
+    proxy
+        .send(ToxicMessage::Remove("narrow".to_string()))
+        .await
+        .unwrap()
+        .unwrap();
+    proxy
+        .send(add(
+            "slices",
+            Toxic::Slicer {
+                size: 4,
+                delay_ms: 100,
+            },
+            Stream::Downstream,
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    stream.write_all(b"abcdefghij").await.unwrap();
+    let mut chunks = vec![];
+    let mut buf = [0u8; 16];
+    while chunks.concat::<u8>().len() < 10 {
+        let n = stream.read(&mut buf).await.unwrap();
+        chunks.push(buf[..n].to_vec());
+    }
+    assert_eq!(
+        chunks,
+        vec![b"abcd".to_vec(), b"efgh".to_vec(), b"ij".to_vec()]
+    );
+
+    proxy
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[actix::test]
+async fn reset_cuts_the_delays() {
+    let (proxy, bound) = echo_proxy("plc-late-link").await;
+    let mut stream = TcpStream::connect(bound).await.unwrap();
+    proxy
+        .send(add(
+            "late",
+            Toxic::Latency {
+                latency_ms: 5_000,
+                jitter_ms: 0,
+            },
+            Stream::Downstream,
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    stream.write_all(b"late").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // the pending chunk is dropped with the connection rather than after the latency
+    let started = Instant::now();
+    proxy
+        .send(add("reset", Toxic::ResetPeer, Stream::Both))
+        .await
+        .unwrap()
+        .unwrap();
+    let mut buf = [0u8; 8];
+    let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    proxy
+        .send(ActorServiceMessage::Stop)
+        .await
+        .unwrap()
+        .unwrap();
+}
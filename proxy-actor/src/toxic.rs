@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// The direction of the bytes through the proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stream {
+    /// From the client to the upstream.
+    Upstream,
+    /// From the upstream to the client.
+    Downstream,
+    #[default]
+    Both,
+}
+
+impl Stream {
+    fn covers(&self, other: Stream) -> bool {
+        *self == Stream::Both || *self == other
+    }
+}
+
+/// A network impairment, the delays are in the wall time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Toxic {
+    /// Delays every chunk by the latency plus a random jitter up to `jitter_ms`.
+    Latency {
+        latency_ms: u64,
+        #[serde(default)]
+        jitter_ms: u64,
+    },
+    /// Limits the throughput.
+    Bandwidth { bytes_per_sec: u64 },
+    /// Splits the data into chunks of `size` bytes sent `delay_ms` apart.
+    Slicer {
+        size: usize,
+        #[serde(default)]
+        delay_ms: u64,
+    },
+    /// Resets the open connections and every new one right after it is accepted.
+    ResetPeer,
+    /// Swallows the data while the connections stay open.
+    Blackhole,
+}
+
+/// The toxics of a proxy by name, shared with the connections.
+/// The connections are notified of every change, so a reset hits the idle ones as well.
+#[derive(Debug, Clone)]
+pub(crate) struct Toxics {
+    inner: Arc<Mutex<BTreeMap<String, (Toxic, Stream)>>>,
+    changes: Arc<watch::Sender<()>>,
+}
+
+impl Default for Toxics {
+    fn default() -> Self {
+        Toxics {
+            inner: Arc::new(Mutex::new(BTreeMap::new())),
+            changes: Arc::new(watch::Sender::new(())),
+        }
+    }
+}
+
+impl Toxics {
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, (Toxic, Stream)>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn add(&self, name: String, toxic: Toxic, stream: Stream) {
+        self.lock().insert(name, (toxic, stream));
+        self.changes.send_replace(());
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        let removed = self.lock().remove(name).is_some();
+        self.changes.send_replace(());
+        removed
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+        self.changes.send_replace(());
+    }
+
+    pub fn list(&self) -> Vec<(String, Toxic, Stream)> {
+        self.lock()
+            .iter()
+            .map(|(name, (toxic, stream))| (name.clone(), toxic.clone(), *stream))
+            .collect()
+    }
+
+    fn of(&self, stream: Stream) -> Vec<Toxic> {
+        self.lock()
+            .values()
+            .filter(|(_, s)| s.covers(stream))
+            .map(|(t, _)| t.clone())
+            .collect()
+    }
+
+    pub fn resets(&self) -> bool {
+        self.lock().values().any(|(t, _)| *t == Toxic::ResetPeer)
+    }
+}
+
+/// How a direction of a connection ended.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Ending {
+    Closed,
+    Reset,
+}
+
+/// Waits out the delay of a toxic, `false` when a reset added meanwhile cut it short.
+async fn delay(duration: Duration, toxics: &Toxics, changes: &mut watch::Receiver<()>) -> bool {
+    let deadline = tokio::time::Instant::now() + duration;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => return true,
+            _ = changes.changed() => {
+                if toxics.resets() {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// Copies the bytes of one direction through the toxics until the end of the stream or a reset.
+pub(crate) async fn pump<R, W>(
+    from: &mut R,
+    to: &mut W,
+    stream: Stream,
+    toxics: &Toxics,
+) -> std::io::Result<Ending>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut changes = toxics.changes.subscribe();
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        if toxics.resets() {
+            return Ok(Ending::Reset);
+        }
+        let n = tokio::select! {
+            n = from.read(&mut buf) => n?,
+            _ = changes.changed() => continue,
+        };
+        if n == 0 {
+            to.shutdown().await?;
+            return Ok(Ending::Closed);
+        }
+        let active = toxics.of(stream);
+        if active.contains(&Toxic::Blackhole) {
+            continue;
+        }
+        let mut chunk_size = n;
+        let mut slice_delay = Duration::ZERO;
+        let mut rate = None;
+        for toxic in active.iter() {
+            match toxic {
+                Toxic::Latency {
+                    latency_ms,
+                    jitter_ms,
+                } => {
+                    let jitter = if *jitter_ms > 0 {
+                        rand::random::<u64>() % (jitter_ms + 1)
+                    } else {
+                        0
+                    };
+                    let latency = Duration::from_millis(latency_ms + jitter);
+                    if !delay(latency, toxics, &mut changes).await {
+                        return Ok(Ending::Reset);
+                    }
+                }
+                Toxic::Bandwidth { bytes_per_sec } => rate = Some((*bytes_per_sec).max(1)),
+                Toxic::Slicer { size, delay_ms } => {
+                    chunk_size = chunk_size.min((*size).max(1));
+                    slice_delay = Duration::from_millis(*delay_ms);
+                }
+                Toxic::ResetPeer | Toxic::Blackhole => {}
+            }
+        }
+        for (idx, chunk) in buf[..n].chunks(chunk_size).enumerate() {
+            let mut wait = if idx > 0 { slice_delay } else { Duration::ZERO };
+            if let Some(rate) = rate {
+                wait += Duration::from_secs_f64(chunk.len() as f64 / rate as f64);
+            }
+            if !wait.is_zero() && !delay(wait, toxics, &mut changes).await {
+                return Ok(Ending::Reset);
+            }
+            to.write_all(chunk).await?;
+        }
+        to.flush().await?;
+    }
+}
@@ -0,0 +1,63 @@
+use actor::ActorError;
+use std::fmt::{Display, Formatter};
+use topology::error::TopologyError;
+
+#[derive(Debug)]
+pub struct ScenarioError(pub String);
+pub type ScenarioResult<T> = Result<T, ScenarioError>;
+
+impl Display for ScenarioError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<ActorError> for ScenarioError {
+    fn from(e: ActorError) -> Self {
+        ScenarioError(e.to_string())
+    }
+}
+
+impl From<TopologyError> for ScenarioError {
+    fn from(e: TopologyError) -> Self {
+        ScenarioError(e.0)
+    }
+}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(e: std::io::Error) -> Self {
+        ScenarioError(e.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for ScenarioError {
+    fn from(e: serde_yaml::Error) -> Self {
+        ScenarioError(format!("Invalid yaml scenario: {}", e))
+    }
+}
+
+impl From<sqlx::Error> for ScenarioError {
+    fn from(e: sqlx::Error) -> Self {
+        ScenarioError(e.to_string())
+    }
+}
+
+impl From<actix::MailboxError> for ScenarioError {
+    fn from(e: actix::MailboxError) -> Self {
+        ScenarioError(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for ScenarioError {
+    fn from(e: reqwest::Error) -> Self {
+        ScenarioError(e.to_string())
+    }
+}
+
+impl From<russh::Error> for ScenarioError {
+    fn from(e: russh::Error) -> Self {
+        ScenarioError(e.to_string())
+    }
+}
@@ -0,0 +1,264 @@
+pub mod error;
+pub mod replay;
+pub mod step;
+#[cfg(test)]
+mod tests;
+
+use crate::error::ScenarioResult;
+use crate::step::{RunState, Step};
+use actor::trace::TraceRecorder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use utils::logging::LogCapture;
+
+/// A scripted end-to-end test: the steps are run in order until the first failure.
+///
+/// ```yaml
+/// name: order to machine
+/// steps:
+///   - start_topology: factory.yaml
+///   - publish: { topic: orders, payload: { "id": 1 } }
+///   - expect_http: { method: POST, path: /machine/start, within_ms: 2000 }
+///   - set_opcua: { actor: plc, node: "ns=2;s=Line1.Speed", value: 50 }
+///   - stop_topology
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    pub steps: Vec<Step>,
+    /// Relative paths in the steps are resolved against this directory.
+    #[serde(skip, default = "current_dir")]
+    base_dir: PathBuf,
+}
+
+fn current_dir() -> PathBuf {
+    PathBuf::from(".")
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_millis() as u64
+}
+
+impl Scenario {
+    pub fn new(name: impl Into<String>) -> Self {
+        Scenario {
+            name: name.into(),
+            steps: vec![],
+            base_dir: current_dir(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> ScenarioResult<Self> {
+        let path = path.as_ref();
+        let mut scenario = Scenario::from_yaml(&std::fs::read_to_string(path)?)?;
+        if let Some(dir) = path.parent() {
+            scenario.base_dir = dir.to_path_buf();
+        }
+        Ok(scenario)
+    }
+
+    pub fn from_yaml(text: &str) -> ScenarioResult<Self> {
+        Ok(serde_yaml::from_str(text)?)
+    }
+
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn start_topology(self, path: impl Into<PathBuf>) -> Self {
+        self.step(Step::StartTopology(path.into()))
+    }
+
+    pub fn stop_topology(self) -> Self {
+        self.step(Step::StopTopology)
+    }
+
+    pub fn publish(self, topic: impl Into<String>, payload: impl Into<serde_json::Value>) -> Self {
+        self.step(Step::Publish {
+            topic: topic.into(),
+            payload: payload.into(),
+        })
+    }
+
+    pub fn set_opcua(
+        self,
+        actor: impl Into<String>,
+        node: impl Into<String>,
+        value: impl Into<serde_json::Value>,
+    ) -> Self {
+        self.step(Step::SetOpcua {
+            actor: actor.into(),
+            node: node.into(),
+            value: value.into(),
+            data_type: None,
+        })
+    }
+
+    pub fn expect_http(
+        self,
+        method: impl Into<String>,
+        path: impl Into<String>,
+        within: Duration,
+    ) -> Self {
+        self.step(Step::ExpectHttp {
+            method: method.into(),
+            path: path.into(),
+            actor: None,
+            within_ms: millis(within),
+        })
+    }
+
+    pub fn expect_span(
+        self,
+        protocol: impl Into<String>,
+        detail: impl Into<String>,
+        within: Duration,
+    ) -> Self {
+        self.step(Step::ExpectSpan {
+            protocol: protocol.into(),
+            actor: None,
+            detail: Some(detail.into()),
+            within_ms: millis(within),
+        })
+    }
+
+    pub fn expect_log(
+        self,
+        target: impl Into<String>,
+        text: impl Into<String>,
+        within: Duration,
+    ) -> Self {
+        self.step(Step::ExpectLog {
+            target: target.into(),
+            text: text.into(),
+            within_ms: millis(within),
+        })
+    }
+
+    pub fn expect_db_row(
+        self,
+        url: impl Into<String>,
+        query: impl Into<String>,
+        within: Duration,
+    ) -> Self {
+        self.step(Step::ExpectDbRow {
+            url: url.into(),
+            query: query.into(),
+            within_ms: millis(within),
+        })
+    }
+
+    pub fn wait(self, duration: Duration) -> Self {
+        self.step(Step::WaitMs(millis(duration)))
+    }
+
+    /// Runs the steps, the steps after a failed one are skipped.
+    /// A topology left running by the scenario is stopped at the end.
+    pub async fn run(&self) -> ScenarioReport {
+        let mut state = RunState {
+            name: self.name.clone(),
+            base_dir: self.base_dir.clone(),
+            logs: LogCapture::start(),
+            running: None,
+            spans_from: TraceRecorder::global().sequence(),
+            matched_spans: HashSet::new(),
+        };
+        let mut failed = false;
+        let mut steps = vec![];
+        for step in self.steps.iter() {
+            if failed {
+                steps.push(StepReport::new(step, StepStatus::Skipped, 0));
+                continue;
+            }
+            log::info!("[{}] {}", self.name, step);
+            let started = Instant::now();
+            let status = match step.run(&mut state).await {
+                Ok(()) => StepStatus::Passed,
+                Err(e) => {
+                    log::error!("[{}] {} failed: {}", self.name, step, e);
+                    failed = true;
+                    StepStatus::Failed(e.0)
+                }
+            };
+            steps.push(StepReport::new(step, status, millis(started.elapsed())));
+        }
+        if let Some(running) = state.running.take() {
+            running.stop().await;
+        }
+        ScenarioReport {
+            name: self.name.clone(),
+            steps,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "error")]
+pub enum StepStatus {
+    Passed,
+    Failed(String),
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StepReport {
+    pub step: String,
+    #[serde(flatten)]
+    pub status: StepStatus,
+    pub elapsed_ms: u64,
+}
+
+impl StepReport {
+    fn new(step: &Step, status: StepStatus, elapsed_ms: u64) -> Self {
+        StepReport {
+            step: step.to_string(),
+            status,
+            elapsed_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub steps: Vec<StepReport>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|s| s.status == StepStatus::Passed)
+    }
+}
+
+impl Display for ScenarioReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}: {}",
+            self.name,
+            if self.passed() { "PASSED" } else { "FAILED" }
+        )?;
+        for (idx, s) in self.steps.iter().enumerate() {
+            match &s.status {
+                StepStatus::Passed => {
+                    writeln!(f, "  {:>2}. PASS {} ({}ms)", idx + 1, s.step, s.elapsed_ms)?
+                }
+                StepStatus::Failed(e) => writeln!(
+                    f,
+                    "  {:>2}. FAIL {} ({}ms): {}",
+                    idx + 1,
+                    s.step,
+                    s.elapsed_ms,
+                    e
+                )?,
+                StepStatus::Skipped => writeln!(f, "  {:>2}. SKIP {}", idx + 1, s.step)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+use crate::error::{ScenarioError, ScenarioResult};
+use actor::bus::EventBus;
+use actor::registry::ActorRegistry;
+use actor::trace::{Direction, Protocol, Span, TraceContext, TraceRecorder, Traced};
+use opcua::types::NodeId;
+use opcua_serv_actor::{DataTypeOf, UpdateValueMessage};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::Instant;
+use topology::launch::RunningTopology;
+use topology::{Payload, Topology};
+use utils::logging::LogCapture;
+
+/// How often the expectations are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+fn default_within() -> u64 {
+    1000
+}
+
+/// A step of a scenario. The expectations wait (in the wall time) up to `within_ms`
+/// for something to happen, the HTTP requests and spans recorded since the start of the run
+/// count as well but each one fulfils a single expectation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Starts the topology file, a relative path is resolved against the scenario file.
+    StartTopology(PathBuf),
+    StopTopology,
+    /// Publishes the payload on the event bus like a source actor does.
+    Publish {
+        topic: String,
+        payload: serde_json::Value,
+    },
+    /// Writes the value of an OPC UA node, e.g. `ns=2;s=Line1.Speed`.
+    /// The value is converted to `data_type` (e.g. `Int32`), by default to the type the node declares.
+    SetOpcua {
+        actor: String,
+        node: String,
+        value: serde_json::Value,
+        #[serde(default)]
+        data_type: Option<String>,
+    },
+    ExpectHttp {
+        method: String,
+        path: String,
+        #[serde(default)]
+        actor: Option<String>,
+        #[serde(default = "default_within")]
+        within_ms: u64,
+    },
+    /// Expects a recorded hop, the detail is matched as a substring.
+    ExpectSpan {
+        protocol: String,
+        #[serde(default)]
+        actor: Option<String>,
+        #[serde(default)]
+        detail: Option<String>,
+        #[serde(default = "default_within")]
+        within_ms: u64,
+    },
+    /// Expects the target (the actor key) to log the text.
+    ExpectLog {
+        target: String,
+        text: String,
+        #[serde(default = "default_within")]
+        within_ms: u64,
+    },
+    /// Expects the sqlite query to return at least one row.
+    ExpectDbRow {
+        url: String,
+        query: String,
+        #[serde(default = "default_within")]
+        within_ms: u64,
+    },
+    WaitMs(u64),
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::StartTopology(path) => write!(f, "start topology {}", path.display()),
+            Step::StopTopology => write!(f, "stop topology"),
+            Step::Publish { topic, .. } => write!(f, "publish on {}", topic),
+            Step::SetOpcua {
+                actor, node, value, ..
+            } => write!(f, "set {} {} to {}", actor, node, value),
+            Step::ExpectHttp {
+                method,
+                path,
+                within_ms,
+                ..
+            } => write!(f, "expect HTTP {} {} within {}ms", method, path, within_ms),
+            Step::ExpectSpan {
+                protocol,
+                detail,
+                within_ms,
+                ..
+            } => write!(
+                f,
+                "expect {} {} within {}ms",
+                protocol,
+                detail.as_deref().unwrap_or("message"),
+                within_ms
+            ),
+            Step::ExpectLog {
+                target,
+                text,
+                within_ms,
+            } => write!(
+                f,
+                "expect {} to log '{}' within {}ms",
+                target, text, within_ms
+            ),
+            Step::ExpectDbRow {
+                query, within_ms, ..
+            } => write!(f, "expect a row of '{}' within {}ms", query, within_ms),
+            Step::WaitMs(ms) => write!(f, "wait {}ms", ms),
+        }
+    }
+}
+
+/// The state shared by the steps of a run.
+pub(crate) struct RunState {
+    pub name: String,
+    pub base_dir: PathBuf,
+    pub logs: LogCapture,
+    pub running: Option<RunningTopology>,
+    /// The sequence number of the first span recorded during the run.
+    pub spans_from: u64,
+    /// The sequence numbers of the spans already matched by an expectation.
+    pub matched_spans: HashSet<u64>,
+}
+
+struct SpanMatcher<'a> {
+    protocol: &'a str,
+    actor: Option<&'a str>,
+    detail: Option<&'a str>,
+    exact: bool,
+}
+
+impl SpanMatcher<'_> {
+    fn matches(&self, span: &Span) -> bool {
+        span.protocol
+            .to_string()
+            .eq_ignore_ascii_case(self.protocol)
+            && self.actor.is_none_or(|a| a == span.actor)
+            && self.detail.is_none_or(|d| {
+                if self.exact {
+                    span.detail == d
+                } else {
+                    span.detail.contains(d)
+                }
+            })
+    }
+
+    /// The first matching span recorded since the start of the run and not matched yet.
+    fn find(&self, state: &RunState) -> Option<u64> {
+        TraceRecorder::global()
+            .spans_since(state.spans_from)
+            .into_iter()
+            .find(|(seq, span)| !state.matched_spans.contains(seq) && self.matches(span))
+            .map(|(seq, _)| seq)
+    }
+
+    /// Waits for a matching span, a span already recorded during the run matches too.
+    /// Every span matches at most one expectation.
+    async fn expect(&self, state: &mut RunState, within: Duration) -> ScenarioResult<()> {
+        let found = Cell::new(None);
+        wait_until(within, || {
+            found.set(self.find(state));
+            async { Ok(found.get().is_some()) }
+        })
+        .await?;
+        state.matched_spans.extend(found.get());
+        Ok(())
+    }
+}
+
+async fn wait_until<F, Fut>(within: Duration, check: F) -> ScenarioResult<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ScenarioResult<bool>>,
+{
+    let deadline = Instant::now() + within;
+    loop {
+        if check().await? {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(ScenarioError(format!("Timed out after {:?}", within)));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn payload_text(payload: &serde_json::Value) -> String {
+    match payload {
+        serde_json::Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn resolve(base_dir: &Path, path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base_dir.join(path)
+    }
+}
+
+impl Step {
+    pub(crate) async fn run(&self, state: &mut RunState) -> ScenarioResult<()> {
+        match self {
+            Step::StartTopology(path) => {
+                if state.running.is_some() {
+                    return Err(ScenarioError("A topology is already running".to_string()));
+                }
+                let topology = Topology::load(resolve(&state.base_dir, path))?;
+                state.running = Some(topology.start().await?);
+                Ok(())
+            }
+            Step::StopTopology => match state.running.take() {
+                Some(running) => {
+                    running.stop().await;
+                    Ok(())
+                }
+                None => Err(ScenarioError("No topology is running".to_string())),
+            },
+            Step::Publish { topic, payload } => {
+                let text = payload_text(payload);
+                let trace = TraceContext::new_root();
+                trace.record(&state.name, Protocol::Internal, Direction::Outbound, topic);
+                let bus = EventBus::global();
+                bus.publish(topic, Payload(text.clone())).await;
+                bus.publish(topic, Traced::new(trace, Payload(text))).await;
+                Ok(())
+            }
+            Step::SetOpcua {
+                actor,
+                node,
+                value,
+                data_type,
+            } => {
+                let node_id = NodeId::from_str(node)
+                    .map_err(|_| ScenarioError(format!("Invalid node id {}", node)))?;
+                let no_actor = || ScenarioError(format!("No OPC UA actor {}", actor));
+                let data_type = match data_type {
+                    Some(name) => topology::nodes::data_type(name)?,
+                    None => {
+                        ActorRegistry::lookup::<DataTypeOf>(actor)
+                            .await
+                            .ok_or_else(no_actor)?
+                            .send(DataTypeOf(node_id.clone()))
+                            .await??
+                    }
+                };
+                let value = topology::nodes::variant(data_type, value).ok_or_else(|| {
+                    ScenarioError(format!("The value {} is not a {:?}", value, data_type))
+                })?;
+                let recipient = ActorRegistry::lookup::<Traced<UpdateValueMessage>>(actor)
+                    .await
+                    .ok_or_else(no_actor)?;
+                let trace = TraceContext::new_root();
+                trace.record(
+                    &state.name,
+                    Protocol::OpcUa,
+                    Direction::Outbound,
+                    format!("write {}", node),
+                );
+                recipient
+                    .send(Traced::new(
+                        trace.child(),
+                        UpdateValueMessage { node_id, value },
+                    ))
+                    .await??;
+                Ok(())
+            }
+            Step::ExpectHttp {
+                method,
+                path,
+                actor,
+                within_ms,
+            } => {
+                let detail = format!("{} {}", method.to_uppercase(), path);
+                SpanMatcher {
+                    protocol: "Http",
+                    actor: actor.as_deref(),
+                    detail: Some(&detail),
+                    exact: true,
+                }
+                .expect(state, Duration::from_millis(*within_ms))
+                .await
+            }
+            Step::ExpectSpan {
+                protocol,
+                actor,
+                detail,
+                within_ms,
+            } => {
+                SpanMatcher {
+                    protocol,
+                    actor: actor.as_deref(),
+                    detail: detail.as_deref(),
+                    exact: false,
+                }
+                .expect(state, Duration::from_millis(*within_ms))
+                .await
+            }
+            Step::ExpectLog {
+                target,
+                text,
+                within_ms,
+            } => {
+                let within = Duration::from_millis(*within_ms);
+                if state.logs.wait_for(target, text, within).await {
+                    Ok(())
+                } else {
+                    Err(ScenarioError(format!("Timed out after {:?}", within)))
+                }
+            }
+            Step::ExpectDbRow {
+                url,
+                query,
+                within_ms,
+            } => {
+                let pool = sqlx::SqlitePool::connect(url).await?;
+                let res = wait_until(Duration::from_millis(*within_ms), || async {
+                    Ok(sqlx::query(query).fetch_optional(&pool).await?.is_some())
+                })
+                .await;
+                pool.close().await;
+                res
+            }
+            Step::WaitMs(ms) => {
+                tokio::time::sleep(Duration::from_millis(*ms)).await;
+                Ok(())
+            }
+        }
+    }
+}
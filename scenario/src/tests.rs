@@ -0,0 +1,356 @@
+use crate::replay::{Replay, ReplayOutcome, Timing};
+use crate::step::Step;
+use crate::{Scenario, StepStatus};
+use actix::{Actor, Context, Handler};
+use actor::bus::{EventBus, SubscribeOptions};
+use actor::recording::{Exchange, Interaction, TrafficRecorder};
+use actor::registry::ActorRegistry;
+use actor::trace::Direction;
+use chrono::Utc;
+use http_serv_actor::BoundAddresses;
+use opcua::server::prelude::{Config, ServerBuilder};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use topology::{Payload, Topology};
+
+const LINE: &str = r#"
+name: scenario-line
+actors:
+  - key: machine
+    kind: http
+    port: 0
+    routes:
+      - method: POST
+        path: /machine/start
+  - key: plc-boot
+    kind: process
+    exe: echo
+    cmd: READY
+"#;
+
+/// The url of the HTTP actor once it listens.
+async fn bound_url(key: &str) -> Option<String> {
+    let server = ActorRegistry::lookup::<BoundAddresses>(key).await?;
+    let bound = server.send(BoundAddresses).await.ok()?;
+    bound.first().map(|addr| format!("http://{}", addr))
+}
+
+#[actix::test]
+async fn run_steps() {
+    let dir = std::env::temp_dir().join(format!("parallax-scenario-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("line.yaml"), LINE).unwrap();
+
+    // posts as soon as the machine listens, the request may come before its expectation runs
+    actix::spawn(async {
+        let url = loop {
+            match bound_url("machine").await {
+                Some(url) => break url,
+                None => tokio::task::yield_now().await,
+            }
+        };
+        let _ = reqwest::Client::new()
+            .post(format!("{}/machine/start", url))
+            .send()
+            .await;
+    });
+
+    let report = Scenario::new("machine start")
+        .start_topology(dir.join("line.yaml"))
+        .expect_log("plc-boot", "READY", Duration::from_secs(2))
+        .expect_http("post", "/machine/start", Duration::from_secs(2))
+        .expect_http("post", "/machine/start", Duration::from_millis(100))
+        .expect_span("ssh", "cat recipe.txt", Duration::from_millis(100))
+        .stop_topology()
+        .run()
+        .await;
+
+    let text = report.to_string();
+    assert!(text.starts_with("machine start: FAILED\n"));
+    assert!(text.contains("   2. PASS expect plc-boot to log 'READY' within 2000ms"));
+    assert!(text.contains("   4. FAIL expect HTTP post /machine/start within 100ms"));
+    assert!(text.contains("   6. SKIP stop topology\n"));
+
+    let statuses: Vec<_> = report.steps.iter().map(|s| &s.status).collect();
+    assert_eq!(statuses[..3], [&StepStatus::Passed; 3]);
+    // the request fulfils one expectation only
+    assert!(matches!(statuses[3], StepStatus::Failed(_)));
+    assert_eq!(statuses[4..], [&StepStatus::Skipped; 2]);
+    assert!(!report.passed());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+struct Collector(UnboundedSender<String>);
+
+impl Actor for Collector {
+    type Context = Context<Self>;
+}
+
+impl Handler<Payload> for Collector {
+    type Result = ();
+
+    fn handle(&mut self, msg: Payload, _ctx: &mut Self::Context) -> Self::Result {
+        let _ = self.0.send(msg.0);
+    }
+}
+
+#[actix::test]
+async fn run_publish_opcua_and_db_steps() {
+    let dir = std::env::temp_dir().join(format!("parallax-plc-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    ServerBuilder::new_anonymous("plc")
+        .host_and_port("127.0.0.1", port)
+        .pki_dir(dir.join("pki"))
+        .create_sample_keypair(false)
+        .config()
+        .save(&dir.join("server.conf"))
+        .unwrap();
+    std::fs::write(
+        dir.join("plc.yaml"),
+        r#"
+name: plc-line
+actors:
+  - key: plc
+    kind: opcua
+    config: server.conf
+    namespaces: [{ index: 1, uri: "urn:line" }]
+    nodes:
+      - { ns: 1, id: Line1.Speed, type: variable, data_type: Int32, value: 10 }
+"#,
+    )
+    .unwrap();
+    let db = format!("sqlite://{}?mode=rwc", dir.join("mes.db").display());
+    let pool = sqlx::SqlitePool::connect(&db).await.unwrap();
+    sqlx::query("CREATE TABLE orders (id INTEGER PRIMARY KEY)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO orders (id) VALUES (1)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let (tx, mut published) = unbounded_channel();
+    let subscription = EventBus::global().subscribe(
+        "plc-orders",
+        Collector(tx).start().recipient(),
+        SubscribeOptions::default(),
+    );
+    let report = Scenario::new("plc steps")
+        .start_topology(dir.join("plc.yaml"))
+        .publish("plc-orders", serde_json::json!({ "id": 1 }))
+        // written as the Int32 the node declares
+        .set_opcua("plc", "ns=1;s=Line1.Speed", 50)
+        .expect_db_row(
+            &db,
+            "SELECT * FROM orders WHERE id = 1",
+            Duration::from_secs(1),
+        )
+        .step(Step::SetOpcua {
+            actor: "plc".to_string(),
+            node: "ns=1;s=Line1.Speed".to_string(),
+            value: 50.into(),
+            data_type: Some("Double".to_string()),
+        })
+        .stop_topology()
+        .run()
+        .await;
+    EventBus::global().unsubscribe(subscription);
+
+    let statuses: Vec<_> = report.steps.iter().map(|s| s.status.clone()).collect();
+    assert_eq!(statuses[..4], [const { StepStatus::Passed }; 4], "{}", report);
+    assert!(
+        matches!(&statuses[4], StepStatus::Failed(e) if e.contains("is a Int32, not a Double")),
+        "{}",
+        report
+    );
+    assert_eq!(statuses[5], StepStatus::Skipped);
+    assert_eq!(published.recv().await.unwrap(), r#"{"id":1}"#);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn load_yaml() {
+    let scenario = Scenario::from_yaml(
+        r#"
+name: order flow
+steps:
+  - start_topology: factory.yaml
+  - publish: { topic: orders, payload: { "id": 1 } }
+  - expect_http: { method: POST, path: /machine/start, within_ms: 2000 }
+  - set_opcua: { actor: plc, node: "ns=2;s=Line1.Speed", value: 50 }
+  - expect_db_row: { url: "sqlite://mes.db", query: "SELECT * FROM orders" }
+  - wait_ms: 100
+  - stop_topology
+"#,
+    )
+    .unwrap();
+    assert_eq!(scenario.steps.len(), 7);
+    assert_eq!(
+        scenario.steps[2],
+        Step::ExpectHttp {
+            method: "POST".to_string(),
+            path: "/machine/start".to_string(),
+            actor: None,
+            within_ms: 2000
+        }
+    );
+    assert_eq!(
+        scenario.steps[4].to_string(),
+        "expect a row of 'SELECT * FROM orders' within 1000ms"
+    );
+    assert_eq!(scenario.steps[6], Step::StopTopology);
+}
+
+#[actix::test]
+async fn record_and_replay() {
+    let running = Topology::from_yaml(
+        r#"
+name: replay-line
+actors:
+  - key: recipes
+    kind: http
+    port: 0
+    routes:
+      - path: /recipe
+        body: { "speed": 10 }
+"#,
+    )
+    .unwrap()
+    .start()
+    .await
+    .unwrap();
+
+    let recorder = TrafficRecorder::global();
+    recorder.start();
+    let url = bound_url("recipes").await.unwrap();
+    let res = reqwest::get(format!("{}/recipe", url)).await.unwrap();
+    assert_eq!(res.status(), 200);
+    recorder.stop();
+
+    let mut recorded: Vec<_> = recorder
+        .interactions()
+        .into_iter()
+        .filter(|i| i.actor == "recipes")
+        .collect();
+    assert_eq!(recorded.len(), 1);
+    let mut changed = recorded[0].clone();
+    if let Exchange::Http { response, .. } = &mut changed.exchange {
+        assert_eq!(response.as_deref(), Some(r#"{"speed":10}"#));
+        *response = Some(r#"{"speed":20}"#.to_string());
+    }
+    let mut other = recorded[0].clone();
+    other.actor = "mes".to_string();
+    recorded.extend([changed, other]);
+
+    let report = Replay::new(recorded)
+        .target("recipes", &url)
+        .unwrap()
+        .with_timing(Timing::Asap)
+        .run()
+        .await;
+    running.stop().await;
+
+    let text = report.to_string();
+    assert!(text.starts_with("replay of 3 interactions: FAILED\n"));
+    assert!(text.contains("     expected: {\"speed\":20}\n       actual:   {\"speed\":10}"));
+    assert!(text.contains("    3. SKIP mes "));
+
+    let outcomes: Vec<_> = report.entries.iter().map(|e| &e.outcome).collect();
+    assert_eq!(outcomes[0], &ReplayOutcome::Matched);
+    assert_eq!(
+        outcomes[1],
+        &ReplayOutcome::Mismatch {
+            expected: r#"{"speed":20}"#.to_string(),
+            actual: r#"{"speed":10}"#.to_string()
+        }
+    );
+    assert_eq!(outcomes[2], &ReplayOutcome::Skipped);
+    assert!(!report.passed());
+}
+
+#[actix::test]
+async fn replay_by_direction() {
+    let running = Topology::from_yaml(
+        r#"
+name: direction-line
+actors:
+  - key: fes
+    kind: http
+    port: 0
+    routes:
+      - path: /recipe
+        body: { "speed": 10 }
+      - method: POST
+        path: /orders/1/complete
+        body: { "ack": true }
+"#,
+    )
+    .unwrap()
+    .start()
+    .await
+    .unwrap();
+
+    let interaction = |actor: &str, direction, exchange| Interaction {
+        timestamp: Utc::now(),
+        actor: actor.to_string(),
+        direction,
+        exchange,
+    };
+    let http = |method: &str, uri: &str, response: Option<&str>| Exchange::Http {
+        method: method.to_string(),
+        uri: uri.to_string(),
+        headers: Default::default(),
+        body: String::new(),
+        status: response.map(|_| 200),
+        response: response.map(str::to_string),
+    };
+    let amqp = Exchange::Amqp {
+        topic: "orders".to_string(),
+        body: serde_json::json!({"id": 1}),
+    };
+    let recording = vec![
+        interaction(
+            "fes",
+            Direction::Inbound,
+            http("GET", "/recipe", Some(r#"{"speed":10}"#)),
+        ),
+        // the completion callback of a client is verified against its recorded response
+        interaction(
+            "fes",
+            Direction::Outbound,
+            http("POST", "/orders/1/complete", Some(r#"{"ack":true}"#)),
+        ),
+        interaction(
+            "fes",
+            Direction::Outbound,
+            http("POST", "/orders/1/complete", None),
+        ),
+        // nothing to verify of a sent message, the received one is replayed to the broker
+        interaction("erp", Direction::Outbound, amqp.clone()),
+        interaction("erp", Direction::Inbound, amqp),
+    ];
+
+    let report = Replay::new(recording)
+        .target("fes", &bound_url("fes").await.unwrap())
+        .unwrap()
+        .target("erp", "amqp://127.0.0.1:1")
+        .unwrap()
+        .with_timing(Timing::Asap)
+        .run()
+        .await;
+    running.stop().await;
+
+    let outcomes: Vec<_> = report.entries.iter().map(|e| &e.outcome).collect();
+    assert_eq!(outcomes[..2], [&ReplayOutcome::Matched; 2]);
+    assert_eq!(outcomes[2..4], [&ReplayOutcome::Skipped; 2]);
+    assert!(matches!(outcomes[4], ReplayOutcome::Failed { .. }));
+}
@@ -1,13 +1,16 @@
 use crate::{CmdProcessor, OsFiles};
-use std::fmt::{Display, Formatter};
 
 use crate::error::SshError;
-use actor::{ActorError, ActorResult, ActorResultVoid};
+use actor::fault::{Fault, Faults};
+use actor::recording::{Exchange, TrafficRecorder};
+use actor::trace::{Direction, Protocol, TraceContext};
 use async_trait::async_trait;
 use russh::server::{Auth, Msg, Session};
-use russh::{Channel, ChannelId, Error, server};
+use russh::{Channel, ChannelId, server};
 use russh_keys::key::PublicKey;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use utils::gauges::metrics::Metrics;
 
 #[derive(Clone)]
 pub struct BaseSshHandler {
@@ -53,17 +56,14 @@ pub fn default_cmd_processors() -> Vec<CmdProcessor> {
     vec![
         Box::new(|cmd, files| {
             if cmd.trim() == "ls" {
-                let guard = files.lock();
-                if let Err(e) = guard {
-                    return Some(Err(e.into()));
-                }
-
-                let file_list = guard
-                    .unwrap()
-                    .keys()
-                    .map(|k| k.as_str())
-                    .collect::<Vec<_>>()
-                    .join("\n");
+                let file_list = match files.lock() {
+                    Ok(guard) => {
+                        let mut keys = guard.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+                        keys.sort();
+                        keys.join("\n")
+                    }
+                    Err(e) => return Some(Err(e.into())),
+                };
 
                 Some(Ok((
                     if file_list.is_empty() {
@@ -88,21 +88,41 @@ pub fn default_cmd_processors() -> Vec<CmdProcessor> {
 }
 
 pub struct SshHandler {
+    key: String,
     files: OsFiles,
     command_history: Arc<Mutex<Vec<String>>>,
     cmd_handler: BaseSshHandler,
+    faults: Faults,
 }
 
 impl SshHandler {
     pub fn new(
+        key: String,
         files: OsFiles,
         command_history: Arc<Mutex<Vec<String>>>,
         cmd_handler: BaseSshHandler,
+        faults: Faults,
     ) -> Self {
         Self {
+            key,
             files,
             command_history,
             cmd_handler,
+            faults,
+        }
+    }
+
+    fn auth(&self) -> Auth {
+        if self.faults.draw().contains(&Fault::RefuseAuth) {
+            log::info!("[{}] Refuse the authentication", self.key);
+            Metrics::global()
+                .counter(&self.key, "ssh_auth_errors")
+                .inc();
+            Auth::Reject {
+                proceed_with_methods: None,
+            }
+        } else {
+            Auth::Accept
         }
     }
 }
@@ -111,8 +131,13 @@ impl SshHandler {
 impl server::Handler for SshHandler {
     type Error = SshError;
 
-    async fn auth_password(self, user: &str, password: &str) -> Result<(Self, Auth), Self::Error> {
-        Ok((self, Auth::Accept))
+    async fn auth_password(
+        self,
+        _user: &str,
+        _password: &str,
+    ) -> Result<(Self, Auth), Self::Error> {
+        let auth = self.auth();
+        Ok((self, auth))
     }
 
     async fn auth_publickey(
@@ -120,7 +145,8 @@ impl server::Handler for SshHandler {
         _user: &str,
         _public_key: &PublicKey,
     ) -> Result<(Self, Auth), Self::Error> {
-        Ok((self, Auth::Accept))
+        let auth = self.auth();
+        Ok((self, auth))
     }
 
     async fn channel_open_session(
@@ -137,16 +163,49 @@ impl server::Handler for SshHandler {
         data: &[u8],
         mut session: Session,
     ) -> Result<(Self, Session), Self::Error> {
-        let cmd = String::from_utf8_lossy(data).to_string();
+        let data = String::from_utf8_lossy(data);
+        let (traceparent, cmd) = TraceContext::split_command(&data);
+        let cmd = cmd.to_string();
+        TraceContext::continue_from(traceparent).record(
+            &self.key,
+            Protocol::Ssh,
+            Direction::Inbound,
+            cmd.as_str(),
+        );
+
+        let started = Instant::now();
+        let drawn = self.faults.draw();
+        self.faults.delay(&drawn).await;
+        if drawn.contains(&Fault::Hang) {
+            log::info!("[{}] Hang on {}", self.key, cmd);
+            self.faults.hang().await;
+        }
 
         if let Ok(mut history) = self.command_history.lock() {
             history.push(cmd.clone());
         }
 
+        let metrics = Metrics::global();
         let result = match self.cmd_handler.handle_command(&cmd, self.files.clone()) {
             Ok((response, _)) => response,
-            Err(e) => format!("Error: {:?}\n", e),
+            Err(e) => {
+                metrics.counter(&self.key, "ssh_errors").inc();
+                format!("Error: {:?}\n", e)
+            }
         };
+        metrics.counter(&self.key, "ssh_commands").inc();
+        metrics
+            .histogram(&self.key, "ssh_command_latency_us")
+            .record_duration(started.elapsed());
+
+        TrafficRecorder::global().record(
+            &self.key,
+            Direction::Inbound,
+            Exchange::Ssh {
+                command: cmd,
+                output: Some(result.clone()),
+            },
+        );
 
         let _ = session.data(channel, result.as_bytes().to_vec().into());
         let _ = session.exit_status_request(channel, 0);
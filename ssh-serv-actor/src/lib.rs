@@ -3,13 +3,15 @@ pub mod handler;
 #[cfg(test)]
 mod tests;
 
-use crate::error::{SshError, SshResult, SshResultVoid};
+use crate::error::{SshResult, SshResultVoid};
 use crate::handler::{BaseSshHandler, SshHandler};
 use actix::{
-    Actor, ActorContext, AsyncContext, Context, Handler, Message, SpawnHandle, WrapFuture,
+    Actor, ActorContext, AsyncContext, Context, Handler, Message, MessageResult, WrapFuture,
 };
-use actor::{ActorResult, ActorResultVoid, ActorServiceMessage};
-use russh::client::Handle;
+use actor::fault::{FaultMessage, Faults};
+use actor::registry::{ActorRegistry, Registration};
+use actor::status::{ActorState, ActorStatusMessage, Lifecycle};
+use actor::{ActorResultVoid, ActorServiceMessage};
 use russh::server::Config;
 use russh_keys::key::KeyPair;
 use std::collections::HashMap;
@@ -28,6 +30,8 @@ pub struct SshServer {
     files: OsFiles,
     command_history: Arc<Mutex<Vec<String>>>,
     cmd_handler: BaseSshHandler,
+    lifecycle: Lifecycle,
+    faults: Faults,
 }
 
 impl Default for SshServer {
@@ -43,8 +47,11 @@ impl SshServer {
         port: u16,
         cmd_processors: Option<Vec<CmdProcessor>>,
     ) -> Self {
+        let key = key.into();
         SshServer {
-            key: key.into(),
+            lifecycle: Lifecycle::new(&key),
+            faults: Faults::new(&key),
+            key,
             host: host.into(),
             port,
             files: Arc::new(Mutex::new(HashMap::new())),
@@ -56,6 +63,23 @@ impl SshServer {
 
 impl Actor for SshServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ActorRegistry::register(
+            Registration::new(&self.key, ctx.address().recipient())
+                .with(ctx.address().recipient::<ActorStatusMessage>())
+                .with(ctx.address().recipient::<SshFileOperation>())
+                .with(ctx.address().recipient::<AddProcessor>())
+                .with(ctx.address().recipient::<FaultMessage>()),
+        );
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if self.lifecycle.state() != ActorState::Failed {
+            self.lifecycle.set(ActorState::Stopped);
+        }
+        ActorRegistry::deregister(&self.key, ctx.address().recipient());
+    }
 }
 
 impl Handler<ActorServiceMessage> for SshServer {
@@ -72,9 +96,13 @@ impl Handler<ActorServiceMessage> for SshServer {
                 let addr = format!("{}:{}", self.host, self.port);
 
                 log::info!("Starting SSH server {} on {}", self.key, addr);
+                self.lifecycle.set(ActorState::Starting);
                 let files = self.files.clone();
                 let command_history = self.command_history.clone();
                 let cmd_handler = self.cmd_handler.clone();
+                let lifecycle = self.lifecycle.clone();
+                let key = self.key.clone();
+                let faults = self.faults.clone();
                 ctx.spawn(
                     async move {
                         let server_config = config.clone();
@@ -82,10 +110,12 @@ impl Handler<ActorServiceMessage> for SshServer {
                             Ok(l) => l,
                             Err(e) => {
                                 log::error!("Failed to bind SSH server to {}: {}", addr, e);
+                                lifecycle.fail(format!("Failed to bind to {}: {}", addr, e));
                                 return;
                             }
                         };
                         log::info!("SSH server listening on {}", addr);
+                        lifecycle.set(ActorState::Running);
 
                         loop {
                             match listener.accept().await {
@@ -93,9 +123,11 @@ impl Handler<ActorServiceMessage> for SshServer {
                                     log::info!("New SSH connection from {}", peer_addr);
 
                                     let handler = SshHandler::new(
+                                        key.clone(),
                                         files.clone(),
                                         command_history.clone(),
                                         cmd_handler.clone(),
+                                        faults.clone(),
                                     );
 
                                     let conn_config = server_config.clone();
@@ -119,6 +151,7 @@ impl Handler<ActorServiceMessage> for SshServer {
                 );
             }
             ActorServiceMessage::Stop => {
+                self.lifecycle.set(ActorState::Stopping);
                 ctx.stop();
                 log::info!("SSH server stopped");
             }
@@ -128,10 +161,26 @@ impl Handler<ActorServiceMessage> for SshServer {
     }
 }
 
+impl Handler<ActorStatusMessage> for SshServer {
+    type Result = MessageResult<ActorStatusMessage>;
+
+    fn handle(&mut self, _msg: ActorStatusMessage, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.lifecycle.status())
+    }
+}
+
+impl Handler<FaultMessage> for SshServer {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: FaultMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.faults.handle(msg)
+    }
+}
+
 impl Handler<SshFileOperation> for SshServer {
     type Result = SshResultVoid;
 
-    fn handle(&mut self, msg: SshFileOperation, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: SshFileOperation, _ctx: &mut Self::Context) -> Self::Result {
         match msg {
             SshFileOperation::Add(path, content) => {
                 log::info!("Add file {}", path);
@@ -150,7 +199,7 @@ impl Handler<SshFileOperation> for SshServer {
 impl Handler<AddProcessor> for SshServer {
     type Result = SshResultVoid;
 
-    fn handle(&mut self, msg: AddProcessor, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: AddProcessor, _ctx: &mut Self::Context) -> Self::Result {
         log::info!("Add processor");
         self.cmd_handler.add_processor(msg.0)?;
         Ok(())
@@ -1,12 +1,17 @@
-use crate::error::{SshResult, SshResultVoid};
+use crate::error::SshResult;
 use crate::{AddProcessor, SshFileOperation, SshServer};
 use actix::Actor;
+use actor::fault::{Fault, FaultInjection, FaultMessage};
+use actor::status::{ActorState, wait_for_state};
+use actor::trace::{Protocol, TraceContext, TraceRecorder};
 use actor::{ActorResultVoid, ActorServiceMessage};
 use russh::{ChannelMsg, client};
 use russh_keys::key::PublicKey;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::sleep;
+use utils::gauges::metrics::Metrics;
 use utils::logger_on;
 
 struct TestSshClient;
@@ -20,7 +25,7 @@ impl TestSshClient {
         )
         .await?;
 
-        let auth_success = session
+        let _auth_success = session
             .authenticate_password("test_user", "test_pass")
             .await?;
 
@@ -56,7 +61,7 @@ impl client::Handler for TestSshClient {
 
     async fn check_server_key(
         self,
-        server_public_key: &PublicKey,
+        _server_public_key: &PublicKey,
     ) -> Result<(Self, bool), Self::Error> {
         Ok((self, true))
     }
@@ -72,7 +77,12 @@ async fn smoke_ssh() -> ActorResultVoid {
         .send(ActorServiceMessage::Start)
         .await
         .unwrap()?;
-    sleep(Duration::from_millis(100)).await;
+    wait_for_state(
+        &server_handle.clone().recipient(),
+        ActorState::Running,
+        Duration::from_secs(1),
+    )
+    .await?;
 
     let client = TestSshClient;
 
@@ -134,6 +144,19 @@ async fn smoke_ssh() -> ActorResultVoid {
         client.call("ssh_test_server").await?
     );
 
+    // The trace context rides in front of the command
+    let order = TraceContext::new_root();
+    assert_eq!(
+        "It is a new Ssh test server!\n",
+        client.call(&order.wrap_command("ssh_test_server")).await?
+    );
+    let spans = TraceRecorder::global().trace(&order.trace_id);
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].protocol, Protocol::Ssh);
+    assert_eq!(spans[0].actor, "ssh_server");
+    assert_eq!(spans[0].parent_span_id.as_ref(), Some(&order.span_id));
+    assert_eq!(spans[0].detail, "ssh_test_server");
+
     // Stop the SSH server
     server_handle
         .send(ActorServiceMessage::Stop)
@@ -142,3 +165,67 @@ async fn smoke_ssh() -> ActorResultVoid {
     sleep(Duration::from_millis(100)).await;
     Ok(())
 }
+
+/// Connects and authenticates, `None` when the authentication is refused.
+async fn session(addr: SocketAddr) -> SshResult<Option<client::Handle<TestSshClient>>> {
+    let mut session =
+        client::connect(Arc::new(client::Config::default()), addr, TestSshClient).await?;
+    let accepted = session
+        .authenticate_password("test_user", "test_pass")
+        .await?;
+    Ok(accepted.then_some(session))
+}
+
+async fn exec(session: &client::Handle<TestSshClient>, cmd: &str) -> SshResult<String> {
+    let mut channel = session.channel_open_session().await?;
+    channel.exec(true, cmd).await?;
+    let mut output = String::new();
+    while let Some(msg) = channel.wait().await {
+        if let ChannelMsg::Data { ref data } = msg {
+            output.push_str(&String::from_utf8_lossy(data));
+        }
+    }
+    Ok(output)
+}
+
+#[actix::test]
+async fn refuse_auth_and_hang() -> ActorResultVoid {
+    // a port of its own, the smoke test listens on the default one
+    let port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .unwrap()
+        .port();
+    let server = SshServer::new("faulty_ssh", "127.0.0.1", port, None).start();
+    server.send(ActorServiceMessage::Start).await.unwrap()?;
+    wait_for_state(
+        &server.clone().recipient(),
+        ActorState::Running,
+        Duration::from_secs(1),
+    )
+    .await?;
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let inject = |fault| FaultMessage::Inject(FaultInjection::new(fault));
+
+    server.send(inject(Fault::RefuseAuth)).await.unwrap()?;
+    assert!(session(addr).await.unwrap().is_none());
+    let refused = Metrics::global().counter("faulty_ssh", "ssh_auth_errors");
+    assert_eq!(refused.get(), 1);
+    server.send(FaultMessage::Clear).await.unwrap()?;
+    let connected = session(addr).await.unwrap().unwrap();
+
+    // the command is answered once the hang is cleared
+    server.send(inject(Fault::Hang)).await.unwrap()?;
+    let hanging = tokio::spawn(async move { exec(&connected, "ls").await });
+    sleep(Duration::from_millis(300)).await;
+    assert!(!hanging.is_finished());
+    server.send(FaultMessage::Clear).await.unwrap()?;
+    let output = tokio::time::timeout(Duration::from_secs(1), hanging)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(output, "No files found\n");
+
+    server.send(ActorServiceMessage::Stop).await.unwrap()?;
+    Ok(())
+}
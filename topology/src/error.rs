@@ -0,0 +1,56 @@
+use actor::ActorError;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub struct TopologyError(pub String);
+pub type TopologyResult<T> = Result<T, TopologyError>;
+
+impl Display for TopologyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+impl From<ActorError> for TopologyError {
+    fn from(e: ActorError) -> Self {
+        TopologyError(e.to_string())
+    }
+}
+
+impl From<TopologyError> for ActorError {
+    fn from(value: TopologyError) -> Self {
+        ActorError::StartupError(value.0)
+    }
+}
+
+impl From<std::io::Error> for TopologyError {
+    fn from(e: std::io::Error) -> Self {
+        TopologyError(e.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for TopologyError {
+    fn from(e: serde_yaml::Error) -> Self {
+        TopologyError(format!("Invalid yaml topology: {}", e))
+    }
+}
+
+impl From<toml::de::Error> for TopologyError {
+    fn from(e: toml::de::Error) -> Self {
+        TopologyError(format!("Invalid toml topology: {}", e))
+    }
+}
+
+impl From<sqlx::Error> for TopologyError {
+    fn from(e: sqlx::Error) -> Self {
+        TopologyError(e.to_string())
+    }
+}
+
+impl From<actix::MailboxError> for TopologyError {
+    fn from(e: actix::MailboxError) -> Self {
+        TopologyError(e.to_string())
+    }
+}
@@ -0,0 +1,434 @@
+use crate::error::{TopologyError, TopologyResult};
+use crate::spec::{ActorKind, ActorSpec, HttpRouteSpec};
+use crate::{Payload, Topology, nodes};
+use actix::{Actor, ActorContext, Context, Handler, Recipient, ResponseFuture};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{HttpResponse, web};
+use actor::bus::{EventBus, SubscribeOptions, SubscriptionId};
+use actor::clock::SimClock;
+use actor::status::{ActorState, ActorStatus, ActorStatusMessage};
+use actor::trace::Traced;
+use actor::{ActorResultVoid, ActorServiceMessage};
+use azure_actor::listener::AzureTopicListener;
+use azure_actor::sender::{AzureTopicSender, SendMessage};
+use db_actor::sqlite::{SqLiteQueryActor, Sql};
+use http_client_actor::HttpClient;
+use http_serv_actor::stub::StubMessage;
+use http_serv_actor::tls::TlsConfig;
+use http_serv_actor::{BaseHttpServer, RouterConfig};
+use opcua_serv_actor::OpcuaServer;
+use process_actor::ProcessActor;
+use proxy_actor::TcpProxy;
+use ssh_serv_actor::{SshFileOperation, SshServer};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// An actor started from the topology.
+#[derive(Clone)]
+pub struct LaunchedActor {
+    pub key: String,
+    pub kind: &'static str,
+    service: Recipient<ActorServiceMessage>,
+    status: Recipient<ActorStatusMessage>,
+    /// The event bus subscriptions feeding a sink along its links.
+    subscriptions: Vec<SubscriptionId>,
+    forwarder: Option<Recipient<ActorServiceMessage>>,
+}
+
+impl LaunchedActor {
+    pub async fn status(&self) -> TopologyResult<ActorStatus> {
+        Ok(self.status.send(ActorStatusMessage).await?)
+    }
+
+    /// Cuts the links feeding the actor, a restarted topology subscribes again.
+    fn unlink(&self) {
+        for id in &self.subscriptions {
+            EventBus::global().unsubscribe(*id);
+        }
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.do_send(ActorServiceMessage::Stop);
+        }
+    }
+}
+
+/// The started topology, the actors are kept in the start order.
+pub struct RunningTopology {
+    name: String,
+    actors: Vec<LaunchedActor>,
+}
+
+impl RunningTopology {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn actors(&self) -> &[LaunchedActor] {
+        &self.actors
+    }
+
+    pub async fn status(&self) -> Vec<ActorStatus> {
+        let mut statuses = Vec::with_capacity(self.actors.len());
+        for actor in &self.actors {
+            let status = actor.status().await.unwrap_or_else(|e| ActorStatus {
+                key: actor.key.clone(),
+                state: ActorState::Stopped,
+                last_error: Some(e.to_string()),
+                uptime: None,
+            });
+            statuses.push(status);
+        }
+        statuses
+    }
+
+    /// Stops the actors in the reverse start order.
+    pub async fn stop(self) {
+        stop_all(&self.actors).await;
+    }
+}
+
+async fn stop_all(actors: &[LaunchedActor]) {
+    for actor in actors.iter().rev() {
+        actor.unlink();
+        match actor.service.send(ActorServiceMessage::Stop).await {
+            Ok(Ok(())) => log::info!("[{}] stopped", actor.key),
+            Ok(Err(e)) => log::warn!("[{}] failed to stop: {:?}", actor.key, e),
+            Err(e) => log::debug!("[{}] is already gone: {}", actor.key, e),
+        }
+    }
+}
+
+impl Topology {
+    /// Validates the topology and starts the actors in the dependency order.
+    /// An actor is considered started when it leaves `Starting`.
+    /// When an actor fails to start, the already started ones are stopped again.
+    pub async fn start(&self) -> TopologyResult<RunningTopology> {
+        self.validate()?;
+        SimClock::global().set_speed(self.spec.clock.speed)?;
+        let timeout = Duration::from_millis(self.spec.startup_timeout_ms);
+        let mut actors: Vec<LaunchedActor> = vec![];
+
+        for spec in self.start_order()? {
+            log::info!("[{}] Starting {} actor", spec.key, spec.kind.name());
+            let launched = match self.launch(spec).await {
+                Ok(launched) => launched,
+                Err(e) => {
+                    stop_all(&actors).await;
+                    return Err(TopologyError(format!("{}: {}", spec.key, e)));
+                }
+            };
+            actors.push(launched);
+            if let Err(e) = wait_started(actors.last().expect("just pushed"), timeout).await {
+                stop_all(&actors).await;
+                return Err(e);
+            }
+        }
+
+        Ok(RunningTopology {
+            name: self.spec.name.clone(),
+            actors,
+        })
+    }
+
+    async fn launch(&self, spec: &ActorSpec) -> TopologyResult<LaunchedActor> {
+        let key = spec.key.clone();
+        let mut subscriptions = vec![];
+        let mut forwarder = None;
+        let (service, status) = match &spec.kind {
+            ActorKind::Http {
+                host,
+                port,
+                routes,
+                stubs,
+                mappings,
+                tls,
+            } => {
+                let config = (!routes.is_empty()).then(|| router_config(routes.clone()));
+                let mut server = BaseHttpServer::new(&key, host, *port, config);
+                if let Some(dir) = mappings {
+                    server = server.with_mappings(self.resolve(dir));
+                }
+                if let Some(tls) = tls {
+                    let resolve = |p: &Option<PathBuf>| p.as_ref().map(|p| self.resolve(p));
+                    server = server.with_tls(TlsConfig {
+                        cert: resolve(&tls.cert),
+                        key: resolve(&tls.key),
+                        client_ca: resolve(&tls.client_ca),
+                        ca_file: resolve(&tls.ca_file),
+                        ..tls.clone()
+                    });
+                }
+                let addr = server.start();
+                for stub in stubs {
+                    addr.send(StubMessage::add(stub.clone()))
+                        .await?
+                        .map_err(|e| TopologyError(format!("Can not add {}: {:?}", stub.id, e)))?;
+                }
+                (addr.clone().recipient(), addr.recipient())
+            }
+            ActorKind::Ssh { host, port, files } => {
+                let addr = SshServer::new(&key, host, *port, None).start();
+                for (name, content) in files {
+                    addr.send(SshFileOperation::Add(
+                        name.clone(),
+                        content.clone().into_bytes(),
+                    ))
+                    .await?
+                    .map_err(|e| TopologyError(format!("Can not add {}: {:?}", name, e)))?;
+                }
+                (addr.clone().recipient(), addr.recipient())
+            }
+            ActorKind::Opcua {
+                config,
+                namespaces,
+                nodes,
+            } => {
+                let structure = nodes::server_structure(namespaces, nodes)?;
+                let addr = OpcuaServer::new(&key, &self.resolve(config), structure)?.start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+            ActorKind::Process { exe, cmd, env } => {
+                let env = env.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                let addr = ProcessActor::new(key.clone(), exe.clone(), cmd.clone(), env).start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+            ActorKind::Sqlite {
+                url,
+                query,
+                interval_ms,
+                init,
+            } => {
+                let pool = sqlx::SqlitePool::connect(url).await?;
+                for statement in init {
+                    sqlx::query(statement).execute(&pool).await?;
+                }
+                let actor: SqLiteQueryActor<Payload, _> = SqLiteQueryActor::new(
+                    key.clone(),
+                    Sql::from(query.as_str()),
+                    Duration::from_millis(*interval_ms),
+                    pool,
+                );
+                let addr = actor.start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+            ActorKind::AzureListener {
+                url,
+                topic,
+                subscription,
+            } => {
+                let addr = AzureTopicListener::<String, Payload>::new(
+                    key.as_str(),
+                    url,
+                    topic,
+                    subscription,
+                )
+                .start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+            ActorKind::AzureSender { url, topic } => {
+                let addr = AzureTopicSender::<String>::new(key.as_str(), url, topic).start();
+                let sink = SinkForwarder {
+                    key: key.clone(),
+                    target: addr.clone().recipient(),
+                }
+                .start();
+                // the sources publish to the topic named after their key
+                let payloads = sink.clone().recipient::<Traced<Payload>>();
+                for link in self.spec.links.iter().filter(|l| l.to == key) {
+                    subscriptions.push(EventBus::global().subscribe(
+                        &link.from,
+                        payloads.clone(),
+                        SubscribeOptions::default(),
+                    ));
+                }
+                forwarder = Some(sink.recipient());
+                (addr.clone().recipient(), addr.recipient())
+            }
+            ActorKind::Proxy {
+                host,
+                port,
+                upstream,
+            } => {
+                let addr = TcpProxy::new(&key, host, *port, upstream).start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+            ActorKind::HttpClient {
+                base_url,
+                auth,
+                timeout_ms,
+                retry,
+            } => {
+                let mut client = HttpClient::new(&key, base_url)
+                    .with_timeout(Duration::from_millis(*timeout_ms))
+                    .with_retry(retry.clone());
+                if let Some(auth) = auth {
+                    client = client.with_auth(auth.clone());
+                }
+                let addr = client.start();
+                (addr.clone().recipient(), addr.recipient())
+            }
+        };
+
+        let launched = LaunchedActor {
+            key,
+            kind: spec.kind.name(),
+            service,
+            status,
+            subscriptions,
+            forwarder,
+        };
+        let started: TopologyResult<()> =
+            async { Ok(launched.service.send(ActorServiceMessage::Start).await??) }.await;
+        if started.is_err() {
+            launched.unlink();
+        }
+        started.map(|_| launched)
+    }
+}
+
+async fn wait_started(actor: &LaunchedActor, timeout: Duration) -> TopologyResult<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let status = actor.status().await?;
+        match status.state {
+            ActorState::Created | ActorState::Starting if Instant::now() < deadline => {
+                actix::clock::sleep(Duration::from_millis(10)).await;
+            }
+            ActorState::Created | ActorState::Starting => {
+                return Err(TopologyError(format!(
+                    "{} is still {} after {:?}",
+                    actor.key, status.state, timeout
+                )));
+            }
+            ActorState::Failed => {
+                return Err(TopologyError(format!(
+                    "{} failed to start: {}",
+                    actor.key,
+                    status.last_error.unwrap_or_default()
+                )));
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn router_config(routes: Vec<HttpRouteSpec>) -> RouterConfig {
+    Arc::new(move |cfg| {
+        for route in &routes {
+            let method = Method::from_bytes(route.method.as_bytes()).unwrap_or(Method::GET);
+            let status = StatusCode::from_u16(route.status).unwrap_or(StatusCode::OK);
+            let body = route.body.clone();
+            cfg.route(
+                &route.path,
+                web::method(method).to(move || {
+                    let body = body.clone();
+                    async move { HttpResponse::build(status).json(body) }
+                }),
+            );
+        }
+    })
+}
+
+/// Turns the payloads coming along a link into messages for an azure sender.
+struct SinkForwarder {
+    key: String,
+    target: Recipient<Traced<SendMessage<String>>>,
+}
+
+impl Actor for SinkForwarder {
+    type Context = Context<Self>;
+}
+
+impl Handler<ActorServiceMessage> for SinkForwarder {
+    type Result = ActorResultVoid;
+
+    fn handle(&mut self, msg: ActorServiceMessage, ctx: &mut Self::Context) -> Self::Result {
+        if let ActorServiceMessage::Stop = msg {
+            ctx.stop();
+        }
+        Ok(())
+    }
+}
+
+/// Waits until the sender takes the payload, so a full sender mailbox holds the link back
+/// through the bus instead of dropping the payload.
+impl Handler<Traced<Payload>> for SinkForwarder {
+    type Result = ResponseFuture<()>;
+
+    fn handle(&mut self, msg: Traced<Payload>, _ctx: &mut Self::Context) -> Self::Result {
+        let Traced { trace, message } = msg;
+        let key = self.key.clone();
+        let target = self.target.clone();
+        Box::pin(async move {
+            match target
+                .send(Traced::new(trace, SendMessage(message.0)))
+                .await
+            {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => log::warn!("[{}] The sender refused the payload: {}", key, e),
+                Err(e) => log::error!("[{}] Can not forward the payload: {}", key, e),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SinkForwarder;
+    use crate::Payload;
+    use actix::{Actor, Context, Handler};
+    use actor::ActorResultVoid;
+    use actor::trace::{TraceContext, Traced};
+    use azure_actor::sender::SendMessage;
+    use std::collections::HashSet;
+    use std::time::Duration;
+    use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+    use tokio::time::timeout;
+
+    /// Stands in for the azure sender.
+    struct Sender(UnboundedSender<String>);
+
+    impl Actor for Sender {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Self::Context) {
+            ctx.set_mailbox_capacity(1);
+        }
+    }
+
+    impl Handler<Traced<SendMessage<String>>> for Sender {
+        type Result = ActorResultVoid;
+
+        fn handle(
+            &mut self,
+            msg: Traced<SendMessage<String>>,
+            _ctx: &mut Self::Context,
+        ) -> Self::Result {
+            let _ = self.0.send(msg.message.0);
+            Ok(())
+        }
+    }
+
+    #[actix::test]
+    async fn forward_past_a_full_mailbox() {
+        let (tx, mut sent) = unbounded_channel();
+        let forwarder = SinkForwarder {
+            key: "sink".to_string(),
+            target: Sender(tx).start().recipient(),
+        }
+        .start();
+
+        // far more than the sender mailbox holds
+        for i in 0..100 {
+            forwarder.do_send(Traced::new(
+                TraceContext::new_root(),
+                Payload(i.to_string()),
+            ));
+        }
+        let mut payloads = HashSet::new();
+        while payloads.len() < 100 {
+            let payload = timeout(Duration::from_secs(1), sent.recv()).await;
+            payloads.insert(payload.unwrap().unwrap());
+        }
+    }
+}
@@ -0,0 +1,294 @@
+pub mod error;
+pub mod launch;
+pub mod nodes;
+pub mod spec;
+#[cfg(test)]
+mod tests;
+
+use crate::error::{TopologyError, TopologyResult};
+use crate::spec::{ActorKind, ActorSpec, TopologySpec};
+use actix::Message;
+use http_serv_actor::wiremock;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Column, Row};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The message flowing along the topology links, a json document in text form.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct Payload(pub String);
+
+impl From<String> for Payload {
+    fn from(value: String) -> Self {
+        Payload(value)
+    }
+}
+
+impl From<Vec<SqliteRow>> for Payload {
+    fn from(rows: Vec<SqliteRow>) -> Self {
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|row| {
+                let mut obj = serde_json::Map::new();
+                for (idx, column) in row.columns().iter().enumerate() {
+                    let value = if let Ok(v) = row.try_get::<Option<i64>, _>(idx) {
+                        v.map(Into::into)
+                    } else if let Ok(v) = row.try_get::<Option<f64>, _>(idx) {
+                        v.map(Into::into)
+                    } else {
+                        row.try_get::<Option<String>, _>(idx)
+                            .ok()
+                            .flatten()
+                            .map(Into::into)
+                    };
+                    obj.insert(
+                        column.name().to_string(),
+                        value.unwrap_or(serde_json::Value::Null),
+                    );
+                }
+                serde_json::Value::Object(obj)
+            })
+            .collect();
+        Payload(serde_json::Value::Array(rows).to_string())
+    }
+}
+
+pub struct Topology {
+    spec: TopologySpec,
+    /// Relative paths in the spec are resolved against this directory.
+    base_dir: PathBuf,
+}
+
+impl Topology {
+    /// Loads a topology from a yaml (`.yaml`, `.yml`) or toml (`.toml`) file.
+    pub fn load(path: impl AsRef<Path>) -> TopologyResult<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| TopologyError(format!("Can not read {}: {}", path.display(), e)))?;
+        let spec = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text)?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&text)?,
+            _ => {
+                return Err(TopologyError(format!(
+                    "Unknown topology format {}, expected yaml or toml",
+                    path.display()
+                )));
+            }
+        };
+        let base_dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        Ok(Topology { spec, base_dir })
+    }
+
+    pub fn from_yaml(text: &str) -> TopologyResult<Self> {
+        Ok(Topology::new(serde_yaml::from_str(text)?))
+    }
+
+    pub fn from_toml(text: &str) -> TopologyResult<Self> {
+        Ok(Topology::new(toml::from_str(text)?))
+    }
+
+    pub fn new(spec: TopologySpec) -> Self {
+        Topology {
+            spec,
+            base_dir: PathBuf::from("."),
+        }
+    }
+
+    pub fn spec(&self) -> &TopologySpec {
+        &self.spec
+    }
+
+    fn actor(&self, key: &str) -> Option<&ActorSpec> {
+        self.spec.actors.iter().find(|a| a.key == key)
+    }
+
+    /// Checks keys, references and the actor settings without starting anything.
+    pub fn validate(&self) -> TopologyResult<()> {
+        if !self.spec.clock.speed.is_finite() || self.spec.clock.speed <= 0.0 {
+            return Err(TopologyError(format!(
+                "The clock speed has to be positive, got {}",
+                self.spec.clock.speed
+            )));
+        }
+        let mut keys = HashSet::new();
+        for actor in &self.spec.actors {
+            if actor.key.is_empty() {
+                return Err(TopologyError("An actor has an empty key".to_string()));
+            }
+            if !keys.insert(actor.key.as_str()) {
+                return Err(TopologyError(format!("Duplicated key {}", actor.key)));
+            }
+        }
+
+        for actor in &self.spec.actors {
+            for dep in &actor.depends_on {
+                if dep == &actor.key {
+                    return Err(TopologyError(format!("{} depends on itself", actor.key)));
+                }
+                if !keys.contains(dep.as_str()) {
+                    return Err(TopologyError(format!(
+                        "{} depends on unknown actor {}",
+                        actor.key, dep
+                    )));
+                }
+            }
+            self.validate_kind(actor)?;
+        }
+
+        for link in &self.spec.links {
+            let from = self.actor(&link.from).ok_or_else(|| {
+                TopologyError(format!("The link source {} is unknown", link.from))
+            })?;
+            let to = self
+                .actor(&link.to)
+                .ok_or_else(|| TopologyError(format!("The link target {} is unknown", link.to)))?;
+            if !from.kind.is_source() {
+                return Err(TopologyError(format!(
+                    "{} ({}) can not be a link source",
+                    from.key,
+                    from.kind.name()
+                )));
+            }
+            if !to.kind.is_sink() {
+                return Err(TopologyError(format!(
+                    "{} ({}) can not be a link target",
+                    to.key,
+                    to.kind.name()
+                )));
+            }
+        }
+
+        self.start_order().map(|_| ())
+    }
+
+    fn validate_kind(&self, actor: &ActorSpec) -> TopologyResult<()> {
+        match &actor.kind {
+            ActorKind::Http {
+                routes,
+                stubs,
+                mappings,
+                ..
+            } => {
+                if let Some(dir) = mappings {
+                    wiremock::load_mappings(&self.resolve(dir))
+                        .map_err(|e| TopologyError(format!("{}: {}", actor.key, e)))?;
+                }
+                for stub in stubs {
+                    stub.validate().map_err(|e| {
+                        TopologyError(format!("{}: stub {}: {}", actor.key, stub.id, e))
+                    })?;
+                }
+                for route in routes {
+                    actix_web::http::Method::from_bytes(route.method.as_bytes()).map_err(|_| {
+                        TopologyError(format!(
+                            "{}: unknown http method {}",
+                            actor.key, route.method
+                        ))
+                    })?;
+                    actix_web::http::StatusCode::from_u16(route.status).map_err(|_| {
+                        TopologyError(format!(
+                            "{}: invalid http status {}",
+                            actor.key, route.status
+                        ))
+                    })?;
+                }
+            }
+            ActorKind::Opcua {
+                namespaces, nodes, ..
+            } => {
+                nodes::server_structure(namespaces, nodes)
+                    .map_err(|e| TopologyError(format!("{}: {}", actor.key, e)))?;
+            }
+            ActorKind::Proxy { upstream, .. }
+                if upstream
+                    .rsplit_once(':')
+                    .is_none_or(|(_, port)| port.parse::<u16>().is_err()) =>
+            {
+                return Err(TopologyError(format!(
+                    "{}: the upstream {} is not host:port",
+                    actor.key, upstream
+                )));
+            }
+            ActorKind::HttpClient { base_url, .. }
+                if !base_url.starts_with("http://") && !base_url.starts_with("https://") =>
+            {
+                return Err(TopologyError(format!(
+                    "{}: the base url {} is not http(s)",
+                    actor.key, base_url
+                )));
+            }
+            ActorKind::Sqlite { interval_ms: 0, .. } => {
+                return Err(TopologyError(format!(
+                    "{}: the polling interval can not be 0",
+                    actor.key
+                )));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Orders the actors so that every actor comes after its dependencies
+    /// and every link target comes before its source.
+    /// The declaration order is kept when there is no constraint.
+    pub fn start_order(&self) -> TopologyResult<Vec<&ActorSpec>> {
+        let mut deps: HashMap<&str, HashSet<&str>> = self
+            .spec
+            .actors
+            .iter()
+            .map(|a| {
+                (
+                    a.key.as_str(),
+                    a.depends_on.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+        for link in &self.spec.links {
+            if let Some(d) = deps.get_mut(link.from.as_str()) {
+                d.insert(link.to.as_str());
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.spec.actors.len());
+        let mut started = HashSet::new();
+        while order.len() < self.spec.actors.len() {
+            let next = self.spec.actors.iter().find(|a| {
+                !started.contains(a.key.as_str())
+                    && deps[a.key.as_str()].iter().all(|d| started.contains(d))
+            });
+            match next {
+                Some(actor) => {
+                    started.insert(actor.key.as_str());
+                    order.push(actor);
+                }
+                None => {
+                    let mut cycle: Vec<_> = self
+                        .spec
+                        .actors
+                        .iter()
+                        .map(|a| a.key.as_str())
+                        .filter(|k| !started.contains(k))
+                        .collect();
+                    cycle.sort();
+                    return Err(TopologyError(format!(
+                        "Dependency cycle between {}",
+                        cycle.join(", ")
+                    )));
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.base_dir.join(path)
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::error::{TopologyError, TopologyResult};
+use crate::spec::{NamespaceSpec, NodeSpec, NodeType};
+use opcua::types::{DataTypeId, NodeId, QualifiedName, Variant};
+use opcua_serv_actor::data::{Namespace, Node, ServerStructure};
+
+pub fn server_structure(
+    namespaces: &[NamespaceSpec],
+    nodes: &[NodeSpec],
+) -> TopologyResult<ServerStructure> {
+    let namespaces = namespaces
+        .iter()
+        .map(|ns| Namespace(ns.index, ns.uri.clone()))
+        .collect();
+    let nodes = nodes.iter().map(node).collect::<TopologyResult<Vec<_>>>()?;
+    Ok(ServerStructure::new(namespaces, nodes))
+}
+
+fn node(spec: &NodeSpec) -> TopologyResult<Node> {
+    let node_id = NodeId::new(spec.ns, spec.id.clone());
+    let name = spec.name.clone().unwrap_or_else(|| spec.id.clone());
+    let browse_name = QualifiedName::new(spec.ns, name.as_str());
+    let children = spec
+        .children
+        .iter()
+        .map(node)
+        .collect::<TopologyResult<Vec<_>>>()?;
+
+    let node = match spec.node_type {
+        NodeType::Folder => Node::folder(node_id, browse_name, name.into(), children),
+        NodeType::Object => Node::object(node_id, browse_name, name.into(), children),
+        NodeType::Variable | NodeType::Property => {
+            let type_name = spec
+                .data_type
+                .as_deref()
+                .ok_or_else(|| TopologyError(format!("The node {} has no data_type", spec.id)))?;
+            let data_type = data_type(type_name)?;
+            let value = variant(data_type, &spec.value).ok_or_else(|| {
+                TopologyError(format!(
+                    "The value {} of the node {} is not a {}",
+                    spec.value, spec.id, type_name
+                ))
+            })?;
+            if spec.node_type == NodeType::Property {
+                Node::property(
+                    node_id,
+                    browse_name,
+                    name.into(),
+                    data_type,
+                    spec.value_rank,
+                    value,
+                )
+            } else {
+                Node::variable_with(
+                    node_id,
+                    browse_name,
+                    name.into(),
+                    data_type,
+                    spec.value_rank,
+                    value,
+                    children,
+                )
+            }
+        }
+    };
+    Ok(node)
+}
+
+pub fn data_type(name: &str) -> TopologyResult<DataTypeId> {
+    match name {
+        "Boolean" => Ok(DataTypeId::Boolean),
+        "Int16" => Ok(DataTypeId::Int16),
+        "Int32" => Ok(DataTypeId::Int32),
+        "Int64" => Ok(DataTypeId::Int64),
+        "UInt16" => Ok(DataTypeId::UInt16),
+        "UInt32" => Ok(DataTypeId::UInt32),
+        "UInt64" => Ok(DataTypeId::UInt64),
+        "Float" => Ok(DataTypeId::Float),
+        "Double" => Ok(DataTypeId::Double),
+        "String" => Ok(DataTypeId::String),
+        _ => Err(TopologyError(format!("Unsupported data type {}", name))),
+    }
+}
+
+/// Converts a json value into a variant of the given type, a missing value gives the type default.
+pub fn variant(data_type: DataTypeId, value: &serde_json::Value) -> Option<Variant> {
+    use serde_json::Value;
+    let value = match (data_type, value) {
+        (DataTypeId::Boolean, Value::Null) => Variant::from(false),
+        (DataTypeId::Boolean, Value::Bool(b)) => Variant::from(*b),
+        (DataTypeId::String, Value::Null) => Variant::from(String::new()),
+        (DataTypeId::String, Value::String(s)) => Variant::from(s.clone()),
+        (DataTypeId::Float, v) => Variant::from(number(v)?.as_f64()? as f32),
+        (DataTypeId::Double, v) => Variant::from(number(v)?.as_f64()?),
+        (DataTypeId::Int16, v) => Variant::from(i16::try_from(number(v)?.as_i64()?).ok()?),
+        (DataTypeId::Int32, v) => Variant::from(i32::try_from(number(v)?.as_i64()?).ok()?),
+        (DataTypeId::Int64, v) => Variant::from(number(v)?.as_i64()?),
+        (DataTypeId::UInt16, v) => Variant::from(u16::try_from(number(v)?.as_u64()?).ok()?),
+        (DataTypeId::UInt32, v) => Variant::from(u32::try_from(number(v)?.as_u64()?).ok()?),
+        (DataTypeId::UInt64, v) => Variant::from(number(v)?.as_u64()?),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn number(value: &serde_json::Value) -> Option<serde_json::Number> {
+    match value {
+        serde_json::Value::Null => Some(0.into()),
+        serde_json::Value::Number(n) => Some(n.clone()),
+        _ => None,
+    }
+}
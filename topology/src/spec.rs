@@ -0,0 +1,237 @@
+use http_client_actor::config::{Auth, RetryPolicy};
+use http_serv_actor::stub::Stub;
+use http_serv_actor::tls::TlsConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The description of a simulated factory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopologySpec {
+    pub name: String,
+    /// How long an actor may stay in `Starting` before the launch is aborted.
+    #[serde(default = "default_startup_timeout_ms")]
+    pub startup_timeout_ms: u64,
+    #[serde(default)]
+    pub clock: ClockSpec,
+    #[serde(default)]
+    pub actors: Vec<ActorSpec>,
+    /// Data flows from a source (sqlite poller, azure listener) to a sink (azure sender)
+    /// through the event bus topic named after the source key.
+    #[serde(default)]
+    pub links: Vec<LinkSpec>,
+}
+
+fn default_startup_timeout_ms() -> u64 {
+    5000
+}
+
+/// Settings of the global simulation clock.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockSpec {
+    /// Simulated seconds per wall clock second, e.g. 96 replays an 8 hours shift in 5 minutes.
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+}
+
+impl Default for ClockSpec {
+    fn default() -> Self {
+        ClockSpec {
+            speed: default_speed(),
+        }
+    }
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkSpec {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActorSpec {
+    pub key: String,
+    /// Keys of the actors that have to be running before this one starts.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(flatten)]
+    pub kind: ActorKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActorKind {
+    Http {
+        #[serde(default = "default_host")]
+        host: String,
+        port: u16,
+        #[serde(default)]
+        routes: Vec<HttpRouteSpec>,
+        /// Added when the actor starts, they can be changed at runtime with `StubMessage`.
+        #[serde(default)]
+        stubs: Vec<Stub>,
+        /// A directory of WireMock mappings, reloaded when a file changes.
+        #[serde(default)]
+        mappings: Option<PathBuf>,
+        /// Serves HTTPS, with a generated CA when no certificate is given.
+        #[serde(default)]
+        tls: Option<TlsConfig>,
+    },
+    Ssh {
+        #[serde(default = "default_host")]
+        host: String,
+        port: u16,
+        #[serde(default)]
+        files: BTreeMap<String, String>,
+    },
+    Opcua {
+        config: PathBuf,
+        #[serde(default)]
+        namespaces: Vec<NamespaceSpec>,
+        #[serde(default)]
+        nodes: Vec<NodeSpec>,
+    },
+    Process {
+        exe: String,
+        #[serde(default)]
+        cmd: String,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+    Sqlite {
+        url: String,
+        query: String,
+        #[serde(default = "default_interval_ms")]
+        interval_ms: u64,
+        /// Statements executed once before polling, e.g. to create the schema.
+        #[serde(default)]
+        init: Vec<String>,
+    },
+    AzureListener {
+        url: String,
+        topic: String,
+        subscription: String,
+    },
+    AzureSender {
+        url: String,
+        topic: String,
+    },
+    /// A TCP proxy in front of another endpoint, e.g. `upstream: 127.0.0.1:4840`.
+    Proxy {
+        #[serde(default = "default_host")]
+        host: String,
+        port: u16,
+        upstream: String,
+    },
+    /// An HTTP client calling the system under test, e.g. `base_url: http://127.0.0.1:5000/fes`.
+    HttpClient {
+        base_url: String,
+        #[serde(default)]
+        auth: Option<Auth>,
+        /// The timeout of every attempt.
+        #[serde(default = "default_timeout_ms")]
+        timeout_ms: u64,
+        #[serde(default)]
+        retry: RetryPolicy,
+    },
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
+
+fn default_timeout_ms() -> u64 {
+    http_client_actor::DEFAULT_TIMEOUT.as_millis() as u64
+}
+
+impl ActorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActorKind::Http { .. } => "http",
+            ActorKind::Ssh { .. } => "ssh",
+            ActorKind::Opcua { .. } => "opcua",
+            ActorKind::Process { .. } => "process",
+            ActorKind::Sqlite { .. } => "sqlite",
+            ActorKind::AzureListener { .. } => "azure_listener",
+            ActorKind::AzureSender { .. } => "azure_sender",
+            ActorKind::Proxy { .. } => "proxy",
+            ActorKind::HttpClient { .. } => "http_client",
+        }
+    }
+
+    pub fn is_source(&self) -> bool {
+        matches!(
+            self,
+            ActorKind::Sqlite { .. } | ActorKind::AzureListener { .. }
+        )
+    }
+
+    pub fn is_sink(&self) -> bool {
+        matches!(self, ActorKind::AzureSender { .. })
+    }
+}
+
+/// A static response served by an http mock.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpRouteSpec {
+    #[serde(default = "default_method")]
+    pub method: String,
+    pub path: String,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub body: serde_json::Value,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamespaceSpec {
+    pub index: u16,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeType {
+    Folder,
+    Object,
+    Variable,
+    Property,
+}
+
+/// A node of the OPC UA address space, the id is a string id in the given namespace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeSpec {
+    pub ns: u16,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub node_type: NodeType,
+    /// Browse and display name, the id is used when it is missing.
+    pub name: Option<String>,
+    pub data_type: Option<String>,
+    #[serde(default)]
+    pub value: serde_json::Value,
+    #[serde(default = "default_value_rank")]
+    pub value_rank: i32,
+    #[serde(default)]
+    pub children: Vec<NodeSpec>,
+}
+
+fn default_value_rank() -> i32 {
+    -1
+}
@@ -0,0 +1,165 @@
+use crate::{Payload, Topology};
+use actor::bus::EventBus;
+use actor::status::ActorState;
+use actor::trace::{TraceContext, Traced};
+
+const FACTORY: &str = r#"
+name: line-1
+actors:
+  - key: mes
+    kind: http
+    port: 0
+    routes:
+      - path: /orders
+        body: { "orders": [] }
+  - key: plc-shell
+    kind: ssh
+    port: 0
+    depends_on: [mes]
+    files:
+      recipe.txt: "speed=10"
+  - key: worker
+    kind: process
+    exe: sleep
+    cmd: "10"
+"#;
+
+#[actix::test]
+async fn start_status_stop() {
+    let topology = Topology::from_yaml(FACTORY).expect("valid yaml");
+    let order: Vec<_> = topology
+        .start_order()
+        .expect("no cycles")
+        .iter()
+        .map(|a| a.key.as_str())
+        .collect();
+    assert_eq!(order, vec!["mes", "plc-shell", "worker"]);
+
+    let running = topology.start().await.expect("started");
+    for status in running.status().await {
+        assert_eq!(status.state, ActorState::Running, "{}", status.key);
+    }
+    running.stop().await;
+}
+
+#[actix::test]
+async fn failed_start_unlinks_the_sink() {
+    // the sink can not connect, so the start fails after its link is subscribed
+    let topology = Topology::from_yaml(
+        r#"
+name: unlinked
+actors:
+  - key: unlinked-historian
+    kind: sqlite
+    url: "sqlite::memory:"
+    query: SELECT 1
+  - key: unlinked-out
+    kind: azure_sender
+    url: amqp://127.0.0.1:1
+    topic: telemetry
+links:
+  - from: unlinked-historian
+    to: unlinked-out
+"#,
+    )
+    .unwrap();
+    for _ in 0..2 {
+        assert!(topology.start().await.is_err());
+        let payload = Traced::new(TraceContext::new_root(), Payload("{}".to_string()));
+        assert_eq!(
+            EventBus::global().try_publish("unlinked-historian", payload),
+            0
+        );
+    }
+}
+
+#[test]
+fn validation_errors() {
+    let unknown = Topology::from_yaml(
+        r#"
+name: broken
+actors:
+  - key: a
+    kind: process
+    exe: sleep
+    depends_on: [b]
+"#,
+    )
+    .unwrap();
+    assert!(
+        unknown
+            .validate()
+            .unwrap_err()
+            .0
+            .contains("unknown actor b")
+    );
+
+    let cycle = Topology::from_toml(
+        r#"
+name = "cycle"
+
+[[actors]]
+key = "a"
+kind = "process"
+exe = "sleep"
+depends_on = ["b"]
+
+[[actors]]
+key = "b"
+kind = "process"
+exe = "sleep"
+depends_on = ["a"]
+"#,
+    )
+    .unwrap();
+    assert!(
+        cycle
+            .validate()
+            .unwrap_err()
+            .0
+            .contains("cycle between a, b")
+    );
+
+    let proxy = Topology::from_yaml(
+        r#"
+name: proxy
+actors:
+  - key: link
+    kind: proxy
+    port: 2223
+    upstream: localhost
+"#,
+    )
+    .unwrap();
+    assert!(proxy.validate().unwrap_err().0.contains("is not host:port"));
+
+    let client = Topology::from_yaml(
+        r#"
+name: client
+actors:
+  - key: callbacks
+    kind: http_client
+    base_url: 127.0.0.1:5000
+"#,
+    )
+    .unwrap();
+    assert!(client.validate().unwrap_err().0.contains("is not http(s)"));
+
+    let stub = Topology::from_yaml(
+        r#"
+name: stub
+actors:
+  - key: mes
+    kind: http
+    port: 8080
+    stubs:
+      - id: orders
+        request: { path: "^/orders/(" }
+"#,
+    )
+    .unwrap();
+    assert!(stub.validate().unwrap_err().0.contains("stub orders"));
+
+    let example = Topology::load("examples/factory.yaml").expect("example loads");
+    example.validate().expect("example is valid");
+}
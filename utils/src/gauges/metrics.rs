@@ -0,0 +1,496 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+/// The prefix of the exported metric names.
+const PREFIX: &str = "parallax";
+/// The quantiles exported for the histograms.
+const QUANTILES: [f64; 5] = [0.5, 0.9, 0.95, 0.99, 0.999];
+/// The number of buckets per power of two, the recorded values keep ~1.5% precision.
+const SUB_BUCKETS: u64 = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct MetricKey {
+    pub actor: String,
+    pub label: String,
+}
+
+impl MetricKey {
+    pub fn new(actor: impl Into<String>, label: impl Into<String>) -> Self {
+        MetricKey {
+            actor: actor.into(),
+            label: label.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value going up and down, e.g. the queue length or the utilization of a machine.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, delta: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + delta).to_bits())
+            });
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Default)]
+struct HistogramInner {
+    buckets: BTreeMap<u64, u64>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+/// An HDR-style histogram: the values are counted in log-linear buckets,
+/// so the memory does not grow with the number of recorded values.
+/// Durations are recorded in microseconds.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram(Arc<Mutex<HistogramInner>>);
+
+fn bucket_of(value: u64) -> u64 {
+    if value < 2 * SUB_BUCKETS {
+        value
+    } else {
+        let shift = 64 - value.leading_zeros() as u64 - 7;
+        shift * SUB_BUCKETS + (value >> shift)
+    }
+}
+
+/// The highest value counted in the bucket.
+fn bucket_high(bucket: u64) -> u64 {
+    if bucket < 2 * SUB_BUCKETS {
+        bucket
+    } else {
+        let shift = bucket / SUB_BUCKETS - 1;
+        let top = bucket % SUB_BUCKETS + SUB_BUCKETS;
+        ((top + 1) << shift) - 1
+    }
+}
+
+impl Histogram {
+    fn lock(&self) -> MutexGuard<'_, HistogramInner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record(&self, value: u64) {
+        let mut inner = self.lock();
+        *inner.buckets.entry(bucket_of(value)).or_default() += 1;
+        inner.min = if inner.count == 0 {
+            value
+        } else {
+            inner.min.min(value)
+        };
+        inner.max = inner.max.max(value);
+        inner.count += 1;
+        inner.sum = inner.sum.saturating_add(value);
+    }
+
+    pub fn record_duration(&self, d: Duration) {
+        self.record(d.as_micros().min(u64::MAX as u128) as u64);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.lock().count
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.lock().sum
+    }
+
+    pub fn min(&self) -> u64 {
+        self.lock().min
+    }
+
+    pub fn max(&self) -> u64 {
+        self.lock().max
+    }
+
+    pub fn mean(&self) -> f64 {
+        let inner = self.lock();
+        if inner.count == 0 {
+            0.0
+        } else {
+            inner.sum as f64 / inner.count as f64
+        }
+    }
+
+    /// The value below which the given percentage (0..=100) of the recorded values fall.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let inner = self.lock();
+        if inner.count == 0 {
+            return 0;
+        }
+        let rank = ((p.clamp(0.0, 100.0) / 100.0 * inner.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, n) in inner.buckets.iter() {
+            seen += n;
+            if seen >= rank {
+                return bucket_high(*bucket).clamp(inner.min, inner.max);
+            }
+        }
+        inner.max
+    }
+
+    pub fn summary(&self) -> HistogramSummary {
+        HistogramSummary {
+            count: self.count(),
+            min: self.min(),
+            max: self.max(),
+            mean: self.mean(),
+            p50: self.percentile(50.0),
+            p90: self.percentile(90.0),
+            p95: self.percentile(95.0),
+            p99: self.percentile(99.0),
+            p999: self.percentile(99.9),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramSummary {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub p999: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CounterReport {
+    #[serde(flatten)]
+    pub key: MetricKey,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GaugeReport {
+    #[serde(flatten)]
+    pub key: MetricKey,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramReport {
+    #[serde(flatten)]
+    pub key: MetricKey,
+    #[serde(flatten)]
+    pub summary: HistogramSummary,
+}
+
+/// The end-of-run report of all the metrics.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsReport {
+    pub counters: Vec<CounterReport>,
+    pub gauges: Vec<GaugeReport>,
+    pub histograms: Vec<HistogramReport>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    counters: BTreeMap<MetricKey, Counter>,
+    gauges: BTreeMap<MetricKey, Gauge>,
+    histograms: BTreeMap<MetricKey, Histogram>,
+}
+
+/// The registry of the KPIs of the simulation keyed by the actor and the label.
+/// The returned counters, gauges and histograms are handles, they can be kept and updated
+/// without going through the registry again.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<MetricsInner>>,
+}
+
+impl Metrics {
+    /// The registry used by the actors.
+    pub fn global() -> Metrics {
+        static GLOBAL: OnceLock<Metrics> = OnceLock::new();
+        GLOBAL.get_or_init(Metrics::default).clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MetricsInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn counter(&self, actor: &str, label: &str) -> Counter {
+        self.lock()
+            .counters
+            .entry(MetricKey::new(actor, label))
+            .or_default()
+            .clone()
+    }
+
+    pub fn gauge(&self, actor: &str, label: &str) -> Gauge {
+        self.lock()
+            .gauges
+            .entry(MetricKey::new(actor, label))
+            .or_default()
+            .clone()
+    }
+
+    pub fn histogram(&self, actor: &str, label: &str) -> Histogram {
+        self.lock()
+            .histograms
+            .entry(MetricKey::new(actor, label))
+            .or_default()
+            .clone()
+    }
+
+    pub fn clear(&self) {
+        *self.lock() = MetricsInner::default();
+    }
+
+    pub fn report(&self) -> MetricsReport {
+        let inner = self.lock();
+        MetricsReport {
+            counters: inner
+                .counters
+                .iter()
+                .map(|(key, c)| CounterReport {
+                    key: key.clone(),
+                    value: c.get(),
+                })
+                .collect(),
+            gauges: inner
+                .gauges
+                .iter()
+                .map(|(key, g)| GaugeReport {
+                    key: key.clone(),
+                    value: g.get(),
+                })
+                .collect(),
+            histograms: inner
+                .histograms
+                .iter()
+                .map(|(key, h)| HistogramReport {
+                    key: key.clone(),
+                    summary: h.summary(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.report()).unwrap_or_default()
+    }
+
+    pub fn write_report(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+
+    /// The metrics in the Prometheus text exposition format,
+    /// the histograms are exported as summaries with quantiles.
+    /// The samples of every actor are grouped in the family of their label,
+    /// a label used by metrics of different kinds gets the kind appended to its family name.
+    pub fn to_prometheus(&self) -> String {
+        let inner = self.lock();
+        let mut families: BTreeMap<String, Family> = BTreeMap::new();
+        for (key, c) in inner.counters.iter() {
+            let family = Family::of(&mut families, &key.label, "counter");
+            let _ = writeln!(
+                family.samples,
+                "{}{{actor=\"{}\"}} {}",
+                family.name,
+                escape(&key.actor),
+                c.get()
+            );
+        }
+        for (key, g) in inner.gauges.iter() {
+            let family = Family::of(&mut families, &key.label, "gauge");
+            let _ = writeln!(
+                family.samples,
+                "{}{{actor=\"{}\"}} {}",
+                family.name,
+                escape(&key.actor),
+                g.get()
+            );
+        }
+        for (key, h) in inner.histograms.iter() {
+            let family = Family::of(&mut families, &key.label, "summary");
+            let actor = escape(&key.actor);
+            for q in QUANTILES {
+                let _ = writeln!(
+                    family.samples,
+                    "{}{{actor=\"{}\",quantile=\"{}\"}} {}",
+                    family.name,
+                    actor,
+                    q,
+                    h.percentile(q * 100.0)
+                );
+            }
+            let _ = writeln!(
+                family.samples,
+                "{}_sum{{actor=\"{}\"}} {}",
+                family.name,
+                actor,
+                h.sum()
+            );
+            let _ = writeln!(
+                family.samples,
+                "{}_count{{actor=\"{}\"}} {}",
+                family.name,
+                actor,
+                h.count()
+            );
+        }
+
+        let mut out = String::new();
+        for family in families.values() {
+            let help = family.help.replace('\\', "\\\\").replace('\n', "\\n");
+            let _ = writeln!(out, "# HELP {} {}", family.name, help);
+            let _ = writeln!(out, "# TYPE {} {}", family.name, family.kind);
+            out.push_str(&family.samples);
+        }
+        out
+    }
+}
+
+/// The samples of a Prometheus metric family, written after its `# HELP` and `# TYPE` lines.
+struct Family {
+    name: String,
+    help: String,
+    kind: &'static str,
+    samples: String,
+}
+
+impl Family {
+    fn of<'a>(
+        families: &'a mut BTreeMap<String, Family>,
+        label: &str,
+        kind: &'static str,
+    ) -> &'a mut Family {
+        let mut name = format!("{}_{}", PREFIX, sanitize(label));
+        if families.get(&name).is_some_and(|f| f.kind != kind) {
+            name = format!("{}_{}", name, kind);
+        }
+        families.entry(name.clone()).or_insert_with(|| Family {
+            name,
+            help: label.to_string(),
+            kind,
+            samples: String::new(),
+        })
+    }
+}
+
+fn sanitize(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_percentiles() {
+        let h = Histogram::default();
+        for v in 1..=10_000 {
+            h.record(v);
+        }
+        assert_eq!(h.count(), 10_000);
+        assert_eq!(h.min(), 1);
+        assert_eq!(h.max(), 10_000);
+        for (p, expected) in [(50.0, 5_000.0), (99.0, 9_900.0), (100.0, 10_000.0)] {
+            let actual = h.percentile(p) as f64;
+            assert!(
+                (actual - expected).abs() / expected < 0.02,
+                "p{} = {}",
+                p,
+                actual
+            );
+        }
+        assert_eq!(Histogram::default().percentile(50.0), 0);
+    }
+
+    #[test]
+    fn export() {
+        let metrics = Metrics::default();
+        metrics.counter("mes", "orders").add(3);
+        metrics.counter("erp", "orders").inc();
+        metrics.gauge("mill", "utilization").set(0.75);
+        let latency = metrics.histogram("mes", "order latency");
+        latency.record_duration(Duration::from_millis(20));
+        metrics.histogram("mes", "order latency").record(40_000);
+
+        let text = metrics.to_prometheus();
+        assert_eq!(text.matches("# TYPE parallax_orders counter").count(), 1);
+        assert!(text.contains(
+            "# HELP parallax_orders orders\n# TYPE parallax_orders counter\n\
+             parallax_orders{actor=\"erp\"} 1\nparallax_orders{actor=\"mes\"} 3\n"
+        ));
+        assert!(text.contains("parallax_orders{actor=\"mes\"} 3"));
+        assert!(text.contains("parallax_utilization{actor=\"mill\"} 0.75"));
+        assert!(text.contains("parallax_order_latency_count{actor=\"mes\"} 2"));
+        assert!(text.contains("parallax_order_latency_sum{actor=\"mes\"} 60000"));
+
+        // the families of a label are not mixed up, even between actors
+        metrics.counter("erp", "utilization").inc();
+        let text = metrics.to_prometheus();
+        assert!(text.contains(
+            "# TYPE parallax_utilization counter\nparallax_utilization{actor=\"erp\"} 1\n"
+        ));
+        assert!(text.contains(
+            "# TYPE parallax_utilization_gauge gauge\n\
+             parallax_utilization_gauge{actor=\"mill\"} 0.75\n"
+        ));
+        let names: Vec<_> = text
+            .lines()
+            .filter_map(|l| l.strip_prefix("# TYPE "))
+            .collect();
+        let mut unique = names.clone();
+        unique.dedup();
+        assert_eq!(names, unique);
+
+        let report: serde_json::Value = serde_json::from_str(&metrics.to_json()).unwrap();
+        assert_eq!(report["counters"][0]["actor"], "erp");
+        assert_eq!(report["histograms"][0]["count"], 2);
+        assert_eq!(report["histograms"][0]["min"], 20_000);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::gauges::metrics::{Metrics, MetricsReport};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    pub count: u64,
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+/// The KPIs of one actor. The counters with `error` in the label are summed up as the errors.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ActorKpis {
+    pub errors: u64,
+    pub counters: BTreeMap<String, u64>,
+    /// The counters per second of the run.
+    pub throughput: BTreeMap<String, f64>,
+    /// The histograms, the values are in microseconds when they are recorded as durations.
+    pub latency: BTreeMap<String, Latency>,
+}
+
+/// The result of a benchmark run, it is compared to the report of a previous run (the baseline).
+/// The KPIs are the counters and histograms the actors record in `Metrics::global()`,
+/// e.g. `http_requests`, `ssh_commands`, `opcua_writes`, `amqp_sent`, `sql_polls` or `tcp_connections`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub name: String,
+    pub duration_secs: f64,
+    pub actors: BTreeMap<String, ActorKpis>,
+    /// The number of messages per protocol.
+    pub protocols: BTreeMap<String, u64>,
+}
+
+/// The allowed changes against the baseline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Thresholds {
+    /// The allowed growth of the latency percentiles in percent.
+    pub latency_pct: f64,
+    /// The allowed drop of the throughput in percent.
+    pub throughput_pct: f64,
+    /// The allowed number of new errors per actor.
+    pub errors: u64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            latency_pct: 10.0,
+            throughput_pct: 10.0,
+            errors: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Regression {
+    pub actor: String,
+    pub metric: String,
+    pub baseline: f64,
+    pub current: f64,
+    pub change_pct: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    pub baseline: String,
+    pub regressions: Vec<Regression>,
+}
+
+impl Comparison {
+    pub fn is_regression(&self) -> bool {
+        !self.regressions.is_empty()
+    }
+}
+
+fn change_pct(baseline: f64, current: f64) -> f64 {
+    if baseline == 0.0 {
+        if current == 0.0 { 0.0 } else { 100.0 }
+    } else {
+        (current - baseline) / baseline * 100.0
+    }
+}
+
+fn to_io(e: serde_json::Error) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+impl BenchmarkReport {
+    pub fn from_metrics(name: impl Into<String>, metrics: &Metrics, elapsed: Duration) -> Self {
+        BenchmarkReport::from_metrics_report(name, &metrics.report(), elapsed)
+    }
+
+    pub fn from_metrics_report(
+        name: impl Into<String>,
+        report: &MetricsReport,
+        elapsed: Duration,
+    ) -> Self {
+        let secs = elapsed.as_secs_f64();
+        let mut actors: BTreeMap<String, ActorKpis> = BTreeMap::new();
+        for c in report.counters.iter() {
+            let kpis = actors.entry(c.key.actor.clone()).or_default();
+            if c.key.label.contains("error") {
+                kpis.errors += c.value;
+            }
+            kpis.counters.insert(c.key.label.clone(), c.value);
+            let per_sec = if secs > 0.0 {
+                c.value as f64 / secs
+            } else {
+                0.0
+            };
+            kpis.throughput.insert(c.key.label.clone(), per_sec);
+        }
+        for h in report.histograms.iter() {
+            actors
+                .entry(h.key.actor.clone())
+                .or_default()
+                .latency
+                .insert(
+                    h.key.label.clone(),
+                    Latency {
+                        count: h.summary.count,
+                        p50: h.summary.p50,
+                        p95: h.summary.p95,
+                        p99: h.summary.p99,
+                        max: h.summary.max,
+                    },
+                );
+        }
+        BenchmarkReport {
+            name: name.into(),
+            duration_secs: secs,
+            actors,
+            protocols: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_protocols(mut self, protocols: BTreeMap<String, u64>) -> Self {
+        self.protocols = protocols;
+        self
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        serde_json::from_str(&std::fs::read_to_string(path)?).map_err(to_io)
+    }
+
+    /// Flags the KPIs which are worse than the baseline beyond the thresholds.
+    /// The KPIs missing in the baseline are not compared.
+    pub fn compare(&self, baseline: &BenchmarkReport, thresholds: &Thresholds) -> Comparison {
+        let mut regressions = vec![];
+        let mut flag = |actor: &str, metric: String, base: f64, current: f64| {
+            regressions.push(Regression {
+                actor: actor.to_string(),
+                metric,
+                baseline: base,
+                current,
+                change_pct: change_pct(base, current),
+            })
+        };
+        for (actor, current) in self.actors.iter() {
+            let Some(base) = baseline.actors.get(actor) else {
+                continue;
+            };
+            if current.errors > base.errors + thresholds.errors {
+                flag(
+                    actor,
+                    "errors".to_string(),
+                    base.errors as f64,
+                    current.errors as f64,
+                );
+            }
+            for (label, tp) in current.throughput.iter() {
+                if label.contains("error") {
+                    continue;
+                }
+                if let Some(base_tp) = base.throughput.get(label)
+                    && *tp < base_tp * (1.0 - thresholds.throughput_pct / 100.0)
+                {
+                    flag(actor, format!("{}/s", label), *base_tp, *tp);
+                }
+            }
+            for (label, lat) in current.latency.iter() {
+                let Some(base_lat) = base.latency.get(label) else {
+                    continue;
+                };
+                for (p, b, c) in [
+                    ("p50", base_lat.p50, lat.p50),
+                    ("p95", base_lat.p95, lat.p95),
+                    ("p99", base_lat.p99, lat.p99),
+                ] {
+                    if c as f64 > b as f64 * (1.0 + thresholds.latency_pct / 100.0) {
+                        flag(actor, format!("{} {}", label, p), b as f64, c as f64);
+                    }
+                }
+            }
+        }
+        Comparison {
+            baseline: baseline.name.clone(),
+            regressions,
+        }
+    }
+
+    pub fn to_json(&self) -> std::io::Result<String> {
+        serde_json::to_string_pretty(self).map_err(to_io)
+    }
+
+    pub fn to_markdown(&self, comparison: Option<&Comparison>) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Benchmark {}\n", self.name);
+        let _ = writeln!(out, "Duration: {:.3} s\n", self.duration_secs);
+        let _ = writeln!(out, "## Actors\n");
+        let _ = writeln!(out, "| Actor | Metric | Value | Per second |");
+        let _ = writeln!(out, "|---|---|---|---|");
+        for (actor, kpis) in self.actors.iter() {
+            let _ = writeln!(out, "| {} | errors | {} | |", actor, kpis.errors);
+            for (label, value) in kpis.counters.iter() {
+                let tp = kpis.throughput.get(label).copied().unwrap_or_default();
+                let _ = writeln!(out, "| {} | {} | {} | {:.2} |", actor, label, value, tp);
+            }
+        }
+        let _ = writeln!(out, "\n## Latency\n");
+        let _ = writeln!(out, "| Actor | Metric | Count | p50 | p95 | p99 | Max |");
+        let _ = writeln!(out, "|---|---|---|---|---|---|---|");
+        for (actor, kpis) in self.actors.iter() {
+            for (label, l) in kpis.latency.iter() {
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} | {} | {} |",
+                    actor, label, l.count, l.p50, l.p95, l.p99, l.max
+                );
+            }
+        }
+        let _ = writeln!(out, "\n## Messages\n");
+        let _ = writeln!(out, "| Protocol | Messages |");
+        let _ = writeln!(out, "|---|---|");
+        for (protocol, n) in self.protocols.iter() {
+            let _ = writeln!(out, "| {} | {} |", protocol, n);
+        }
+        if let Some(comparison) = comparison {
+            let _ = writeln!(out, "\n## Baseline {}\n", comparison.baseline);
+            if comparison.is_regression() {
+                let _ = writeln!(out, "| Actor | Metric | Baseline | Current | Change |");
+                let _ = writeln!(out, "|---|---|---|---|---|");
+                for r in comparison.regressions.iter() {
+                    let _ = writeln!(
+                        out,
+                        "| {} | {} | {:.2} | {:.2} | {:+.1}% |",
+                        r.actor, r.metric, r.baseline, r.current, r.change_pct
+                    );
+                }
+            } else {
+                let _ = writeln!(out, "No regressions.");
+            }
+        }
+        out
+    }
+
+    pub fn to_html(&self, comparison: Option<&Comparison>) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Benchmark {}</title></head><body>",
+            html(&self.name)
+        );
+        let mut table = None;
+        for line in self.to_markdown(comparison).lines() {
+            if let Some(title) = line.strip_prefix("## ") {
+                let _ = writeln!(out, "<h2>{}</h2>", html(title));
+            } else if let Some(title) = line.strip_prefix("# ") {
+                let _ = writeln!(out, "<h1>{}</h1>", html(title));
+            } else if line.starts_with("|---") {
+                continue;
+            } else if let Some(row) = line.strip_prefix('|') {
+                let cell = if table.is_none() { "th" } else { "td" };
+                if table.is_none() {
+                    let _ = writeln!(out, "<table border=\"1\">");
+                    table = Some(());
+                }
+                let cells: String = row
+                    .trim_end_matches('|')
+                    .split('|')
+                    .map(|c| format!("<{0}>{1}</{0}>", cell, html(c.trim())))
+                    .collect();
+                let _ = writeln!(out, "<tr>{}</tr>", cells);
+            } else {
+                if table.take().is_some() {
+                    let _ = writeln!(out, "</table>");
+                }
+                if !line.is_empty() {
+                    let _ = writeln!(out, "<p>{}</p>", html(line));
+                }
+            }
+        }
+        if table.is_some() {
+            let _ = writeln!(out, "</table>");
+        }
+        let _ = writeln!(out, "</body></html>");
+        out
+    }
+
+    /// Writes `<name>.json`, `<name>.md` and `<name>.html` to the directory and returns their paths.
+    pub fn write(
+        &self,
+        dir: impl AsRef<Path>,
+        comparison: Option<&Comparison>,
+    ) -> std::io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let files = [
+            (format!("{}.json", self.name), self.to_json()?),
+            (format!("{}.md", self.name), self.to_markdown(comparison)),
+            (format!("{}.html", self.name), self.to_html(comparison)),
+        ];
+        let mut paths = vec![];
+        for (file, content) in files {
+            let path = dir.join(file);
+            std::fs::write(&path, content)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+fn html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(orders: u64, errors: u64, latency_ms: u64) -> BenchmarkReport {
+        let metrics = Metrics::default();
+        metrics.counter("mes", "orders").add(orders);
+        metrics.counter("mes", "http_errors").add(errors);
+        let h = metrics.histogram("mes", "order_latency_us");
+        for _ in 0..100 {
+            h.record_duration(Duration::from_millis(latency_ms));
+        }
+        BenchmarkReport::from_metrics("run", &metrics, Duration::from_secs(10))
+            .with_protocols(BTreeMap::from([("Http".to_string(), orders)]))
+    }
+
+    #[test]
+    fn compare_to_baseline() {
+        let baseline = run(1000, 0, 20);
+        let kpis = &baseline.actors["mes"];
+        assert_eq!(kpis.throughput["orders"], 100.0);
+        assert_eq!(kpis.latency["order_latency_us"].p99, 20_000);
+
+        let thresholds = Thresholds::default();
+        assert!(
+            !run(950, 0, 21)
+                .compare(&baseline, &thresholds)
+                .is_regression()
+        );
+
+        let current = run(800, 2, 30);
+        let comparison = current.compare(&baseline, &thresholds);
+        let metrics: Vec<_> = comparison
+            .regressions
+            .iter()
+            .map(|r| r.metric.as_str())
+            .collect();
+        assert_eq!(
+            metrics,
+            vec![
+                "errors",
+                "orders/s",
+                "order_latency_us p50",
+                "order_latency_us p95",
+                "order_latency_us p99"
+            ]
+        );
+        assert!(
+            current
+                .to_markdown(Some(&comparison))
+                .contains("| mes | orders/s | 100.00 | 80.00 | -20.0% |")
+        );
+
+        let dir = std::env::temp_dir().join(format!("parallax-report-{}", std::process::id()));
+        let paths = current.write(&dir, Some(&comparison)).unwrap();
+        assert_eq!(BenchmarkReport::load(&paths[0]).unwrap(), current);
+        assert!(
+            std::fs::read_to_string(&paths[2])
+                .unwrap()
+                .contains("<td>orders/s</td>")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,94 +1,301 @@
-use chrono::DateTime;
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
-type Marker = String;
+pub type Marker = String;
+/// The source of the timestamps, e.g. the simulation clock instead of the wall clock.
+pub type TimeSource = Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>;
 
-#[derive(Debug, Clone)]
-struct Reading {
-    marker: Marker,
-    timestamp: DateTime<chrono::Utc>,
-    parent: Marker,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub marker: Marker,
+    pub timestamp: DateTime<Utc>,
+    pub parent: Marker,
 }
 
 impl Reading {
-    pub fn new(marker: Marker, timestamp: DateTime<chrono::Utc>, parent: Marker) -> Self {
+    pub fn new(marker: Marker, timestamp: DateTime<Utc>, parent: Marker) -> Self {
         Self {
             marker,
             timestamp,
             parent,
         }
     }
+
+    /// The time passed from the earlier reading to this one.
+    pub fn since(&self, earlier: &Reading) -> Duration {
+        to_duration(self.timestamp - earlier.timestamp)
+    }
 }
 
-#[derive(Default, Debug, Clone)]
-struct Stopwatch {
+/// A named span measured between `start` and `stop`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    /// Tells apart the spans with the same name.
+    pub id: usize,
+    pub name: Marker,
+    pub parent: Option<Marker>,
+    pub parent_id: Option<usize>,
+    pub started: DateTime<Utc>,
+    pub stopped: Option<DateTime<Utc>>,
+}
+
+impl Measurement {
+    /// The duration of a stopped span.
+    pub fn duration(&self) -> Option<Duration> {
+        self.stopped.map(|s| to_duration(s - self.started))
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.stopped.is_none()
+    }
+}
+
+fn to_duration(delta: chrono::TimeDelta) -> Duration {
+    delta.to_std().unwrap_or_default()
+}
+
+#[derive(Default, Debug)]
+struct StopwatchInner {
     readings: Vec<Reading>,
-    parents: Vec<Marker>,
+    /// By id, the stopped ones until they are drained.
+    spans: BTreeMap<usize, Measurement>,
+    next_id: usize,
+    /// The duration of the span of every name stopped last, kept when the spans are drained.
+    last: HashMap<Marker, Duration>,
+}
+
+/// Collects readings and named spans. The stopwatch is a shared handle,
+/// the clones can be moved to other actors and tasks and measure into the same readings.
+/// The nesting is carried by the [`SpanHandle`]s, so concurrent tasks never share a parent.
+/// The spans are kept until they are drained or cleared, a long simulation measuring
+/// every order has to [`drain`](Stopwatch::drain) them regularly.
+#[derive(Clone)]
+pub struct Stopwatch {
+    inner: Arc<Mutex<StopwatchInner>>,
+    now: TimeSource,
+}
+
+impl Default for Stopwatch {
+    fn default() -> Self {
+        Stopwatch::new()
+    }
+}
+
+impl std::fmt::Debug for Stopwatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stopwatch")
+            .field("inner", &self.inner)
+            .finish()
+    }
 }
 
 impl Stopwatch {
     pub fn new() -> Self {
         Self {
-            readings: Vec::new(),
-            parents: Vec::new(),
+            inner: Arc::new(Mutex::new(StopwatchInner::default())),
+            now: Arc::new(Utc::now),
         }
     }
 
-    pub fn read<M: Into<String>>(&mut self, marker: M) -> DateTime<chrono::Utc> {
-        let marker = marker.into();
-        let parent = self.parents.last().unwrap_or(&marker);
-        self.read_to(marker.clone(), parent.clone())
+    pub fn with_time_source(
+        mut self,
+        now: impl Fn() -> DateTime<Utc> + Send + Sync + 'static,
+    ) -> Self {
+        self.now = Arc::new(now);
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, StopwatchInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
     }
 
-    pub fn read_start<Marker: Into<String>>(&mut self, marker: Marker) -> DateTime<chrono::Utc> {
+    /// A reading of its own, the marker is its parent.
+    pub fn read<M: Into<String>>(&self, marker: M) -> DateTime<Utc> {
         let marker = marker.into();
         self.read_to(marker.clone(), marker)
     }
 
-    pub fn read_to<Marker: Into<String>>(
-        &mut self,
-        marker: Marker,
-        parent: Marker,
-    ) -> DateTime<chrono::Utc> {
-        let time = chrono::Utc::now();
-
-        self.readings
+    pub fn read_to<M: Into<String>>(&self, marker: M, parent: M) -> DateTime<Utc> {
+        let time = (self.now)();
+        self.lock()
+            .readings
             .push(Reading::new(marker.into(), time, parent.into()));
         time
     }
 
-    pub fn new_parent<M: Into<String>>(&mut self, marker: M) -> Option<Marker> {
-        let last = self.parents.last().cloned();
-        self.parents.push(marker.into());
-        last
+    /// The readings grouped by the parent.
+    pub fn readings(&self) -> HashMap<Marker, Vec<Reading>> {
+        let mut parents: HashMap<Marker, Vec<Reading>> = HashMap::new();
+        for r in self.lock().readings.iter() {
+            parents.entry(r.parent.clone()).or_default().push(r.clone());
+        }
+        parents
+    }
+
+    /// Starts a top level span, the spans nested in it are started from the handle.
+    pub fn start<M: Into<String>>(&self, name: M) -> SpanHandle {
+        self.start_span(name.into(), None)
     }
 
-    pub fn get_parent(&self, idx: usize) -> Option<&Marker> {
-        self.parents.get(idx)
+    /// Starts a span parenting the readings and spans made through the returned handle.
+    pub fn new_parent<M: Into<String>>(&self, marker: M) -> SpanHandle {
+        self.start(marker)
     }
-    pub fn readings(&self) -> HashMap<Marker, Vec<Reading>> {
-        let mut parents = HashMap::new();
 
-        for r in self.readings.iter() {
-            parents
-                .entry(r.parent.clone())
-                .and_modify(|e: &mut Vec<_>| e.push(r.clone()))
-                .or_insert(vec![r.clone()]);
+    fn start_span(&self, name: Marker, parent: Option<(usize, Marker)>) -> SpanHandle {
+        let started = (self.now)();
+        let mut inner = self.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let (parent_id, parent) = parent.unzip();
+        inner.spans.insert(
+            id,
+            Measurement {
+                id,
+                name: name.clone(),
+                parent,
+                parent_id,
+                started,
+                stopped: None,
+            },
+        );
+        SpanHandle {
+            watch: self.clone(),
+            id,
+            name,
+            started,
         }
+    }
 
-        parents
+    fn stop_span(&self, id: usize) -> Option<Duration> {
+        let stopped = (self.now)();
+        let mut inner = self.lock();
+        let span = inner.spans.get_mut(&id).filter(|s| s.is_running())?;
+        span.stopped = Some(stopped);
+        let (name, duration) = (span.name.clone(), span.duration()?);
+        inner.last.insert(name, duration);
+        Some(duration)
+    }
+
+    /// Measures the future as a top level span.
+    pub async fn measure<M: Into<String>, F: Future>(&self, name: M, f: F) -> F::Output {
+        self.start(name).measure(f).await
+    }
+
+    /// The duration of the span with the name stopped last, drained or not.
+    pub fn duration<M: AsRef<str>>(&self, name: M) -> Option<Duration> {
+        self.lock().last.get(name.as_ref()).copied()
+    }
+
+    pub fn spans(&self) -> Vec<Measurement> {
+        self.lock().spans.values().cloned().collect()
+    }
+
+    /// Removes and returns the stopped spans, the running ones are kept.
+    pub fn drain(&self) -> Vec<Measurement> {
+        let mut inner = self.lock();
+        let (stopped, running) = std::mem::take(&mut inner.spans)
+            .into_iter()
+            .partition(|(_, s)| !s.is_running());
+        inner.spans = running;
+        stopped.into_values().collect()
+    }
+
+    /// The spans nested in the spans with the name.
+    pub fn children<M: AsRef<str>>(&self, parent: M) -> Vec<Measurement> {
+        self.lock()
+            .spans
+            .values()
+            .filter(|s| s.parent.as_deref() == Some(parent.as_ref()))
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets everything, the ids are not reused so the handles of the cleared spans stop nothing.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        *inner = StopwatchInner {
+            next_id: inner.next_id,
+            ..StopwatchInner::default()
+        };
+    }
+}
+
+/// A started span. It is moved along with the work it measures,
+/// e.g. to another task, and nests the spans and readings made through it.
+#[derive(Debug, Clone)]
+pub struct SpanHandle {
+    watch: Stopwatch,
+    id: usize,
+    name: Marker,
+    started: DateTime<Utc>,
+}
+
+impl SpanHandle {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn started(&self) -> DateTime<Utc> {
+        self.started
+    }
+
+    /// Starts a span nested in this one.
+    pub fn start<M: Into<String>>(&self, name: M) -> SpanHandle {
+        self.watch
+            .start_span(name.into(), Some((self.id, self.name.clone())))
+    }
+
+    /// A reading grouped under this span.
+    pub fn read<M: Into<String>>(&self, marker: M) -> DateTime<Utc> {
+        self.watch.read_to(marker.into(), self.name.clone())
+    }
+
+    /// Stops the span, returns its duration or `None` when it is already stopped.
+    pub fn stop(&self) -> Option<Duration> {
+        self.watch.stop_span(self.id)
+    }
+
+    /// Measures the future as this span.
+    pub async fn measure<F: Future>(&self, f: F) -> F::Output {
+        let res = f.await;
+        self.stop();
+        res
+    }
+
+    /// The spans nested in this one.
+    pub fn children(&self) -> Vec<Measurement> {
+        self.watch
+            .lock()
+            .spans
+            .values()
+            .filter(|s| s.parent_id == Some(self.id))
+            .cloned()
+            .collect()
     }
 }
 
 impl Display for Stopwatch {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let _ = writeln!(f, "Stopwatch");
-        let readings = self.readings();
-        for (marker, readings) in readings.iter() {
-            let _ = writeln!(f, " - Marker: {},", marker);
+        writeln!(f, "Stopwatch")?;
+        for (marker, readings) in self.readings().iter() {
+            writeln!(f, " - Marker: {},", marker)?;
             for reading in readings {
-                let _ = writeln!(f, "  - {}: {},", reading.marker, reading.timestamp);
+                writeln!(f, "  - {}: {},", reading.marker, reading.timestamp)?;
+            }
+        }
+        for span in self.spans() {
+            match span.duration() {
+                Some(d) => writeln!(f, " - Span: {}: {:?},", span.name, d)?,
+                None => writeln!(f, " - Span: {}: running,", span.name)?,
             }
         }
         Ok(())
@@ -98,22 +305,124 @@ impl Display for Stopwatch {
 #[cfg(test)]
 mod tests {
     use crate::gauges::stopwatch::Stopwatch;
+    use chrono::{TimeDelta, Utc};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     #[test]
     fn stopwatch() {
-        let mut s = Stopwatch::new();
+        let s = Stopwatch::new();
 
         s.read("a");
-        s.new_parent("a");
-        s.read("b");
-        s.read("c");
+        let a = s.new_parent("a");
+        a.read("b");
+        a.read("c");
+
+        s.read("d");
+
+        let b = a.start("b");
+        b.read("e");
+
+        let readings = s.readings();
+        assert_eq!(readings["a"].len(), 3);
+        assert_eq!(readings["b"].len(), 1);
+        assert_eq!(readings["d"].len(), 1);
+        assert!(b.stop().is_some());
+        assert_eq!(a.children()[0].name, "b");
+        assert!(s.to_string().contains(" - Span: b: "));
+    }
+
+    #[test]
+    fn spans_across_threads() {
+        let now = Arc::new(Mutex::new(Utc::now()));
+        let time = now.clone();
+        let s = Stopwatch::new().with_time_source(move || *time.lock().unwrap());
+        let tick = |ms| *now.lock().unwrap() += TimeDelta::milliseconds(ms);
+
+        let order = s.start("order-1");
+        let mill = order.start("mill");
+        tick(30);
+
+        let shared = order.clone();
+        let pack = std::thread::spawn(move || {
+            mill.stop();
+            shared.start("pack")
+        })
+        .join()
+        .unwrap();
+        tick(20);
+
+        assert_eq!(pack.stop(), Some(Duration::from_millis(20)));
+        assert_eq!(order.stop(), Some(Duration::from_millis(50)));
+        assert_eq!(order.stop(), None);
+        assert_eq!(s.duration("mill"), Some(Duration::from_millis(30)));
+        let steps: Vec<_> = s.children("order-1").into_iter().map(|m| m.name).collect();
+        assert_eq!(steps, vec!["mill", "pack"]);
+    }
 
-        s.read_start("d");
+    #[test]
+    fn drain_the_stopped_spans() {
+        let s = Stopwatch::new();
+        let first = s.start("order");
+        let second = s.start("order");
+        first.stop();
+
+        let drained = s.drain();
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].id, first.id());
+        assert!(s.duration("order").is_some());
+        // the running span is kept and still stops
+        assert_eq!(s.spans().len(), 1);
+        assert!(second.stop().is_some());
+        assert_eq!(s.drain().len(), 1);
+        assert!(s.spans().is_empty());
 
-        s.new_parent("b");
+        // a handle of a cleared span does not stop a new one
+        let old = s.start("order");
+        s.clear();
+        let new = s.start("order");
+        assert_eq!(old.stop(), None);
+        assert!(new.stop().is_some());
+    }
 
-        s.read("e");
+    #[tokio::test]
+    async fn interleaved_tasks() {
+        let now = Arc::new(Mutex::new(Utc::now()));
+        let time = now.clone();
+        let s = Stopwatch::new().with_time_source(move || *time.lock().unwrap());
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let (tick_tx, tick_rx) = tokio::sync::oneshot::channel::<()>();
 
-        print!("{}", s);
+        // two orders with the same span names, the second one starts while the first runs
+        let first = s.clone();
+        let first = tokio::spawn(async move {
+            let order = first.start("order");
+            let mill = order.start("mill");
+            tx.send(()).unwrap();
+            tick_rx.await.unwrap();
+            mill.stop();
+            order
+        });
+        rx.await.unwrap();
+        let order = s.start("order");
+        let mill = order.start("mill");
+        *now.lock().unwrap() += TimeDelta::milliseconds(10);
+        tick_tx.send(()).unwrap();
+        let first = first.await.unwrap();
+        *now.lock().unwrap() += TimeDelta::milliseconds(5);
+
+        assert_eq!(mill.stop(), Some(Duration::from_millis(15)));
+        assert_eq!(first.stop(), Some(Duration::from_millis(15)));
+        assert_eq!(order.stop(), Some(Duration::from_millis(15)));
+        for order in [&first, &order] {
+            let children = order.children();
+            assert_eq!(children.len(), 1);
+            assert_eq!(children[0].parent_id, Some(order.id()));
+        }
+        assert_eq!(
+            first.children()[0].duration(),
+            Some(Duration::from_millis(10))
+        );
+        assert_ne!(first.children()[0].id, order.children()[0].id);
     }
 }
@@ -1,26 +1,8 @@
 pub mod gauges;
+pub mod logging;
 
+/// Installs the logger configured by `RUST_LOG` and `PARALLAX_LOG_FORMAT`,
+/// the repeated calls are ignored.
 pub fn logger_on() {
-    use chrono::Local;
-    use env_logger::{Builder, Env};
-    use std::io::Write;
-
-    Builder::from_env(Env::default().default_filter_or("info"))
-        .format(|buf, record| {
-            let timestamp = Local::now().format("%H:%M:%S%.3f"); // Include milliseconds
-
-            writeln!(
-                buf,
-                "[{} {} {}] {}",
-                timestamp,
-                record.level().to_string().chars().next().unwrap_or('I'),
-                record
-                    .target()
-                    .split("::")
-                    .last()
-                    .unwrap_or(record.target()),
-                record.args()
-            )
-        })
-        .init();
+    logging::init(logging::LogConfig::default());
 }
@@ -0,0 +1,321 @@
+use chrono::{DateTime, Local};
+use log::{Level, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
+
+/// The environment variable selecting the format of the default config, `json` or `text`.
+pub const FORMAT_ENV: &str = "PARALLAX_LOG_FORMAT";
+
+/// The records a capture keeps by default, the oldest ones are dropped first.
+pub const CAPTURE_LIMIT: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line with `ts`, `level`, `target` and `msg`.
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    filters: String,
+    format: LogFormat,
+    routes: Vec<(String, PathBuf)>,
+}
+
+impl Default for LogConfig {
+    /// The filters are taken from `RUST_LOG` (`info` when it is not set)
+    /// and the format from `PARALLAX_LOG_FORMAT`.
+    fn default() -> Self {
+        let format = match std::env::var(FORMAT_ENV) {
+            Ok(f) if f.eq_ignore_ascii_case("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        };
+        LogConfig {
+            filters: std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
+            format,
+            routes: vec![],
+        }
+    }
+}
+
+impl LogConfig {
+    /// The filters in the `RUST_LOG` syntax, e.g. `info,actix_web=warn`.
+    pub fn filters(mut self, filters: impl Into<String>) -> Self {
+        self.filters = filters.into();
+        self
+    }
+
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn json(self) -> Self {
+        self.format(LogFormat::Json)
+    }
+
+    /// Additionally writes the records of the target to the file. The actors log with
+    /// their key as the target (`log::info!(target: &name, ..)`), so this gives a log file per actor.
+    pub fn route(mut self, target: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.routes.push((target.into(), path.into()));
+        self
+    }
+}
+
+/// A log record as it is kept by the captures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    pub timestamp: DateTime<Local>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogLine {
+    fn new(record: &Record) -> Self {
+        LogLine {
+            timestamp: Local::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "[{} {} {}] {}",
+            self.timestamp.format("%H:%M:%S%.3f"),
+            self.level.as_str().chars().next().unwrap_or('I'),
+            self.target.split("::").last().unwrap_or(&self.target),
+            self.message
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "ts": self.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            "level": self.level.as_str(),
+            "target": self.target,
+            "msg": self.message,
+        })
+        .to_string()
+    }
+
+    fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Text => self.to_text(),
+            LogFormat::Json => self.to_json(),
+        }
+    }
+}
+
+/// The last records seen by a capture.
+#[derive(Debug)]
+struct Captured {
+    lines: VecDeque<LogLine>,
+    limit: usize,
+}
+
+impl Captured {
+    fn push(&mut self, line: LogLine) {
+        while self.lines.len() >= self.limit.max(1) {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+}
+
+type Lines = Arc<Mutex<Captured>>;
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+static CAPTURES: Mutex<Vec<Weak<Mutex<Captured>>>> = Mutex::new(Vec::new());
+
+struct ParallaxLogger {
+    filter: env_logger::Logger,
+    format: LogFormat,
+    routes: Vec<(String, Mutex<File>)>,
+}
+
+impl Log for ParallaxLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let line = LogLine::new(record);
+        let formatted = line.format(self.format);
+        eprintln!("{}", formatted);
+        for (target, file) in self.routes.iter() {
+            if target == record.target() {
+                let _ = writeln!(lock(file), "{}", formatted);
+            }
+        }
+        lock(&CAPTURES).retain(|capture| match capture.upgrade() {
+            Some(lines) => {
+                lock(&lines).push(line.clone());
+                true
+            }
+            None => false,
+        });
+    }
+
+    fn flush(&self) {
+        for (_, file) in self.routes.iter() {
+            let _ = lock(file).flush();
+        }
+    }
+}
+
+/// Installs the logger, returns false when a logger is already installed.
+/// It is safe to call it from every test of a test binary, the first call wins.
+pub fn init(config: LogConfig) -> bool {
+    let routes = config
+        .routes
+        .iter()
+        .filter_map(|(target, path)| {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some((target.clone(), Mutex::new(file))),
+                Err(e) => {
+                    eprintln!("Can not open the log file {:?} of {}: {}", path, target, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    let logger = ParallaxLogger {
+        filter: env_logger::Builder::new()
+            .parse_filters(&config.filters)
+            .build(),
+        format: config.format,
+        routes,
+    };
+    let max_level = logger.filter.filter();
+    match log::set_boxed_logger(Box::new(logger)) {
+        Ok(()) => {
+            log::set_max_level(max_level);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Collects the log records while it is alive, e.g. to assert that a process printed `READY`.
+/// The captures see the records of all the threads, filter them by the target (the actor key),
+/// and only the records enabled by the filters of the installed logger.
+/// A capture keeps the last [`CAPTURE_LIMIT`] records, see [`LogCapture::with_limit`].
+#[derive(Debug, Clone)]
+pub struct LogCapture {
+    lines: Lines,
+}
+
+impl LogCapture {
+    /// Starts capturing, installs the default logger when there is none.
+    pub fn start() -> Self {
+        init(LogConfig::default());
+        let lines: Lines = Arc::new(Mutex::new(Captured {
+            lines: VecDeque::new(),
+            limit: CAPTURE_LIMIT,
+        }));
+        lock(&CAPTURES).push(Arc::downgrade(&lines));
+        LogCapture { lines }
+    }
+
+    /// Keeps the last `limit` records instead of the last [`CAPTURE_LIMIT`].
+    pub fn with_limit(self, limit: usize) -> Self {
+        lock(&self.lines).limit = limit;
+        self
+    }
+
+    pub fn lines(&self) -> Vec<LogLine> {
+        lock(&self.lines).lines.iter().cloned().collect()
+    }
+
+    pub fn lines_of(&self, target: &str) -> Vec<LogLine> {
+        lock(&self.lines)
+            .lines
+            .iter()
+            .filter(|l| l.target == target)
+            .cloned()
+            .collect()
+    }
+
+    pub fn contains(&self, target: &str, text: &str) -> bool {
+        lock(&self.lines)
+            .lines
+            .iter()
+            .any(|l| l.target == target && l.message.contains(text))
+    }
+
+    /// Waits until the target logs the text, returns false on timeout.
+    pub async fn wait_for(&self, target: &str, text: &str, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if self.contains(target, text) {
+                return true;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    pub fn clear(&self) {
+        lock(&self.lines).lines.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_and_format() {
+        // pinned, the capture sees what the filters of the logger let through whatever `RUST_LOG` is
+        init(LogConfig::default().filters("info"));
+        assert!(!init(LogConfig::default().json()));
+
+        let capture = LogCapture::start();
+        log::info!(target: "plc-1", "READY");
+        log::debug!(target: "plc-1", "hidden");
+        log::info!(target: "plc-2", "not ready");
+        assert!(capture.contains("plc-1", "READY"));
+        assert!(!capture.contains("plc-2", "READY"));
+        assert_eq!(capture.lines_of("plc-1").len(), 1);
+
+        let line = capture.lines_of("plc-1").remove(0);
+        assert!(line.to_text().ends_with("I plc-1] READY"));
+        let json: serde_json::Value = serde_json::from_str(&line.to_json()).unwrap();
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["target"], "plc-1");
+        assert_eq!(json["msg"], "READY");
+
+        drop(capture);
+        log::info!(target: "plc-1", "after");
+        assert!(lock(&CAPTURES).iter().all(|c| c.upgrade().is_some()));
+
+        // the oldest records go first
+        let capture = LogCapture::start().with_limit(2);
+        for idx in 0..5 {
+            log::info!(target: "plc-3", "tick {}", idx);
+        }
+        let messages: Vec<_> = capture
+            .lines_of("plc-3")
+            .into_iter()
+            .map(|l| l.message)
+            .collect();
+        assert_eq!(messages, vec!["tick 3", "tick 4"]);
+    }
+}